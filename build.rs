@@ -0,0 +1,45 @@
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Embed build metadata to report it in `--version` and the health check and version endpoints.
+// `ORGU_GIT_SHA` takes precedence for builds without `.git`, e.g. Docker builds. `SOURCE_DATE_EPOCH` is respected
+// for reproducible builds.
+fn main() {
+    println!("cargo:rerun-if-env-changed=ORGU_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    let sha = env::var("ORGU_GIT_SHA")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "HEAD"]))
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=ORGU_GIT_SHA={sha}");
+
+    let timestamp = env::var("SOURCE_DATE_EPOCH").ok().unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default()
+    });
+    println!("cargo:rustc-env=ORGU_BUILD_TIMESTAMP={timestamp}");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=ORGU_RUSTC_VERSION={rustc_version}");
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_owned())
+}
@@ -7,12 +7,28 @@ use serde_json::json;
 use thiserror::Error;
 use tracing::error;
 
+use crate::event_queue_client::SendError;
+
 #[derive(Debug, Error)]
 pub enum AppError {
     #[error("internal_server_error: {0}")]
     InternalServerError(#[from] anyhow::Error),
     #[error("authorization_error")]
     AuthorizationError,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("service_unavailable: {0}")]
+    ServiceUnavailable(anyhow::Error),
+}
+
+// Transient failures are reported as 503 so that the caller can retry.
+impl From<SendError> for AppError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::Transient(_) => Self::ServiceUnavailable(e.into()),
+            SendError::Permanent(_) => Self::InternalServerError(e.into()),
+        }
+    }
 }
 
 impl IntoResponse for AppError {
@@ -34,6 +50,19 @@ impl IntoResponse for AppError {
                 "unauthorized",
                 "authorization failed".to_owned(),
             ),
+            Self::ServiceUnavailable(inner) => {
+                error!(error = ?inner, "handler failed to process request temporarily");
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "service_unavailable",
+                    "service temporarily unavailable, retry later".to_owned(),
+                )
+            }
+            Self::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "source IP is not allowed".to_owned(),
+            ),
         };
 
         let body = Json(json!({
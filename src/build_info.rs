@@ -0,0 +1,37 @@
+//! Build metadata embedded by the build script.
+
+use chrono::DateTime;
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Git commit SHA of the build, `unknown` if built without git.
+pub const GIT_SHA: &str = env!("ORGU_GIT_SHA");
+/// Shown in `--version`.
+pub const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("ORGU_GIT_SHA"), ")");
+const BUILD_TIMESTAMP: &str = env!("ORGU_BUILD_TIMESTAMP");
+const RUSTC_VERSION: &str = env!("ORGU_RUSTC_VERSION");
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// RFC 3339 in UTC, none if unknown.
+    pub build_timestamp: Option<String>,
+    pub rustc_version: &'static str,
+}
+
+impl BuildInfo {
+    pub fn get() -> Self {
+        let build_timestamp = BUILD_TIMESTAMP
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|t| t.to_rfc3339());
+        Self {
+            version: VERSION,
+            git_sha: GIT_SHA,
+            build_timestamp,
+            rustc_version: RUSTC_VERSION,
+        }
+    }
+}
@@ -1,45 +1,194 @@
 use std::{
+    cell::Cell,
+    fmt,
+    fs::{canonicalize, create_dir_all, write},
+    future::pending,
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    thread::sleep,
+    time::Duration,
 };
 
 use anyhow::{bail, Context as _, Result};
-use clap::Args;
-use git2::{ErrorClass, ErrorCode, FetchOptions, Oid, Progress, Repository};
+use chrono::{DateTime, FixedOffset};
+use clap::{value_parser, Args, ValueEnum};
+use git2::{
+    Cred, ErrorClass, ErrorCode, FetchOptions, Oid, Progress, Remote, RemoteCallbacks, Repository,
+    SubmoduleUpdateOptions, Version,
+};
+use serde::Serialize;
 use tempfile::tempdir;
 use thiserror::Error;
-use tokio::{task::spawn_blocking, time::timeout};
+use tokio::{
+    select,
+    task::spawn_blocking,
+    time::{interval, timeout, Instant},
+};
 use tracing::{debug, info, info_span, instrument, trace, warn, Span};
+use url::Url;
 
-#[derive(Debug, Args, Clone)]
+use crate::config_serde::{display, display_opt};
+
+#[derive(Debug, Args, Clone, Serialize)]
 pub struct CheckoutConfig {
     /// Depth of the clone. Default is 1. Set 0 to clone the whole repository.
     #[arg(long, env, default_value = "1")]
     fetch_depth: i32,
+    /// Cap `--fetch-depth` to this depth, treating 0 (the whole repository) as unbounded, to prevent accidental full
+    /// clones of huge repositories, e.g. by a stray env var. Clamped depths are logged. Disabled if none.
+    #[arg(long, env, value_parser = value_parser!(i32).range(1..))]
+    max_allowed_fetch_depth: Option<i32>,
     /// Don't fetch the repository and also don't checkout any commits. This is useful for partial fetching.
     #[arg(long, env, default_value = "false", conflicts_with = "fetch_depth")]
     no_fetch: bool,
     /// Timeout seconds for fetching the repository. Default is 10 mins.
     #[arg(long, env, default_value = "10mins")]
+    #[serde(serialize_with = "display")]
     fetch_timeout: humantime::Duration,
+    /// Bound the fetch by date instead of depth. Takes a duration like `30days` or a date like `2024-01-01`.
+    /// libgit2 can only bound fetches by depth as of v1.9, so this falls back to `--fetch-depth` with a warning until
+    /// the linked libgit2 supports it.
+    #[arg(long, env, value_parser = parse_shallow_since)]
+    #[serde(serialize_with = "display_opt")]
+    shallow_since: Option<ShallowSince>,
+    /// Number of parallel threads to fetch the repository. Falls back to a single thread with a note if the linked
+    /// libgit2 doesn't support parallel fetching, which is the case as of v1.9.
+    #[arg(long, env, default_value = "1", value_parser = value_parser!(u32).range(1..))]
+    fetch_parallelism: u32,
+    /// Update submodules after the checkout. The submodule fetches are also bounded by `--fetch-timeout`.
+    #[arg(long, env, default_value = "none")]
+    submodules: Submodules,
+    /// Number of retries of fetching on transient network errors, HTTP 5xx and timeouts. The retries are also bounded
+    /// by `--fetch-timeout`.
+    #[arg(long, env, default_value = "2")]
+    fetch_max_retries: u32,
+    /// Cancel the fetch when it receives more than this many bytes, not to fill the disk with a runaway clone, e.g.
+    /// `--fetch-depth 0` on a huge repository. Applies to each attempt of the fetch. Disabled if none.
+    #[arg(long, env)]
+    max_fetch_bytes: Option<u64>,
+    /// Cancel the fetch when it makes no progress for this duration, e.g. on a stuck connection, instead of waiting
+    /// for `--fetch-timeout`. Reported as a checkout timeout. Disabled if none.
+    #[arg(long, env)]
+    #[serde(serialize_with = "display_opt")]
+    fetch_stall_timeout: Option<humantime::Duration>,
+    /// Local repository to borrow objects from via alternates, like `git clone --reference`, e.g. a pre-seeded
+    /// mirror. The fetch is skipped if the reference already has the commit. Keep the reference while checkouts live.
+    #[arg(long, env, value_parser = parse_reference_repo)]
+    reference_repo: Option<PathBuf>,
+    /// Fetch only the head branch of the pull request at the depth, which is faster than negotiating for the bare
+    /// commit SHA. Falls back to fetching the SHA if fetching the branch fails or it doesn't contain the commit, e.g.
+    /// the branch of a fork or force-pushed after the event.
+    #[arg(long, env, default_value = "false")]
+    single_branch: bool,
+}
+
+impl CheckoutConfig {
+    // Refspec of the head branch to fetch with `--single-branch`. None to fetch the commit SHA.
+    fn branch_refspec(&self, input: &CheckoutInput) -> Option<String> {
+        let head_ref = input.head_ref.as_ref().filter(|_| self.single_branch)?;
+        Some(format!(
+            "+refs/heads/{head_ref}:refs/remotes/{REMOTE_NAME}/{head_ref}"
+        ))
+    }
+
+    // Fetch depth capped by `--max-allowed-fetch-depth`. 0 means unbounded.
+    fn effective_fetch_depth(&self) -> i32 {
+        match self.max_allowed_fetch_depth {
+            Some(max) if self.fetch_depth == 0 || self.fetch_depth > max => max,
+            _ => self.fetch_depth,
+        }
+    }
+
+    /// `--fetch-parallelism` if the given libgit2 version can fetch in parallel, otherwise a single thread.
+    fn effective_fetch_parallelism(&self, libgit2_version: (u32, u32, u32)) -> u32 {
+        if supports_fetch_parallelism(libgit2_version) {
+            self.fetch_parallelism
+        } else {
+            1
+        }
+    }
+}
+
+// libgit2 has no option to parallelize fetching as of v1.9, pack builder threads apply only to push.
+const fn supports_fetch_parallelism(_libgit2_version: (u32, u32, u32)) -> bool {
+    false
+}
+
+#[derive(Debug, Clone)]
+pub enum ShallowSince {
+    Duration(humantime::Duration),
+    Date(humantime::Timestamp),
+}
+
+impl fmt::Display for ShallowSince {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duration(d) => write!(f, "{d}"),
+            Self::Date(t) => write!(f, "{t}"),
+        }
+    }
+}
+
+fn parse_shallow_since(s: &str) -> Result<ShallowSince> {
+    if let Ok(d) = s.parse::<humantime::Duration>() {
+        return Ok(ShallowSince::Duration(d));
+    }
+    // Accept date only format too.
+    let date = if s.len() == 10 {
+        format!("{s}T00:00:00Z")
+    } else {
+        s.to_owned()
+    };
+    let t = date
+        .parse::<humantime::Timestamp>()
+        .with_context(|| format!("invalid shallow-since, expected duration or date: {s}"))?;
+    Ok(ShallowSince::Date(t))
+}
+
+fn parse_reference_repo(s: &str) -> Result<PathBuf> {
+    Repository::open(s).with_context(|| format!("invalid reference repository: {s}"))?;
+    Ok(PathBuf::from(s))
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Submodules {
+    /// Don't update submodules.
+    None,
+    /// Update submodules of the repository.
+    Checkout,
+    /// Update submodules and nested submodules.
+    Recursive,
+}
+
+// Futures are not required to be Send. See `future_not_send` in Cargo.toml.
+#[allow(async_fn_in_trait)]
 #[allow(clippy::indexing_slicing)]
 #[cfg_attr(test, mockall::automock)]
 pub trait Checkout: Sync + Send {
     /// Create new temporary directory and checkout given repository under the directory.
     async fn create_dir_and_checkout(&self, input: &CheckoutInput) -> Result<WorkDir>;
-    /// Checkout given repository under given repository.
-    async fn checkout_under(&self, input: &CheckoutInput, under: &Path) -> Result<()>;
+    /// Checkout given repository under given repository. Returns the checked out commit, none if not checked out.
+    async fn checkout_under(
+        &self,
+        input: &CheckoutInput,
+        under: &Path,
+    ) -> Result<Option<CommitInfo>>;
 }
 
 #[derive(Error, Debug)]
 pub enum CheckoutError {
     #[error("timeout fetching repository took too long: {0}")]
     Timeout(humantime::Duration),
+    #[error("could not access repository, check installation permissions: {0}")]
+    Authentication(#[source] git2::Error),
+    #[error("could not access repository without token, it may be private or not exist: {0}")]
+    Anonymous(#[source] git2::Error),
+    #[error("fetching repository received more than the limit: {0} bytes")]
+    TooLarge(u64),
 }
 
 #[derive(Debug, Clone)]
@@ -47,7 +196,10 @@ pub struct CheckoutInput {
     pub owner: String,
     pub repo: String,
     pub sha: String,
-    pub token: String,
+    /// Branch containing the commit if known, to fetch only the branch with `--single-branch`.
+    pub head_ref: Option<String>,
+    /// Clone anonymously if none, only for public repositories.
+    pub token: Option<String>,
 }
 
 impl CheckoutInput {
@@ -56,13 +208,68 @@ impl CheckoutInput {
     }
 }
 
+/// Metadata of the checked out commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// Full commit message, may be multi-line.
+    pub message: String,
+    /// Author as `name <email>`.
+    pub author: String,
+    /// Commit time in RFC 3339 with the committer's offset.
+    pub timestamp: String,
+}
+
+impl CommitInfo {
+    // Invalid UTF-8 sequences are replaced, and NUL bytes are removed as they can't be passed in env vars.
+    fn new(commit: &git2::Commit<'_>) -> Self {
+        let lossy = |v: &[u8]| String::from_utf8_lossy(v).replace('\0', "");
+        let author = commit.author();
+        let time = commit.time();
+        let timestamp = FixedOffset::east_opt(time.offset_minutes().saturating_mul(60))
+            .and_then(|offset| {
+                DateTime::from_timestamp(time.seconds(), 0).map(|t| t.with_timezone(&offset))
+            })
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+        Self {
+            message: lossy(commit.message_bytes()),
+            author: format!(
+                "{} <{}>",
+                lossy(author.name_bytes()),
+                lossy(author.email_bytes())
+            ),
+            timestamp,
+        }
+    }
+}
+
 /// Checkout result. Holds the path to newly created temporary workding directory.
 pub struct WorkDir {
     pub path: PathBuf,
+    /// Checked out commit, none if not checked out.
+    pub commit: Option<CommitInfo>,
     // To keep the temporary directory alive.
     pub _parent: tempfile::TempDir,
 }
 
+impl WorkDir {
+    /// Remove the temporary directory explicitly to surface the error. Dropping also removes it silently.
+    pub async fn close(self) -> Result<()> {
+        let parent = self._parent;
+        let path = parent.path().to_path_buf();
+        spawn_blocking(move || parent.close())
+            .await?
+            .with_context(|| format!("failed to remove directory: {}", path.display()))
+    }
+
+    /// Disarm the cleanup to keep the directory, e.g. for debugging. Returns the path to the working directory.
+    pub fn into_keep(self) -> PathBuf {
+        let parent = self._parent.into_path();
+        debug!(parent = %parent.display(), "disarmed cleanup of working directory");
+        self.path
+    }
+}
+
 #[derive(Debug)]
 pub struct Libgit2Checkout {
     config: CheckoutConfig,
@@ -72,6 +279,40 @@ impl Libgit2Checkout {
     pub const fn new(config: CheckoutConfig) -> Self {
         Self { config }
     }
+
+    /// Describe what `checkout_under` would fetch without fetching, one `key: value` per line.
+    pub fn plan(&self, input: &CheckoutInput, under: &Path) -> String {
+        let c = &self.config;
+        let bound = match c.effective_fetch_depth() {
+            _ if c.no_fetch => "none, no fetch".to_owned(),
+            0 => "full".to_owned(),
+            depth => format!("depth {depth}"),
+        };
+        let bound = match &c.shallow_since {
+            Some(since) if !c.no_fetch => {
+                format!("{bound}, shallow-since {since} is unsupported by libgit2")
+            }
+            _ => bound,
+        };
+        let auth = if input.token.is_some() {
+            "token"
+        } else {
+            "anonymous"
+        };
+        [
+            format!("remote: {}", remote_url(&input.full_name())),
+            format!(
+                "refspec: {}",
+                c.branch_refspec(input).unwrap_or_else(|| input.sha.clone())
+            ),
+            format!("fetch: {bound}"),
+            format!("auth: {auth}"),
+            format!("submodules: {:?}", c.submodules).to_lowercase(),
+            format!("timeout: {}", c.fetch_timeout),
+            format!("target: {}", under.display()),
+        ]
+        .join("\n")
+    }
 }
 
 const REMOTE_NAME: &str = "origin";
@@ -80,9 +321,10 @@ impl Checkout for Libgit2Checkout {
     async fn create_dir_and_checkout(&self, input: &CheckoutInput) -> Result<WorkDir> {
         let temp = tempdir()?;
         let work_dir = temp.path().join(&input.repo);
-        self.checkout_under(input, &work_dir).await?;
+        let commit = self.checkout_under(input, &work_dir).await?;
         Ok(WorkDir {
             path: work_dir,
+            commit,
             _parent: temp,
         })
     }
@@ -96,29 +338,51 @@ impl Checkout for Libgit2Checkout {
             under = %under.display(),
         )
     )]
-    async fn checkout_under(&self, input: &CheckoutInput, under: &Path) -> Result<()> {
+    async fn checkout_under(
+        &self,
+        input: &CheckoutInput,
+        under: &Path,
+    ) -> Result<Option<CommitInfo>> {
+        let start = Instant::now();
         let repo =
             fetch_with_timeout(under.to_path_buf(), input.clone(), self.config.clone()).await?;
 
         if self.config.no_fetch {
             info!("no_fetch is enabled, skipping checkout");
-            return Ok(());
+            return Ok(None);
         }
 
-        debug!("checking out commit: {}", input.sha);
-        // checkout the specific commit.
-        let oid = Oid::from_str(&input.sha).with_context(|| {
-            format!(
-                "failed to create Git Object ID, invalid commit SHA?: sha={}",
-                input.sha
+        // Repository is not Sync, so drop it before awaiting.
+        let info = {
+            debug!("checking out commit: {}", input.sha);
+            // checkout the specific commit.
+            let oid = Oid::from_str(&input.sha).with_context(|| {
+                format!(
+                    "failed to create Git Object ID, invalid commit SHA?: sha={}",
+                    input.sha
+                )
+            })?;
+            let commit = repo.find_commit(oid)?;
+            repo.checkout_tree(commit.as_object(), None)
+                .with_context(|| {
+                    format!("failed to checkout {}:{}", input.full_name(), input.sha)
+                })?;
+            repo.set_head_detached(commit.id())?;
+            CommitInfo::new(&commit)
+        };
+
+        if self.config.submodules != Submodules::None {
+            let remaining = self.config.fetch_timeout.saturating_sub(start.elapsed());
+            update_submodules_with_timeout(
+                under.to_path_buf(),
+                input.token.clone(),
+                self.config.clone(),
+                remaining,
             )
-        })?;
-        let commit = repo.find_commit(oid)?;
-        repo.checkout_tree(commit.as_object(), None)
-            .with_context(|| format!("failed to checkout {}:{}", input.full_name(), input.sha))?;
-        repo.set_head_detached(commit.id())?;
+            .await?;
+        }
 
-        Ok(())
+        Ok(Some(info))
     }
 }
 
@@ -129,20 +393,138 @@ async fn fetch_with_timeout(
     config: CheckoutConfig,
 ) -> Result<Repository> {
     info!("fetching repository with timeout: {}", config.fetch_timeout);
+    let (major, minor, patch) = Version::get().libgit2_version();
+    let parallelism = config.effective_fetch_parallelism((major, minor, patch));
+    if parallelism != config.fetch_parallelism {
+        info!(
+            fetch_parallelism = config.fetch_parallelism,
+            fallback = parallelism,
+            libgit2_version = format!("{major}.{minor}.{patch}"),
+            "parallel fetching is not supported by the linked libgit2, falling back"
+        );
+    }
     let should_cancel = Arc::new(AtomicBool::new(false));
+    let progress = Arc::new(FetchProgress::new());
 
-    let sc = Arc::clone(&should_cancel);
+    let (sc, p) = (Arc::clone(&should_cancel), Arc::clone(&progress));
     let c = config.clone();
     // To pass span which refers parents to another thread, explicitly create a new span and pass it.
     let span = info_span!("fetch");
-    let task = spawn_blocking(move || fetch(span, sc, under, input, c));
+    let task = spawn_blocking(move || fetch(span, sc, p, under, input, c));
 
-    match timeout(config.fetch_timeout.into(), task).await {
+    let fetched = timeout(config.fetch_timeout.into(), task);
+    let timed_out = select! {
+        res = fetched => match res {
+            Ok(res) => {
+                let res = res.with_context(|| "Failed to spwan blocking task")?;
+                match (res, config.fetch_stall_timeout) {
+                    // The fetch cancels itself when it notices the stall first.
+                    (Err(_), Some(d)) if progress.is_stalled(d) => d,
+                    (res, _) => return res,
+                }
+            }
+            Err(_) => config.fetch_timeout,
+        },
+        d = wait_stalled(&progress, config.fetch_stall_timeout) => d,
+    };
+    should_cancel.store(true, Ordering::Relaxed);
+    debug!("fetching repository timed out, try to cancel the fetch: timeout={timed_out}");
+    Err(CheckoutError::Timeout(timed_out).into())
+}
+
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Completes with the stall timeout when the fetch makes no progress for the duration. Never completes if disabled.
+// libgit2 calls the progress callback only when something arrives, so a stuck connection is detected here.
+async fn wait_stalled(
+    progress: &FetchProgress,
+    stall_timeout: Option<humantime::Duration>,
+) -> humantime::Duration {
+    let Some(d) = stall_timeout else {
+        return pending().await;
+    };
+    let mut ticker = interval(Duration::from(d).min(STALL_CHECK_INTERVAL));
+    loop {
+        ticker.tick().await;
+        if progress.is_stalled(d) {
+            return d;
+        }
+    }
+}
+
+/// Tracks when the fetch made progress last, shared between the fetch thread and the stall watchdog.
+#[derive(Debug)]
+struct FetchProgress {
+    start: Instant,
+    amount: AtomicUsize,
+    // Elapsed millis from `start`.
+    last_progress_millis: AtomicU64,
+}
+
+impl FetchProgress {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            amount: AtomicUsize::new(0),
+            last_progress_millis: AtomicU64::new(0),
+        }
+    }
+
+    // Start over for a retry, which receives from zero again.
+    fn reset(&self) {
+        self.amount.store(0, Ordering::Relaxed);
+        self.last_progress_millis
+            .store(self.elapsed_millis(), Ordering::Relaxed);
+    }
+
+    // Indexing counts as progress too, received bytes don't increase while resolving deltas of a large repository.
+    fn record(&self, progress: &Progress) {
+        let amount = progress
+            .received_bytes()
+            .saturating_add(progress.indexed_objects())
+            .saturating_add(progress.indexed_deltas());
+        if self.amount.fetch_max(amount, Ordering::Relaxed) < amount {
+            self.last_progress_millis
+                .store(self.elapsed_millis(), Ordering::Relaxed);
+        }
+    }
+
+    fn is_stalled(&self, stall_timeout: humantime::Duration) -> bool {
+        let last = Duration::from_millis(self.last_progress_millis.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last) >= stall_timeout.into()
+    }
+
+    fn elapsed_millis(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+}
+
+// Requires owned arguments to pass to another thread.
+async fn update_submodules_with_timeout(
+    under: PathBuf,
+    token: Option<String>,
+    config: CheckoutConfig,
+    remaining: Duration,
+) -> Result<()> {
+    info!(submodules = ?config.submodules, "updating submodules");
+    let should_cancel = Arc::new(AtomicBool::new(false));
+
+    let sc = Arc::clone(&should_cancel);
+    let recursive = config.submodules == Submodules::Recursive;
+    let span = info_span!("update submodules");
+    let task = spawn_blocking(move || {
+        let _guard = span.enter();
+        let repo = Repository::open(&under)
+            .with_context(|| format!("failed to open repository: {}", under.display()))?;
+        update_submodules(&repo, token.as_deref(), recursive, &sc)
+    });
+
+    match timeout(remaining, task).await {
         Ok(res) => res.with_context(|| "Failed to spwan blocking task")?,
         Err(_) => {
             should_cancel.store(true, Ordering::Relaxed);
             debug!(
-                "fetching repository timed out, try to cancel the fetch: timeout={}",
+                "updating submodules timed out, try to cancel the fetch: timeout={}",
                 config.fetch_timeout
             );
             Err(CheckoutError::Timeout(config.fetch_timeout).into())
@@ -150,26 +532,199 @@ async fn fetch_with_timeout(
     }
 }
 
+// Submodules on GitHub are fetched with the same token as the main repository, others without the token.
+fn update_submodules(
+    repo: &Repository,
+    token: Option<&str>,
+    recursive: bool,
+    should_cancel: &AtomicBool,
+) -> Result<()> {
+    for mut submodule in repo.submodules()? {
+        let name = submodule.name().unwrap_or_default().to_owned();
+        let url = submodule.url().unwrap_or_default().to_owned();
+        debug!(name, url, "updating submodule");
+
+        let mut callbacks = RemoteCallbacks::new();
+        if let Some(token) = token {
+            set_credentials(&mut callbacks, token);
+        }
+        callbacks.transfer_progress(|progress| {
+            !should_cancel.load(Ordering::Relaxed) && show_remote_progress(progress)
+        });
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options);
+
+        submodule
+            .update(true, Some(&mut options))
+            .with_context(|| {
+                format!("failed to update submodule, unreachable URL?: name={name}, url={url}")
+            })?;
+
+        if recursive {
+            let sub_repo = submodule
+                .open()
+                .with_context(|| format!("failed to open submodule: name={name}"))?;
+            update_submodules(&sub_repo, token, recursive, should_cancel)?;
+        }
+    }
+    Ok(())
+}
+
+// Supply the token via callback instead of embedding it in the URL, so it can't leak through error messages.
+fn set_credentials<'token>(callbacks: &mut RemoteCallbacks<'token>, token: &'token str) {
+    let mut tried = false;
+    callbacks.credentials(move |url, _, _| {
+        // libgit2 asks again when the credentials are rejected, fail instead of looping.
+        if tried {
+            return Err(git2::Error::new(
+                ErrorCode::Auth,
+                ErrorClass::Http,
+                "credentials rejected",
+            ));
+        }
+        tried = true;
+        github_credentials(url, token)
+    });
+}
+
+// The installation token is only for GitHub. Other hosts, e.g. of submodules, are asked for credentials only when
+// they require authentication, so fail as anonymous access was denied.
+fn github_credentials(url: &str, token: &str) -> Result<Cred, git2::Error> {
+    if !is_github_url(url) {
+        debug!(url, "not supplying token to non-GitHub host");
+        return Err(git2::Error::new(
+            ErrorCode::Auth,
+            ErrorClass::Http,
+            "no credentials for non-GitHub host",
+        ));
+    }
+    Cred::userpass_plaintext("x-access-token", token)
+}
+
+const GITHUB_HOST: &str = "github.com";
+
+fn is_github_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|u| u.scheme() == "https" && u.host_str() == Some(GITHUB_HOST))
+}
+
+fn remote_url(full_name: &str) -> String {
+    format!("https://{GITHUB_HOST}/{full_name}")
+}
+
 fn fetch(
     parent: Span,
     should_cancel: Arc<AtomicBool>,
+    progress: Arc<FetchProgress>,
     under: PathBuf,
     input: CheckoutInput,
     config: CheckoutConfig,
 ) -> Result<Repository> {
     let _guard = parent.enter();
+    with_retry(
+        config.fetch_max_retries,
+        FETCH_RETRY_BASE_INTERVAL,
+        &should_cancel,
+        || fetch_once(&should_cancel, &progress, &under, &input, &config),
+    )
+}
+
+const FETCH_RETRY_BASE_INTERVAL: Duration = Duration::from_secs(1);
+const FETCH_RETRY_MAX_INTERVAL: Duration = Duration::from_secs(10);
+
+// Retry with exponential backoff on transient errors. Blocking, so call this in a blocking thread.
+fn with_retry<T>(
+    max_retries: u32,
+    base_interval: Duration,
+    should_cancel: &AtomicBool,
+    mut f: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                let retryable = e.downcast_ref::<git2::Error>().is_some_and(is_transient);
+                if !retryable || attempt >= max_retries || should_cancel.load(Ordering::Relaxed) {
+                    return Err(e);
+                }
+                attempt += 1;
+                let interval = base_interval
+                    .saturating_mul(2_u32.saturating_pow(attempt - 1))
+                    .min(FETCH_RETRY_MAX_INTERVAL);
+                warn!(attempt, error = ?e, "fetch failed with transient error, retrying in {interval:?}");
+                sleep(interval);
+            }
+        }
+    }
+}
+
+// Network errors, HTTP 5xx and timeouts are transient. Missing refs, HTTP 4xx, authentication errors and local OS
+// errors like no space left are permanent, retrying them only delays the failure.
+fn is_transient(e: &git2::Error) -> bool {
+    if e.code() == ErrorCode::Timeout {
+        true
+    } else if e.class() == ErrorClass::Http {
+        http_status(e).is_some_and(|status| (500..600).contains(&status))
+    } else {
+        matches!(e.class(), ErrorClass::Net | ErrorClass::Ssl)
+            && !matches!(e.code(), ErrorCode::Auth | ErrorCode::Certificate)
+            && !is_missing_ref(e)
+    }
+}
+
+// Servers report a missing commit or branch as a remote error over the network class.
+fn is_missing_ref(e: &git2::Error) -> bool {
+    ["not our ref", "couldn't find remote ref"]
+        .iter()
+        .any(|s| e.message().contains(s))
+}
 
-    let repo = Repository::init(&under)
+// libgit2 reports HTTP failures as "unexpected http status code: 502".
+fn http_status(e: &git2::Error) -> Option<u16> {
+    e.message()
+        .rsplit_once(':')
+        .and_then(|(_, status)| status.trim().parse().ok())
+}
+
+// libgit2 reports invalid credentials as auth error, but lacking access as HTTP status error.
+// GitHub asks for credentials for private or missing repositories when fetching anonymously.
+fn into_fetch_error(e: git2::Error, anonymous: bool) -> anyhow::Error {
+    let denied = e.class() == ErrorClass::Http && matches!(http_status(&e), Some(401 | 403 | 404));
+    if anonymous && (e.code() == ErrorCode::Auth || denied) {
+        CheckoutError::Anonymous(e).into()
+    } else if e.code() == ErrorCode::Auth || denied {
+        CheckoutError::Authentication(e).into()
+    } else {
+        e.into()
+    }
+}
+
+// Initialize the repository under the path. Safe to call it again to reinitialize for retries.
+fn fetch_once(
+    should_cancel: &AtomicBool,
+    progress: &FetchProgress,
+    under: &Path,
+    input: &CheckoutInput,
+    config: &CheckoutConfig,
+) -> Result<Repository> {
+    let mut repo = Repository::init(under)
         .with_context(|| format!("failed init repository: {}", under.display()))?;
+    if let Some(reference) = &config.reference_repo {
+        set_alternates(&repo, reference)?;
+        // Reopen to load the alternates.
+        repo = Repository::open(under)
+            .with_context(|| format!("failed open repository: {}", under.display()))?;
+    }
 
-    let url = format!(
-        "https://x-access-token:{}@github.com/{}",
-        input.token,
-        input.full_name(),
-    );
+    let url = remote_url(&input.full_name());
     if let Err(e) = repo.remote(REMOTE_NAME, &url) {
         if e.class() == ErrorClass::Config && e.code() == ErrorCode::Exists {
             debug!("remote already exists: remote_name={REMOTE_NAME}");
+            // Overwrite URLs with credentials written by older versions.
+            repo.remote_set_url(REMOTE_NAME, &url)
+                .with_context(|| format!("failed set remote url: remote_name={REMOTE_NAME}"))?;
         } else {
             bail!("failed add remote: remote_name={REMOTE_NAME}");
         }
@@ -178,13 +733,51 @@ fn fetch(
     if config.no_fetch {
         return Ok(repo);
     }
+    if config.reference_repo.is_some() && has_commit(&repo, &input.sha) {
+        info!("commit found in the reference repository, skipping fetch");
+        return Ok(repo);
+    }
 
     let mut fetch_options = FetchOptions::new();
-    fetch_options.depth(config.fetch_depth);
-    let mut callbacks = git2::RemoteCallbacks::new();
+    let depth = config.effective_fetch_depth();
+    if let Some(since) = &config.shallow_since {
+        let (major, minor, patch) = Version::get().libgit2_version();
+        warn!(
+            shallow_since = %since,
+            fetch_depth = depth,
+            libgit2_version = format!("{major}.{minor}.{patch}"),
+            "shallow-since is not supported by the linked libgit2, falling back to fetch depth"
+        );
+    }
+    if depth != config.fetch_depth {
+        info!(
+            fetch_depth = config.fetch_depth,
+            clamped_to = depth,
+            "fetch depth is clamped by max-allowed-fetch-depth"
+        );
+    }
+    fetch_options.depth(depth);
+    let mut callbacks = RemoteCallbacks::new();
+    if let Some(token) = &input.token {
+        set_credentials(&mut callbacks, token);
+    }
 
-    let cb = |progress: Progress| {
-        if should_cancel.load(Ordering::Relaxed) {
+    let too_large = Cell::new(false);
+    progress.reset();
+    let cb = |p: Progress| {
+        if let Some(max) = config.max_fetch_bytes {
+            if p.received_bytes() > usize::try_from(max).unwrap_or(usize::MAX) {
+                too_large.set(true);
+            }
+        }
+        progress.record(&p);
+        if config
+            .fetch_stall_timeout
+            .is_some_and(|d| progress.is_stalled(d))
+        {
+            should_cancel.store(true, Ordering::Relaxed);
+        }
+        if too_large.get() || should_cancel.load(Ordering::Relaxed) {
             if let Ok(mut r) = repo.find_remote(REMOTE_NAME) {
                 if let Err(e) = r.stop() {
                     warn!("failed to stop remote: {}", e);
@@ -192,25 +785,91 @@ fn fetch(
             }
             false
         } else {
-            show_remote_progress(progress)
+            show_remote_progress(p)
         }
     };
     callbacks.transfer_progress(cb);
     fetch_options.remote_callbacks(callbacks);
 
     let mut remote = repo.find_remote(REMOTE_NAME)?;
-    let refspec = &[input.sha];
-    debug!("fetching refspec: {:?}", refspec);
-    remote
-        .fetch(refspec, Some(&mut fetch_options), None)
-        .with_context(|| format!("failed to fetch repository: depth={}", config.fetch_depth))?;
+    let res = fetch_commit(
+        &repo,
+        &mut remote,
+        config.branch_refspec(input).as_deref(),
+        &input.sha,
+        &mut fetch_options,
+        || too_large.get() || should_cancel.load(Ordering::Relaxed),
+    );
+    if let (true, Some(max)) = (too_large.get(), config.max_fetch_bytes) {
+        debug!(
+            max_fetch_bytes = max,
+            "fetch received too many bytes, cancelled"
+        );
+        return Err(CheckoutError::TooLarge(max).into());
+    }
+    res.map_err(|e| into_fetch_error(e, input.token.is_none()))
+        .with_context(|| {
+            format!(
+                "failed to fetch repository: depth={}",
+                config.effective_fetch_depth()
+            )
+        })?;
 
     // Recreate Repository to avoid sharing between threads.
-    let repo = Repository::init(&under)
+    let repo = Repository::init(under)
         .with_context(|| format!("failed init repository: {}", under.display()))?;
     Ok(repo)
 }
 
+// Fetch the head branch if given, falling back to the commit SHA unless the fetch was stopped by the callback.
+fn fetch_commit(
+    repo: &Repository,
+    remote: &mut Remote<'_>,
+    branch_refspec: Option<&str>,
+    sha: &str,
+    fetch_options: &mut FetchOptions<'_>,
+    stopped: impl Fn() -> bool,
+) -> Result<(), git2::Error> {
+    if let Some(refspec) = branch_refspec {
+        debug!("fetching refspec: {:?}", refspec);
+        match remote.fetch(&[refspec], Some(fetch_options), None) {
+            Ok(()) if has_commit(repo, sha) => return Ok(()),
+            Ok(()) => {
+                info!("head branch doesn't contain the commit, falling back to fetch by SHA");
+            }
+            Err(e) if stopped() => return Err(e),
+            Err(e) => {
+                info!(error = %e, "failed to fetch head branch, falling back to fetch by SHA");
+            }
+        }
+    }
+    debug!("fetching refspec: {:?}", sha);
+    remote.fetch(&[sha], Some(fetch_options), None)
+}
+
+fn has_commit(repo: &Repository, sha: &str) -> bool {
+    Oid::from_str(sha).is_ok_and(|oid| repo.find_commit(oid).is_ok())
+}
+
+// Borrow objects from the reference repository via `objects/info/alternates`. Safe to call again for retries.
+fn set_alternates(repo: &Repository, reference: &Path) -> Result<()> {
+    let reference = Repository::open(reference)
+        .with_context(|| format!("invalid reference repository: {}", reference.display()))?;
+    // Alternates must be absolute, or relative to the objects directory.
+    let objects = canonicalize(reference.path().join("objects")).with_context(|| {
+        format!(
+            "failed to resolve objects of: {}",
+            reference.path().display()
+        )
+    })?;
+    let info = repo.path().join("objects").join("info");
+    create_dir_all(&info).with_context(|| format!("failed to create: {}", info.display()))?;
+    write(info.join("alternates"), format!("{}\n", objects.display()))
+        .with_context(|| "failed to write alternates")?;
+    debug!(objects = %objects.display(), "borrowing objects from reference repository");
+    Ok(())
+}
+
 // libgit2 requires this signature.
 #[allow(clippy::needless_pass_by_value)]
 // https://github.com/libgit2/libgit2/blob/v1.8.0/examples/clone.c
@@ -257,3 +916,420 @@ fn show_receiving_progress(progress: &Progress) {
         "net {network_percent}% ({kbytes} kb, {received_objects}/{total_objects})  /  idx {index_percent}% ({indexed_objects}/{total_objects})",
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use git2::{Error, IndexAddOption, Signature};
+
+    use super::*;
+
+    fn git_error(class: ErrorClass, code: ErrorCode) -> anyhow::Error {
+        anyhow::Error::new(Error::new(code, class, "test")).context("failed to fetch repository")
+    }
+
+    fn commit_all(repo: &Repository, message: &str) {
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("ferris", "ferris@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+        let parents = parent.iter().collect::<Vec<_>>();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+            .unwrap();
+    }
+
+    #[test]
+    fn submodules() {
+        let dir = tempdir().unwrap();
+        // Blocking fs operations are ok in test.
+        let sub_path = dir.path().join("sub");
+        let sub = Repository::init(&sub_path).unwrap();
+        write(sub_path.join("hello.txt"), "hello").unwrap();
+        commit_all(&sub, "init sub");
+
+        let main_path = dir.path().join("main");
+        let main = Repository::init(&main_path).unwrap();
+        let mut sm = main
+            .submodule(sub_path.to_str().unwrap(), Path::new("libs/sub"), true)
+            .unwrap();
+        sm.clone(None).unwrap();
+        sm.add_finalize().unwrap();
+        commit_all(&main, "add submodule");
+
+        let cloned_path = dir.path().join("cloned");
+        let cloned = Repository::clone(main_path.to_str().unwrap(), &cloned_path).unwrap();
+        let file = cloned_path.join("libs/sub/hello.txt");
+        assert!(!file.exists());
+
+        update_submodules(&cloned, Some("token"), false, &AtomicBool::new(false)).unwrap();
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn commit_info() {
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        write(dir.path().join("hello.txt"), "hello").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::new("ferris", "ferris@example.com", &git2::Time::new(0, 540)).unwrap();
+        let oid = repo
+            .commit(None, &sig, &sig, "fix: typo\n\nlonger body\n", &tree, &[])
+            .unwrap();
+
+        let info = CommitInfo::new(&repo.find_commit(oid).unwrap());
+        assert_eq!(
+            info,
+            CommitInfo {
+                message: "fix: typo\n\nlonger body\n".to_owned(),
+                author: "ferris <ferris@example.com>".to_owned(),
+                timestamp: "1970-01-01T09:00:00+09:00".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn reference_repo() {
+        let dir = tempdir().unwrap();
+        let reference_path = dir.path().join("reference");
+        let reference = Repository::init(&reference_path).unwrap();
+        write(reference_path.join("hello.txt"), "hello").unwrap();
+        commit_all(&reference, "init");
+        let oid = reference.head().unwrap().peel_to_commit().unwrap().id();
+        parse_reference_repo(reference_path.to_str().unwrap()).unwrap();
+        parse_reference_repo(dir.path().join("missing").to_str().unwrap()).unwrap_err();
+
+        let path = dir.path().join("repo");
+        let repo = Repository::init(&path).unwrap();
+        assert!(repo.find_commit(oid).is_err());
+        set_alternates(&repo, &reference_path).unwrap();
+        // Calling again for retries overwrites the alternates.
+        set_alternates(&repo, &reference_path).unwrap();
+        let repo = Repository::open(&path).unwrap();
+        repo.find_commit(oid).unwrap();
+    }
+
+    #[test]
+    fn single_branch_fallback() {
+        let dir = tempdir().unwrap();
+        let origin_path = dir.path().join("origin");
+        let origin = Repository::init(&origin_path).unwrap();
+        write(origin_path.join("hello.txt"), "hello").unwrap();
+        commit_all(&origin, "init");
+        let sha = origin
+            .head()
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .id()
+            .to_string();
+        let url = format!("file://{}", origin_path.display());
+        // The local transport doesn't support shallow fetch, so fetch without depth.
+        let fetch = |refspec: &str, stopped: bool| {
+            let path = tempdir().unwrap();
+            let repo = Repository::init(path.path()).unwrap();
+            let mut remote = repo.remote(REMOTE_NAME, &url).unwrap();
+            let res = fetch_commit(
+                &repo,
+                &mut remote,
+                Some(refspec),
+                &sha,
+                &mut FetchOptions::new(),
+                || stopped,
+            );
+            (res, has_commit(&repo, &sha))
+        };
+
+        // libgit2 fetches nothing for a missing branch.
+        let (res, fetched) = fetch("+refs/heads/missing:refs/remotes/origin/missing", false);
+        res.unwrap();
+        assert!(fetched);
+
+        let invalid = "+refs/heads/bad..name:refs/remotes/origin/bad..name";
+        let (res, fetched) = fetch(invalid, false);
+        res.unwrap();
+        assert!(fetched);
+
+        let (res, fetched) = fetch(invalid, true);
+        res.unwrap_err();
+        assert!(!fetched);
+    }
+
+    #[test]
+    fn plan() {
+        let checkout = Libgit2Checkout::new(CheckoutConfig {
+            fetch_depth: 1,
+            max_allowed_fetch_depth: None,
+            no_fetch: false,
+            fetch_timeout: Duration::from_secs(60).into(),
+            shallow_since: None,
+            fetch_parallelism: 1,
+            submodules: Submodules::Recursive,
+            fetch_max_retries: 2,
+            max_fetch_bytes: None,
+            fetch_stall_timeout: None,
+            reference_repo: None,
+            single_branch: false,
+        });
+        let mut input = CheckoutInput {
+            owner: "Finatext".to_owned(),
+            repo: "orgu".to_owned(),
+            sha: "abc".to_owned(),
+            head_ref: None,
+            token: None,
+        };
+        assert_eq!(
+            checkout.plan(&input, Path::new("/tmp/orgu")),
+            "remote: https://github.com/Finatext/orgu\nrefspec: abc\nfetch: depth 1\nauth: anonymous\nsubmodules: recursive\ntimeout: 1m\ntarget: /tmp/orgu"
+        );
+
+        input.head_ref = Some("feature/x".to_owned());
+        assert_eq!(checkout.config.branch_refspec(&input), None);
+        let checkout = Libgit2Checkout::new(CheckoutConfig {
+            single_branch: true,
+            ..checkout.config
+        });
+        assert!(checkout
+            .plan(&input, Path::new("/tmp/orgu"))
+            .contains("\nrefspec: +refs/heads/feature/x:refs/remotes/origin/feature/x\n"));
+
+        let checkout = Libgit2Checkout::new(CheckoutConfig {
+            shallow_since: Some(parse_shallow_since("2024-01-01").unwrap()),
+            ..checkout.config
+        });
+        assert!(checkout.plan(&input, Path::new("/tmp/orgu")).contains(
+            "\nfetch: depth 1, shallow-since 2024-01-01T00:00:00Z is unsupported by libgit2\n"
+        ));
+    }
+
+    #[test]
+    fn max_allowed_fetch_depth() {
+        let config = |fetch_depth, max_allowed_fetch_depth| CheckoutConfig {
+            fetch_depth,
+            max_allowed_fetch_depth,
+            no_fetch: false,
+            fetch_timeout: Duration::from_secs(60).into(),
+            shallow_since: None,
+            fetch_parallelism: 1,
+            submodules: Submodules::None,
+            fetch_max_retries: 2,
+            max_fetch_bytes: None,
+            fetch_stall_timeout: None,
+            reference_repo: None,
+            single_branch: false,
+        };
+        assert_eq!(config(0, None).effective_fetch_depth(), 0);
+        assert_eq!(config(0, Some(50)).effective_fetch_depth(), 50);
+        assert_eq!(config(100, Some(50)).effective_fetch_depth(), 50);
+        assert_eq!(config(10, Some(50)).effective_fetch_depth(), 10);
+    }
+
+    #[test]
+    fn fetch_parallelism_fallback() {
+        let config = CheckoutConfig {
+            fetch_depth: 1,
+            max_allowed_fetch_depth: None,
+            no_fetch: false,
+            fetch_timeout: Duration::from_secs(60).into(),
+            shallow_since: None,
+            fetch_parallelism: 4,
+            submodules: Submodules::None,
+            fetch_max_retries: 2,
+            max_fetch_bytes: None,
+            fetch_stall_timeout: None,
+            reference_repo: None,
+            single_branch: false,
+        };
+        assert_eq!(config.effective_fetch_parallelism((1, 9, 0)), 1);
+    }
+
+    #[tokio::test]
+    async fn stalled_fetch() {
+        let progress = FetchProgress::new();
+        let d = Duration::from_millis(50).into();
+        assert!(!progress.is_stalled(d));
+        assert_eq!(wait_stalled(&progress, Some(d)).await, d);
+        assert!(progress.is_stalled(d));
+        progress.reset();
+        assert!(!progress.is_stalled(d));
+    }
+
+    #[test]
+    fn shallow_since() {
+        assert!(matches!(
+            parse_shallow_since("30days").unwrap(),
+            ShallowSince::Duration(_)
+        ));
+        assert!(matches!(
+            parse_shallow_since("2024-01-01").unwrap(),
+            ShallowSince::Date(_)
+        ));
+        assert!(matches!(
+            parse_shallow_since("2024-01-01T12:00:00Z").unwrap(),
+            ShallowSince::Date(_)
+        ));
+        parse_shallow_since("yesterday").unwrap_err();
+    }
+
+    #[test]
+    fn fetch_error_mapping() {
+        let auth = into_fetch_error(Error::new(ErrorCode::Auth, ErrorClass::Http, "auth"), false);
+        assert!(matches!(
+            auth.downcast_ref::<CheckoutError>(),
+            Some(CheckoutError::Authentication(_))
+        ));
+
+        let forbidden = into_fetch_error(
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Http,
+                "unexpected http status code: 403",
+            ),
+            false,
+        );
+        assert!(matches!(
+            forbidden.downcast_ref::<CheckoutError>(),
+            Some(CheckoutError::Authentication(_))
+        ));
+
+        let net = into_fetch_error(
+            Error::new(ErrorCode::GenericError, ErrorClass::Net, "reset"),
+            true,
+        );
+        assert!(net.downcast_ref::<CheckoutError>().is_none());
+        assert!(net.downcast_ref::<Error>().is_some_and(is_transient));
+
+        let anonymous = into_fetch_error(
+            Error::new(ErrorCode::Auth, ErrorClass::Http, "no callback set"),
+            true,
+        );
+        assert!(matches!(
+            anonymous.downcast_ref::<CheckoutError>(),
+            Some(CheckoutError::Anonymous(_))
+        ));
+    }
+
+    #[test]
+    fn token_not_in_errors() {
+        let token = "ghs_secret";
+        assert!(!remote_url("owner/repo").contains(token));
+
+        let dir = tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        // Nothing listens on port 1, so fetch fails without network access.
+        let mut remote = repo
+            .remote(REMOTE_NAME, "http://127.0.0.1:1/owner/repo")
+            .unwrap();
+        let mut callbacks = RemoteCallbacks::new();
+        set_credentials(&mut callbacks, token);
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        let e = remote
+            .fetch(&["main"], Some(&mut fetch_options), None)
+            .map_err(|e| into_fetch_error(e, false))
+            .unwrap_err();
+        assert!(!format!("{e:?}").contains(token));
+        assert!(!e.to_string().contains(token));
+    }
+
+    #[test]
+    fn credentials_only_for_github() {
+        let token = "ghs_secret";
+        // Cred doesn't implement Debug.
+        assert!(github_credentials("https://github.com/owner/sub", token).is_ok());
+        assert!(
+            github_credentials("https://x-access-token@github.com/owner/sub.git", token).is_ok()
+        );
+        for url in [
+            "https://gitlab.com/owner/sub",
+            "https://github.com.example.com/owner/sub",
+            "https://example.com/github.com/owner/sub",
+            "http://github.com/owner/sub",
+            "git@github.com:owner/sub.git",
+            "",
+        ] {
+            let e = github_credentials(url, token).err().unwrap();
+            assert_eq!(e.code(), ErrorCode::Auth, "{url}");
+        }
+    }
+
+    #[test]
+    fn retry_transient_error() {
+        let mut attempts = 0;
+        let res = with_retry(2, Duration::ZERO, &AtomicBool::new(false), || {
+            attempts += 1;
+            if attempts == 1 {
+                Err(git_error(ErrorClass::Net, ErrorCode::GenericError))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(res.unwrap(), 2);
+
+        for (class, code, message) in [
+            (
+                ErrorClass::Http,
+                ErrorCode::GenericError,
+                "unexpected http status code: 502",
+            ),
+            (ErrorClass::Os, ErrorCode::Timeout, "timed out"),
+        ] {
+            assert!(is_transient(&Error::new(code, class, message)), "{message}");
+        }
+    }
+
+    #[test]
+    fn retry_exhausted() {
+        let mut attempts = 0;
+        let res: Result<()> = with_retry(2, Duration::ZERO, &AtomicBool::new(false), || {
+            attempts += 1;
+            Err(git_error(ErrorClass::Ssl, ErrorCode::GenericError))
+        });
+        res.unwrap_err();
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn no_retry_on_permanent_error() {
+        for e in [
+            git_error(ErrorClass::Http, ErrorCode::Auth),
+            git_error(ErrorClass::Odb, ErrorCode::NotFound),
+            git_error(ErrorClass::Os, ErrorCode::GenericError),
+            Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Http,
+                "unexpected http status code: 422",
+            )
+            .into(),
+            anyhow!("not a git error"),
+        ] {
+            let mut e = Some(e);
+            let mut attempts = 0;
+            let res: Result<()> = with_retry(2, Duration::ZERO, &AtomicBool::new(false), || {
+                attempts += 1;
+                Err(e.take().unwrap())
+            });
+            res.unwrap_err();
+            assert_eq!(attempts, 1);
+        }
+    }
+
+    #[test]
+    fn no_retry_on_bad_sha() {
+        let mut attempts = 0;
+        let res: Result<()> = with_retry(2, Duration::ZERO, &AtomicBool::new(false), || {
+            attempts += 1;
+            Err(Error::new(
+                ErrorCode::GenericError,
+                ErrorClass::Net,
+                "remote error: upload-pack: not our ref 0123456789abcdef0123456789abcdef01234567",
+            )
+            .into())
+        });
+        res.unwrap_err();
+        assert_eq!(attempts, 1);
+    }
+}
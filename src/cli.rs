@@ -1,13 +1,20 @@
 mod checkout;
+mod completions;
+mod config;
+mod man;
 mod pattern;
 
-use std::process::ExitCode;
+use std::{path::PathBuf, process::ExitCode};
 
 use anyhow::Context;
 use clap::{Args, Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use tracing_subscriber::filter::Directive;
 
-use crate::{front::cli as front, runner::cli as runner, ssmenv::with_replaced_env};
+use crate::{
+    build_info::LONG_VERSION, front::cli as front, runner::cli as runner,
+    ssmenv::with_replaced_env, trace::LogRotation,
+};
 
 pub type CommandResult = anyhow::Result<ExitCode>;
 
@@ -17,7 +24,7 @@ pub const FAILURE: CommandResult = Ok(ExitCode::FAILURE);
 
 #[allow(clippy::partial_pub_fields)] // To use global options.
 #[derive(Debug, Clone, Parser)]
-#[command(version, about, args_override_self(true))]
+#[command(version = LONG_VERSION, about, args_override_self(true))]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -29,6 +36,26 @@ struct Cli {
 pub struct GlobalArgs {
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
+    /// Log level of a target, e.g. `orgu::checkout=debug`. Can be passed multiple times. Overrides the level given by
+    /// the verbosity flags and `RUST_LOG` for the target.
+    #[arg(long = "log-level", global = true, value_name = "TARGET=LEVEL")]
+    pub log_levels: Vec<Directive>,
+    /// Also write logs to this file, e.g. for hosts without log collection agents. Rotated files are suffixed with
+    /// the date, e.g. `orgu.log.2024-01-01`. Disabled if none.
+    #[arg(long, env, global = true)]
+    pub log_file: Option<PathBuf>,
+    /// Rotation of `--log-file`.
+    #[arg(long, env, global = true, value_enum, default_value = "daily")]
+    pub log_rotation: LogRotation,
+    /// Write logs only to `--log-file`, not to stdout.
+    #[arg(
+        long,
+        env,
+        global = true,
+        default_value = "false",
+        requires = "log_file"
+    )]
+    pub log_file_only: bool,
 }
 
 #[derive(Debug, Clone, Subcommand)]
@@ -45,6 +72,13 @@ enum Commands {
     /// Clone and checkout GitHub repository.
     /// Use this command inside CI job in which GitHub Installation Access Token is available.
     Checkout(checkout::CheckoutArgs),
+    #[command(subcommand)]
+    /// Diagnose configuration.
+    Config(config::ConfigCommands),
+    /// Print the shell completion script to stdout, e.g. `orgu completions zsh > _orgu`.
+    Completions(completions::CompletionsArgs),
+    /// Write man pages for orgu and each subcommand to the directory.
+    Man(man::ManArgs),
 }
 
 pub async fn run() -> CommandResult {
@@ -60,6 +94,9 @@ pub async fn run() -> CommandResult {
         Commands::Runner(c) => runner::run(cli.args, c).await,
         Commands::Pattern(c) => pattern::run(cli.args, c).await,
         Commands::Checkout(c) => checkout::checkout(cli.args, c).await,
+        Commands::Config(c) => config::run(cli.args, c),
+        Commands::Completions(c) => completions::completions(cli.args, c),
+        Commands::Man(c) => man::man(cli.args, c),
     }
 }
 
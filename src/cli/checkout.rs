@@ -1,15 +1,20 @@
-use std::{env::current_dir, path::PathBuf};
+use std::{
+    env::current_dir,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context, Result};
 use clap::Args;
-use tokio::fs::create_dir_all;
+use futures::future::join_all;
+use tokio::fs::{create_dir_all, write};
+use tracing::error;
 
 use crate::{
     checkout::{Checkout as _, CheckoutConfig, CheckoutInput, Libgit2Checkout},
     cli::{CommandResult, GlobalArgs, SUCCESS},
     github_client::OctorustClient,
     github_config::GithubApiConfig,
-    trace::init_fmt_with_full,
+    trace::{init_fmt_with_full, init_fmt_with_full_to_stderr},
 };
 
 #[derive(Debug, Clone, Args)]
@@ -17,52 +22,193 @@ pub struct CheckoutArgs {
     /// Target SHA to checkout. If none, remote HEAD will be used.
     #[arg(long, short)]
     sha: Option<String>,
-    /// GitHub App installation token. Or GitHub Personal Access Token.
+    /// GitHub App installation token. Or GitHub Personal Access Token. Public repositories can be checked out
+    /// without token if `--sha` is given.
     #[arg(env = "GITHUB_TOKEN", hide_env_values = true, long)]
-    token: String,
+    token: Option<String>,
     /// Checkout given repository under this path. If none, checkout in
     /// the current working direcotry.
     #[arg(long)]
     under: Option<PathBuf>,
+    /// Resolve the SHA and print what would be fetched without fetching.
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+    /// Write the checked out SHA to this file, e.g. `$GITHUB_OUTPUT` style scripts. Pass `-` to write to stdout, then
+    /// logs are written to stderr instead.
+    #[arg(long, conflicts_with = "dry_run")]
+    output_sha_file: Option<PathBuf>,
+    /// Repository to checkout as `owner/repo[@sha]` instead of OWNER and REPO. Can be passed multiple times to
+    /// checkout repositories concurrently, each under `<under>/<owner>/<repo>`. Remote HEAD is used if no SHA given.
+    #[arg(
+        long = "spec",
+        value_parser = parse_repo_spec,
+        conflicts_with_all = ["owner", "repo", "sha", "output_sha_file"],
+    )]
+    specs: Vec<RepoSpec>,
     /// GitHub owner.
-    owner: String,
+    #[arg(required_unless_present = "specs")]
+    owner: Option<String>,
     /// GitHub repository name.
-    repo: String,
+    #[arg(required_unless_present = "specs")]
+    repo: Option<String>,
     #[command(flatten)]
     checkout_config: CheckoutConfig,
     #[command(flatten)]
     github_config: GithubApiConfig,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RepoSpec {
+    owner: String,
+    repo: String,
+    sha: Option<String>,
+}
+
+fn parse_repo_spec(s: &str) -> Result<RepoSpec> {
+    let (name, sha) = match s.split_once('@') {
+        Some((name, sha)) => (name, Some(sha.to_owned())),
+        None => (s, None),
+    };
+    let Some((owner, repo)) = name.split_once('/') else {
+        bail!("invalid repository spec: expected `owner/repo[@sha]`: `{s}`");
+    };
+    if owner.is_empty() || repo.is_empty() || repo.contains('/') || sha.as_deref() == Some("") {
+        bail!("invalid repository spec: expected `owner/repo[@sha]`: `{s}`");
+    }
+    Ok(RepoSpec {
+        owner: owner.to_owned(),
+        repo: repo.to_owned(),
+        sha,
+    })
+}
+
 pub async fn checkout(global: GlobalArgs, args: CheckoutArgs) -> CommandResult {
-    init_fmt_with_full(&global.verbose);
+    let sha_to_stdout = args.output_sha_file.as_deref() == Some(Path::new("-"));
+    let _guard = if sha_to_stdout {
+        init_fmt_with_full_to_stderr(&global)?
+    } else {
+        init_fmt_with_full(&global)?
+    };
 
-    let under = match args.under {
-        Some(p) => p,
+    let under = match &args.under {
+        Some(p) => p.clone(),
         None => current_dir().with_context(|| "could not get current working directory")?,
     };
-    create_dir_all(&under)
-        .await
-        .with_context(|| format!("could not create directory: {}", under.to_string_lossy()))?;
-    let sha = match args.sha {
-        Some(sha) => sha,
-        None => {
-            let github_client =
-                OctorustClient::new_with_token(args.github_config, args.token.clone())?;
-            github_client
-                .fetch_head_sha(&args.owner, &args.repo)
-                .await?
-        }
+    let specs = match (&args.owner, &args.repo) {
+        (Some(owner), Some(repo)) => vec![RepoSpec {
+            owner: owner.clone(),
+            repo: repo.clone(),
+            sha: args.sha.clone(),
+        }],
+        _ => args.specs.clone(),
     };
+    // Given as OWNER and REPO, checkout directly under the path for compatibility.
+    let single = args.specs.is_empty();
+
+    let mut targets = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let dir = if single {
+            under.clone()
+        } else {
+            under.join(&spec.owner).join(&spec.repo)
+        };
+        if targets.iter().any(|(_, d)| *d == dir) {
+            bail!(
+                "repository given multiple times: {}/{}",
+                spec.owner,
+                spec.repo
+            );
+        }
+        let sha = match spec.sha {
+            Some(sha) => sha,
+            None => fetch_head_sha(&args, &spec.owner, &spec.repo).await?,
+        };
+        let input = CheckoutInput {
+            owner: spec.owner,
+            repo: spec.repo,
+            sha,
+            head_ref: None,
+            token: args.token.clone(),
+        };
+        targets.push((input, dir));
+    }
 
-    let input = CheckoutInput {
-        owner: args.owner,
-        repo: args.repo,
-        sha,
-        token: args.token.clone(),
-    };
     let checkout = Libgit2Checkout::new(args.checkout_config);
-    checkout.checkout_under(&input, &under).await?;
+    if args.dry_run {
+        let plans = targets
+            .iter()
+            .map(|(input, dir)| checkout.plan(input, dir))
+            .collect::<Vec<_>>();
+        println!("{}", plans.join("\n\n"));
+        return SUCCESS;
+    }
+
+    let checkout = &checkout;
+    let results = join_all(targets.iter().map(|(input, dir)| async move {
+        create_dir_all(dir)
+            .await
+            .with_context(|| format!("could not create directory: {}", dir.to_string_lossy()))?;
+        checkout.checkout_under(input, dir).await
+    }))
+    .await;
+    let mut failed = 0_usize;
+    for ((input, _), r) in targets.iter().zip(results) {
+        match r {
+            Ok(_) => (),
+            Err(e) if single => return Err(e),
+            Err(e) => {
+                failed = failed.saturating_add(1);
+                error!(repo = input.full_name(), error = ?e, "failed to checkout repository");
+            }
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "failed to checkout {failed} of {} repositories",
+            targets.len()
+        );
+    }
 
+    let sha = targets.first().map(|(input, _)| &input.sha);
+    match (args.output_sha_file, sha) {
+        (Some(_), Some(sha)) if sha_to_stdout => println!("{sha}"),
+        (Some(path), Some(sha)) => write(&path, format!("{sha}\n"))
+            .await
+            .with_context(|| format!("could not write SHA to file: {}", path.display()))?,
+        _ => (),
+    }
     SUCCESS
 }
+
+async fn fetch_head_sha(args: &CheckoutArgs, owner: &str, repo: &str) -> Result<String> {
+    let token = args
+        .token
+        .clone()
+        .with_context(|| "token is required to fetch remote HEAD, give SHA instead")?;
+    let github_client = OctorustClient::new_with_token(args.github_config.clone(), token)?;
+    github_client.fetch_head_sha(owner, repo).await
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn repo_spec() {
+        assert_eq!(
+            parse_repo_spec("Finatext/orgu@abc").unwrap(),
+            RepoSpec {
+                owner: "Finatext".to_owned(),
+                repo: "orgu".to_owned(),
+                sha: Some("abc".to_owned()),
+            }
+        );
+        assert_eq!(parse_repo_spec("Finatext/orgu").unwrap().sha, None);
+        parse_repo_spec("orgu").unwrap_err();
+        parse_repo_spec("/orgu").unwrap_err();
+        parse_repo_spec("Finatext/orgu/x").unwrap_err();
+        parse_repo_spec("Finatext/orgu@").unwrap_err();
+    }
+}
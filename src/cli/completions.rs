@@ -0,0 +1,35 @@
+use std::io::stdout;
+
+use clap::{Args, CommandFactory as _};
+use clap_complete::{generate, Shell};
+
+use crate::cli::{Cli, CommandResult, GlobalArgs, SUCCESS};
+
+#[derive(Debug, Clone, Args)]
+pub struct CompletionsArgs {
+    /// Shell to generate the completion script for.
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+pub fn completions(_global: GlobalArgs, args: CompletionsArgs) -> CommandResult {
+    let mut cmd = Cli::command();
+    let name = cmd.get_name().to_owned();
+    generate(args.shell, &mut cmd, name, &mut stdout());
+    SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_bash() {
+        let mut cmd = Cli::command();
+        let mut buf = Vec::new();
+        generate(Shell::Bash, &mut cmd, "orgu", &mut buf);
+        let script = String::from_utf8(buf).unwrap();
+        assert!(script.contains("--job-name"));
+        assert!(script.contains("--github-read-timeout"));
+    }
+}
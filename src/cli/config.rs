@@ -0,0 +1,58 @@
+use clap::{Args, Subcommand};
+use serde::Serialize;
+use serde_json::to_string_pretty;
+
+use crate::{
+    checkout::CheckoutConfig,
+    cli::{CommandResult, GlobalArgs, SUCCESS},
+    front::config::FrontConfig,
+    github_config::{GithubApiConfig, GithubAppConfig},
+    runner::handler::Config,
+};
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective configuration as JSON. Secret values are redacted.
+    /// Give the same flags and env vars as the actual command.
+    #[command(subcommand)]
+    Show(ShowTarget),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ShowTarget {
+    /// Configuration of runner commands.
+    Runner(Box<RunnerConfig>),
+    /// Configuration of front commands.
+    Front(Box<FrontConfigArgs>),
+}
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct RunnerConfig {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    checkout_config: CheckoutConfig,
+    #[command(flatten)]
+    handler_config: Config,
+}
+
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct FrontConfigArgs {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    config: FrontConfig,
+}
+
+pub fn run(_global: GlobalArgs, c: ConfigCommands) -> CommandResult {
+    let json = match c {
+        ConfigCommands::Show(ShowTarget::Runner(args)) => to_string_pretty(&args)?,
+        ConfigCommands::Show(ShowTarget::Front(args)) => to_string_pretty(&args)?,
+    };
+    println!("{json}");
+    SUCCESS
+}
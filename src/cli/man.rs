@@ -0,0 +1,37 @@
+use std::{fs::create_dir_all, path::PathBuf};
+
+use anyhow::Context as _;
+use clap::{Args, CommandFactory as _};
+use clap_mangen::generate_to;
+
+use crate::cli::{Cli, CommandResult, GlobalArgs, SUCCESS};
+
+#[derive(Debug, Clone, Args)]
+pub struct ManArgs {
+    /// Directory to write the man pages to. Created if missing.
+    #[arg(default_value = "man")]
+    out_dir: PathBuf,
+}
+
+// Writes `orgu.1` and a page per subcommand, e.g. `orgu-runner-server.1`.
+pub fn man(_global: GlobalArgs, args: ManArgs) -> CommandResult {
+    create_dir_all(&args.out_dir)
+        .with_context(|| format!("creating {} failed", args.out_dir.display()))?;
+    generate_to(Cli::command(), &args.out_dir)
+        .with_context(|| format!("writing man pages to {} failed", args.out_dir.display()))?;
+    SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path();
+        generate_to(Cli::command(), out_dir).unwrap();
+        assert!(out_dir.join("orgu.1").exists());
+        assert!(out_dir.join("orgu-runner-server.1").exists());
+    }
+}
@@ -1,6 +1,9 @@
 mod generate;
+#[cfg(feature = "aws")]
 mod test;
 
+use std::collections::HashMap;
+
 use anyhow::{bail, Result};
 use clap::{Args, Subcommand, ValueEnum};
 use strum::Display;
@@ -10,6 +13,7 @@ use crate::cli::{CommandResult, GlobalArgs};
 #[derive(Debug, Clone, Subcommand)]
 pub enum PatternCommands {
     /// Generate example event to test EventBridge event pattern.
+    #[cfg(feature = "aws")]
     Test(test::TestArgs),
     /// Generate event pattern.
     Generate(generate::GenerateArgs),
@@ -17,6 +21,7 @@ pub enum PatternCommands {
 
 pub async fn run(global: GlobalArgs, c: PatternCommands) -> CommandResult {
     match c {
+        #[cfg(feature = "aws")]
         PatternCommands::Test(args) => test::test(global, args).await,
         PatternCommands::Generate(args) => generate::generate(global, args),
     }
@@ -26,15 +31,28 @@ pub async fn run(global: GlobalArgs, c: PatternCommands) -> CommandResult {
 struct CustomPropsConfig {
     #[arg(short, long, value_parser = parse_key_val)]
     /// GitHub Custom Properties for the example event. Pass each pair as `key=value` format.
+    /// Repeat the same key to give multiple values, e.g. for multi-select properties.
     custom_props: Vec<(String, String)>,
 }
 
+impl CustomPropsConfig {
+    // Group values by key keeping the given order.
+    fn grouped(&self) -> HashMap<String, Vec<String>> {
+        let mut grouped = HashMap::<_, Vec<_>>::new();
+        for (k, v) in &self.custom_props {
+            grouped.entry(k.clone()).or_default().push(v.clone());
+        }
+        grouped
+    }
+}
+
 #[derive(Debug, Clone, ValueEnum, Display)]
 #[clap(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 enum EventType {
     PullRequest,
     CheckSuite,
+    MergeGroup,
 }
 
 #[derive(Debug, Clone, ValueEnum, Display)]
@@ -48,6 +66,7 @@ enum EventAction {
     ReadyForReview,
     Requested,
     Rerequested,
+    ChecksRequested,
 }
 
 fn parse_key_val(s: &str) -> Result<(String, String)> {
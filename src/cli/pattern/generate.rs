@@ -48,12 +48,8 @@ impl DetailRepository {
 }
 
 pub fn generate(_global: GlobalArgs, args: GenerateArgs) -> CommandResult {
-    let custom_properties = args
-        .cps
-        .custom_props
-        .into_iter()
-        .map(|(k, v)| (k, vec![v]))
-        .collect();
+    // EventBridge matches if any of the values matches, also for list values in the event.
+    let custom_properties = args.cps.grouped();
 
     let source = vec!["orgu-front".to_owned()];
     let detail_type = vec!["orgu.check_request".to_owned()];
@@ -62,8 +58,13 @@ pub fn generate(_global: GlobalArgs, args: GenerateArgs) -> CommandResult {
             source,
             detail_type,
             detail: Detail {
-                // To response to "Re-run all checks", subscribe check_suite/rerequested event.
-                event_name: vec!["pull_request".to_owned(), "check_suite".to_owned()],
+                // To response to "Re-run all checks", subscribe check_suite/rerequested event. issue_comment events
+                // are sent only for comment commands, see `front server --comment-command`.
+                event_name: vec![
+                    "pull_request".to_owned(),
+                    "check_suite".to_owned(),
+                    "issue_comment".to_owned(),
+                ],
                 action: vec![
                     // For pull_request event.
                     "opened".to_owned(),
@@ -72,6 +73,8 @@ pub fn generate(_global: GlobalArgs, args: GenerateArgs) -> CommandResult {
                     "ready_for_review".to_owned(),
                     // For check_suite event.
                     "rerequested".to_owned(),
+                    // For issue_comment event.
+                    "created".to_owned(),
                 ],
                 repository: DetailRepository { custom_properties },
             },
@@ -85,6 +88,15 @@ pub fn generate(_global: GlobalArgs, args: GenerateArgs) -> CommandResult {
                 repository: DetailRepository { custom_properties },
             },
         },
+        EventType::MergeGroup => Pattern {
+            source,
+            detail_type,
+            detail: Detail {
+                event_name: vec!["merge_group".to_owned()],
+                action: vec!["checks_requested".to_owned()],
+                repository: DetailRepository { custom_properties },
+            },
+        },
     };
 
     println!("{}", to_string_pretty(&pattern)?);
@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 
+use anyhow::{bail, Context as _, Result};
 use aws_lambda_events::eventbridge::EventBridgeEvent;
 use aws_sdk_cloudwatchevents::Client;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use indoc::printdoc;
-use serde_json::to_string_pretty;
+use serde::Serialize;
+use serde_json::{from_str, from_value, to_string_pretty, to_value, Map, Value};
 use tokio::{
     fs,
     io::{self, AsyncReadExt as _},
@@ -12,7 +14,8 @@ use tokio::{
 
 use crate::{
     cli::{CommandResult, GlobalArgs, FAILURE, SUCCESS},
-    events::{CheckRequest, GithubRepository, User},
+    event_queue_client::SCHEMA_VERSION,
+    events::{CheckRequest, CustomPropValue, GithubRepository, User},
 };
 
 use super::{CustomPropsConfig, EventAction, EventType};
@@ -42,11 +45,50 @@ pub struct TestArgs {
     /// GitHub login name as the sender for the example event.
     #[arg(short, long, default_value = "ferris")]
     sender: String,
+    /// JSON object merged over the example `CheckRequest` to test patterns on fields not exposed by the flags, e.g.
+    /// `{"base_ref":"main","repository":{"private":false}}`. Any field of `CheckRequest` can be overridden: objects
+    /// are merged recursively and other values are replaced. Applied after the other flags.
+    #[arg(long, value_parser = parse_overlay)]
+    event_overlay: Option<Map<String, Value>>,
+    /// Output format of the test result. Ignored in quiet mode, which only sets the exit code.
+    #[arg(long, value_enum, default_value = "pretty")]
+    output: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Human readable text.
+    Pretty,
+    /// JSON object of `matched`, `event` and `pattern` for scripts.
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct TestResult<'res> {
+    matched: bool,
+    event: &'res EventBridgeEvent<CheckRequest>,
+    /// Kept as is if the pattern isn't valid JSON.
+    pattern: Value,
 }
 
 pub async fn test(global: GlobalArgs, args: TestArgs) -> CommandResult {
-    let custom_props = args.cps.custom_props.clone().into_iter().collect();
-    let req = example_check_request(args.clone(), custom_props);
+    let custom_props = args
+        .cps
+        .grouped()
+        .into_iter()
+        .map(|(k, mut vs)| {
+            let v = if vs.len() == 1 {
+                CustomPropValue::Single(vs.remove(0))
+            } else {
+                CustomPropValue::Multiple(vs)
+            };
+            (k, v)
+        })
+        .collect();
+    let mut req = example_check_request(args.clone(), custom_props);
+    if let Some(overlay) = args.event_overlay.clone() {
+        req = apply_overlay(req, overlay)?;
+    }
     let ev = example_eventbridge_event(req);
     let event_json = to_string_pretty(&ev)?;
 
@@ -81,6 +123,16 @@ pub async fn test(global: GlobalArgs, args: TestArgs) -> CommandResult {
         }
     }
 
+    if matches!(args.output, OutputFormat::Json) {
+        let result = TestResult {
+            matched: res.result,
+            event: &ev,
+            pattern: from_str(&input).unwrap_or(Value::String(input)),
+        };
+        println!("{}", to_string_pretty(&result)?);
+        return if res.result { SUCCESS } else { FAILURE };
+    }
+
     if res.result {
         printdoc! {"
           Event match the pattern.
@@ -108,6 +160,35 @@ pub async fn test(global: GlobalArgs, args: TestArgs) -> CommandResult {
     }
 }
 
+fn parse_overlay(s: &str) -> Result<Map<String, Value>> {
+    let Value::Object(m) = from_str(s).with_context(|| "invalid event overlay: not JSON")? else {
+        bail!("invalid event overlay: must be a JSON object");
+    };
+    Ok(m)
+}
+
+fn apply_overlay(req: CheckRequest, overlay: Map<String, Value>) -> Result<CheckRequest> {
+    let mut base = to_value(req)?;
+    if let Some(fields) = base.as_object() {
+        if let Some(k) = overlay.keys().find(|k| !fields.contains_key(*k)) {
+            bail!("invalid event overlay: unknown field of CheckRequest: `{k}`");
+        }
+    }
+    merge(&mut base, Value::Object(overlay));
+    from_value(base).with_context(|| "invalid event overlay: doesn't fit CheckRequest")
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(b), Value::Object(o)) => {
+            for (k, v) in o {
+                merge(b.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (b, o) => *b = o,
+    }
+}
+
 fn example_eventbridge_event(check_request: CheckRequest) -> EventBridgeEvent<CheckRequest> {
     EventBridgeEvent {
         version: Some("0".to_owned()),
@@ -122,12 +203,16 @@ fn example_eventbridge_event(check_request: CheckRequest) -> EventBridgeEvent<Ch
     }
 }
 
-fn example_check_request(args: TestArgs, custom_props: HashMap<String, String>) -> CheckRequest {
+fn example_check_request(
+    args: TestArgs,
+    custom_props: HashMap<String, CustomPropValue>,
+) -> CheckRequest {
     let pr_number = match args.name {
         EventType::PullRequest => Some(5),
-        _ => None,
+        EventType::CheckSuite | EventType::MergeGroup => None,
     };
     CheckRequest {
+        schema_version: SCHEMA_VERSION,
         request_id: "45771944-d356-4540-a0b7-b6dff7637f8d".to_owned(),
         delivery_id: "dc3640c3-4bd0-4a6a-8923-b6f82c859797".to_owned(),
         event_name: args.name.to_string(),
@@ -138,6 +223,8 @@ fn example_check_request(args: TestArgs, custom_props: HashMap<String, String>)
             private: true,
             owner: User { login: args.owner },
             custom_properties: custom_props,
+            archived: false,
+            disabled: false,
         },
         head_sha: "a8619f1cf1f6ade02df413b18265f74d3bc9caca".to_owned(),
         base_sha: None,
@@ -145,6 +232,45 @@ fn example_check_request(args: TestArgs, custom_props: HashMap<String, String>)
         before: None,
         after: Some("a8619f1cf1f6ade02df413b18265f74d3bc9caca".to_owned()),
         pull_request_number: pr_number,
+        pull_request_head_ref: None,
         sender: User { login: args.sender },
+        check_run: None,
+        comment_id: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn overlay() {
+        let req = CheckRequest {
+            head_sha: "head".to_owned(),
+            repository: GithubRepository {
+                name: "orgu".to_owned(),
+                private: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let overlay = parse_overlay(
+            r#"{"base_ref":"main","pull_request_number":null,"repository":{"private":false}}"#,
+        )
+        .unwrap();
+        let req = apply_overlay(req, overlay).unwrap();
+        assert_eq!(req.head_sha, "head");
+        assert_eq!(req.base_ref.as_deref(), Some("main"));
+        assert_eq!(req.pull_request_number, None);
+        assert_eq!(req.repository.name, "orgu");
+        assert!(!req.repository.private);
+
+        parse_overlay("[]").unwrap_err();
+        let unknown = parse_overlay(r#"{"unknown":1}"#).unwrap();
+        apply_overlay(CheckRequest::default(), unknown).unwrap_err();
+        let mistyped = parse_overlay(r#"{"head_sha":1}"#).unwrap();
+        apply_overlay(CheckRequest::default(), mistyped).unwrap_err();
     }
 }
@@ -0,0 +1,70 @@
+//! Serializers for configuration structs to print the effective configuration.
+
+use std::fmt::Display;
+
+use serde::Serializer;
+
+pub const REDACTED: &str = "(redacted)";
+
+/// Hide secret values. Empty values are kept to tell they are not given.
+pub fn redact<S: Serializer>(v: &str, s: S) -> Result<S::Ok, S::Error> {
+    if v.is_empty() {
+        s.serialize_str("")
+    } else {
+        s.serialize_str(REDACTED)
+    }
+}
+
+/// Serialize with Display trait, e.g. `humantime::Duration` as "10s".
+pub fn display<T: Display, S: Serializer>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(v)
+}
+
+#[allow(clippy::ref_option)] // Signature is required by serde.
+pub fn display_opt<T: Display, S: Serializer>(v: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+    match v {
+        Some(v) => s.collect_str(v),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::Serialize;
+    use serde_json::{json, to_value};
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Example {
+        #[serde(serialize_with = "redact")]
+        secret: String,
+        #[serde(serialize_with = "redact")]
+        empty_secret: String,
+        #[serde(serialize_with = "display")]
+        timeout: humantime::Duration,
+        #[serde(serialize_with = "display_opt")]
+        interval: Option<humantime::Duration>,
+    }
+
+    #[test]
+    fn serialize() {
+        let v = Example {
+            secret: "secret".to_owned(),
+            empty_secret: String::new(),
+            timeout: Duration::from_secs(90).into(),
+            interval: None,
+        };
+        assert_eq!(
+            to_value(v).unwrap(),
+            json!({
+                "secret": "(redacted)",
+                "empty_secret": "",
+                "timeout": "1m 30s",
+                "interval": null,
+            })
+        );
+    }
+}
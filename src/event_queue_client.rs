@@ -1,15 +1,44 @@
-use std::str::from_utf8;
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "redis")]
+mod redis_stream;
 
-use anyhow::{bail, Context, Result};
+use std::{io::Write as _, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use aws_config::timeout::TimeoutConfigBuilder;
-use aws_sdk_cloudwatchevents::{types::PutEventsRequestEntry, Client as CwClient};
-use clap::Args;
+use chrono::Utc;
+use flate2::{write::GzEncoder, Compression};
+use http::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE, HOST},
+    HeaderName, HeaderValue, Request, StatusCode,
+};
+use http_body_util::{BodyExt as _, Full};
+use hyper::{body::Bytes, client::conn::http1};
+use hyper_util::rt::TokioIo;
 use reqwest::Client as HttpClient;
-use tracing::{info, instrument};
+use thiserror::Error;
+use tokio::{net::UnixStream, spawn, time::timeout};
+use tracing::{info, instrument, warn};
 use url::Url;
 
-use crate::events::CheckRequest;
+use crate::{events::CheckRequest, relay_signature::RelaySignature};
+
+#[cfg(feature = "aws")]
+pub use aws::{AwsEventBusClient, AwsEventBusConfig};
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaClient, KafkaConfig};
+#[cfg(feature = "redis")]
+pub use redis_stream::{
+    connect as redis_connect, is_transient_redis_error, RedisStreamClient, RedisStreamConfig,
+    REDIS_STREAM_FIELD,
+};
+
+/// Version of the `CheckRequest` schema, stamped by front and checked by runners. Bump it only on changes older
+/// runners can't handle, compatible changes don't need it. See `CheckRequest` for the compatibility policy.
+pub const SCHEMA_VERSION: u32 = 1;
 
 /// Event queue client to send and fan-out events to downstream runners.
 /// AWS EventBridge Event Bus Client or relay server client.
@@ -17,153 +46,299 @@ use crate::events::CheckRequest;
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait EventQueueClient: Sync + Send {
-    async fn send(&self, req: CheckRequest) -> Result<()>;
-}
-
-// Prefixed with `event_bus_` to avoid conflict with `GithubApiConfig`.
-#[derive(Debug, Clone, Args)]
-#[group()]
-pub struct AwsEventBusConfig {
-    /// The name of the EventBridge event bus to send events.
-    #[arg(env, long, default_value = "default")]
-    pub event_bus_name: String,
-    /// Timeout for connecting to the event bus.
-    /// See more detail on: https://docs.rs/aws-config/latest/aws_config/timeout/struct.TimeoutConfigBuilder.html
-    /// To customize retry, see: https://docs.aws.amazon.com/sdk-for-rust/latest/dg/retries.html
-    #[arg(env, long)]
-    pub event_bus_connect_timeout: Option<humantime::Duration>,
-    #[arg(env, long)]
-    pub event_bus_read_timeout: Option<humantime::Duration>,
-    #[arg(env, long)]
-    pub event_bus_operation_timeout: Option<humantime::Duration>,
-    #[arg(env, long)]
-    pub event_bus_operation_attempt_timeout: Option<humantime::Duration>,
+    async fn send(&self, req: CheckRequest) -> Result<(), SendError>;
 }
 
-#[derive(Debug)]
-pub struct AwsEventBusClient {
-    inner: CwClient,
-    event_bus_name: String,
+#[derive(Debug, Error)]
+pub enum SendError {
+    /// Temporary failure such as network errors or throttling. Retrying may succeed.
+    #[error("transient failure sending event")]
+    Transient(#[source] anyhow::Error),
+    /// Failure which retrying won't fix such as serialization errors or invalid requests.
+    #[error("permanent failure sending event")]
+    Permanent(#[source] anyhow::Error),
 }
 
-impl AwsEventBusClient {
-    pub async fn new(config: AwsEventBusConfig) -> Self {
-        let mut timeout = TimeoutConfigBuilder::new();
-        if let Some(d) = config.event_bus_connect_timeout {
-            timeout = timeout.connect_timeout(d.into());
-        }
-        if let Some(d) = config.event_bus_read_timeout {
-            timeout = timeout.read_timeout(d.into());
-        }
-        if let Some(d) = config.event_bus_operation_timeout {
-            timeout = timeout.operation_timeout(d.into());
-        }
-        if let Some(d) = config.event_bus_operation_attempt_timeout {
-            timeout = timeout.operation_attempt_timeout(d.into());
-        }
-
-        let sdk_config = aws_config::load_from_env().await;
-        let mut builder = sdk_config.into_builder();
-        builder.set_timeout_config(Some(timeout.build()));
-        Self {
-            inner: CwClient::new(&builder.build()),
-            event_bus_name: config.event_bus_name,
+impl SendError {
+    const fn transient_if(transient: bool, e: anyhow::Error) -> Self {
+        if transient {
+            Self::Transient(e)
+        } else {
+            Self::Permanent(e)
         }
     }
 }
 
-const EVENT_SOURCE: &str = "orgu-front";
-const EVENT_TYPE: &str = "orgu.check_request";
-
-#[async_trait]
-impl EventQueueClient for AwsEventBusClient {
-    // https://docs.rs/aws-sdk-cloudwatchevents/latest/aws_sdk_cloudwatchevents/types/struct.PutEventsRequestEntry.html
-    //
-    // To propagate trace context, see: https://docs.rs/aws-sdk-cloudwatchevents/latest/aws_sdk_cloudwatchevents/client/customize/index.html
-    #[instrument(skip_all, fields(event_bus_name = %self.event_bus_name))]
-    async fn send(&self, req: CheckRequest) -> Result<()> {
-        info!("sending event to AWS Event Bus");
-        let detail =
-            serde_json::to_string(&req).with_context(|| "serializing CheckRequest failed")?;
-        let input = PutEventsRequestEntry::builder()
-            .set_event_bus_name(Some(self.event_bus_name.clone()))
-            .set_source(Some(EVENT_SOURCE.to_owned()))
-            .set_detail(Some(detail))
-            .set_detail_type(Some(EVENT_TYPE.to_owned()))
-            .build();
-        let out = self
-            .inner
-            .put_events()
-            .entries(input)
-            .send()
-            .await
-            .with_context(|| "sending event to AWS Event Bus failed")?;
-        if out.failed_entry_count > 0 {
-            bail!(
-                "event sent to AWS Event Bus but failed: failed_count={}",
-                out.failed_entry_count
-            );
-        }
-        out.entries.into_iter().flatten().for_each(|e| {
-            info!(
-                "event sent to AWS Event Bus: id={}",
-                e.event_id.unwrap_or_default()
-            );
-        });
-
-        Ok(())
-    }
+// Same as HTTP clients in general, retry on server errors and throttling.
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
 }
 
 #[derive(Debug)]
 pub struct EventQueueRelayConfig {
     pub endpoint: Url,
+    pub connect_timeout: Duration,
+    /// Timeout between reads of the response. Disabled if none.
+    pub read_timeout: Option<Duration>,
+    /// Timeout for the whole request including the response. Disabled if none.
+    pub request_timeout: Option<Duration>,
+    /// Compress request bodies with gzip. The receiver must accept `Content-Encoding: gzip`.
+    pub compress_request: bool,
+    /// Sign request bodies with the timestamp. Disabled if none.
+    pub signature: Option<RelaySignature>,
 }
 
+const UNIX_SCHEME: &str = "unix";
+// Path of the runner server endpoint. Used for Unix domain socket endpoints which have no HTTP path.
+const UNIX_REQUEST_PATH: &str = "/run";
+
 #[derive(Debug)]
 pub struct EventQueueRelayClient {
     inner: HttpClient,
     url: Url,
+    request_timeout: Option<Duration>,
+    compress_request: bool,
+    signature: Option<RelaySignature>,
 }
 
+// Body to send and the signature headers if enabled.
+type EncodedBody = (Vec<u8>, Vec<(HeaderName, HeaderValue)>);
+
 impl EventQueueRelayClient {
-    pub fn new(config: EventQueueRelayConfig) -> Self {
-        Self {
-            inner: HttpClient::new(),
+    pub fn new(config: EventQueueRelayConfig) -> Result<Self> {
+        let mut builder = HttpClient::builder().connect_timeout(config.connect_timeout);
+        if let Some(d) = config.read_timeout {
+            builder = builder.read_timeout(d);
+        }
+        if let Some(d) = config.request_timeout {
+            builder = builder.timeout(d);
+        }
+        Ok(Self {
+            inner: builder.build()?,
             url: config.endpoint,
+            request_timeout: config.request_timeout,
+            compress_request: config.compress_request,
+            signature: config.signature,
+        })
+    }
+
+    // The signature is of the uncompressed body, which the receiver verifies after decompression.
+    fn encode_body(&self, req: &CheckRequest) -> Result<EncodedBody, SendError> {
+        let body = serde_json::to_vec(req)
+            .with_context(|| "serializing CheckRequest failed")
+            .map_err(SendError::Permanent)?;
+        let headers = match &self.signature {
+            Some(s) => s
+                .headers(&body, Utc::now())
+                .with_context(|| "signing CheckRequest failed")
+                .map_err(SendError::Permanent)?
+                .into(),
+            None => Vec::new(),
+        };
+        if !self.compress_request {
+            return Ok((body, headers));
         }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let body = encoder
+            .write_all(&body)
+            .and_then(|()| encoder.finish())
+            .with_context(|| "compressing CheckRequest failed")
+            .map_err(SendError::Permanent)?;
+        Ok((body, headers))
     }
-}
 
-#[async_trait]
-impl EventQueueClient for EventQueueRelayClient {
-    #[instrument(skip_all, fields(url = %self.url))]
-    async fn send(&self, req: CheckRequest) -> Result<()> {
-        info!("sending event to local server");
-        let response = self
+    fn content_encoding(&self) -> Option<&'static str> {
+        self.compress_request.then_some("gzip")
+    }
+
+    async fn send_http(&self, req: &CheckRequest) -> Result<(StatusCode, Bytes), SendError> {
+        let (body, headers) = self.encode_body(req)?;
+        let mut request = self
             .inner
             .post(self.url.clone())
-            .json(&req)
-            .send()
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(encoding) = self.content_encoding() {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.body(body).send().await.map_err(|e| {
+            let transient = !e.is_builder();
+            let e =
+                anyhow::Error::new(e).context(format!("sending event failed: uri={}", self.url));
+            SendError::transient_if(transient, e)
+        })?;
+
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .with_context(|| {
+                format!(
+                    "reading response body failed: uri={}, status={status}",
+                    self.url
+                )
+            })
+            .map_err(SendError::Transient)?;
+        Ok((status, body))
+    }
+
+    // reqwest doesn't support Unix domain socket, so use hyper directly.
+    // The socket path is taken from the URL path, e.g. `unix:///tmp/orgu-runner.sock`.
+    async fn send_unix(&self, req: &CheckRequest) -> Result<(StatusCode, Bytes), SendError> {
+        let (body, headers) = self.encode_body(req)?;
+        let Some(d) = self.request_timeout else {
+            return self
+                .do_send_unix(body, headers)
+                .await
+                .map_err(SendError::Transient);
+        };
+        timeout(d, self.do_send_unix(body, headers))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("sending event timed out: uri={}", self.url)))
+            .map_err(SendError::Transient)
+    }
+
+    async fn do_send_unix(
+        &self,
+        body: Vec<u8>,
+        headers: Vec<(HeaderName, HeaderValue)>,
+    ) -> Result<(StatusCode, Bytes)> {
+        let stream = UnixStream::connect(self.url.path())
+            .await
+            .with_context(|| format!("connecting to unix socket failed: uri={}", self.url))?;
+        let (mut sender, conn) = http1::handshake(TokioIo::new(stream))
+            .await
+            .with_context(|| format!("HTTP handshake failed: uri={}", self.url))?;
+        spawn(async move {
+            if let Err(e) = conn.await {
+                warn!(error = ?e, "unix socket connection failed");
+            }
+        });
+
+        let mut request = Request::post(UNIX_REQUEST_PATH)
+            .header(HOST, "localhost")
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(encoding) = self.content_encoding() {
+            request = request.header(CONTENT_ENCODING, encoding);
+        }
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let request = request.body(Full::new(Bytes::from(body)))?;
+        let response = sender
+            .send_request(request)
             .await
             .with_context(|| format!("sending event failed: uri={}", self.url))?;
 
         let status = response.status();
-        let body = response.bytes().await.with_context(|| {
-            format!(
-                "reading response body failed: uri={}, status={status}",
-                self.url
-            )
-        })?;
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .with_context(|| {
+                format!(
+                    "reading response body failed: uri={}, status={status}",
+                    self.url
+                )
+            })?
+            .to_bytes();
+        Ok((status, body))
+    }
+}
+
+#[async_trait]
+impl EventQueueClient for EventQueueRelayClient {
+    #[instrument(skip_all, fields(url = %self.url))]
+    async fn send(&self, req: CheckRequest) -> Result<(), SendError> {
+        info!("sending event to local server");
+        let (status, body) = if self.url.scheme() == UNIX_SCHEME {
+            self.send_unix(&req).await?
+        } else {
+            self.send_http(&req).await?
+        };
 
         if status.is_success() {
             Ok(())
         } else {
-            bail!(
+            let e = anyhow!(
                 "event sent but response failure: uri={}, status={status}, body={}",
                 self.url,
-                from_utf8(&body)?
-            )
+                String::from_utf8_lossy(&body)
+            );
+            Err(SendError::transient_if(is_transient_status(status), e))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Json, Router};
+    use tokio::{net::TcpListener, time::sleep};
+    use tower_http::decompression::RequestDecompressionLayer;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn compressed_request() {
+        let app = Router::new()
+            .route(
+                "/run",
+                post(|Json(req): Json<CheckRequest>| async move {
+                    if req.request_id == "compressed" {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    }
+                }),
+            )
+            .layer(RequestDecompressionLayer::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint =
+            Url::parse(&format!("http://{}/run", listener.local_addr().unwrap())).unwrap();
+        spawn(async move { axum::serve(listener, app).await });
+
+        let client = EventQueueRelayClient::new(EventQueueRelayConfig {
+            endpoint,
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: None,
+            request_timeout: None,
+            compress_request: true,
+            signature: None,
+        })
+        .unwrap();
+        let req = CheckRequest {
+            request_id: "compressed".to_owned(),
+            ..Default::default()
+        };
+        client.send(req).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn relay_timeout_is_transient() {
+        let app = Router::new().route(
+            "/run",
+            post(|| async {
+                sleep(Duration::from_secs(10)).await;
+                "ok"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint =
+            Url::parse(&format!("http://{}/run", listener.local_addr().unwrap())).unwrap();
+        spawn(async move { axum::serve(listener, app).await });
+
+        let client = EventQueueRelayClient::new(EventQueueRelayConfig {
+            endpoint,
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: None,
+            request_timeout: Some(Duration::from_millis(50)),
+            compress_request: false,
+            signature: None,
+        })
+        .unwrap();
+        let err = client.send(CheckRequest::default()).await.unwrap_err();
+        assert!(matches!(err, SendError::Transient(_)), "{err:?}");
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _};
+use async_trait::async_trait;
+use aws_config::timeout::TimeoutConfigBuilder;
+use aws_sdk_cloudwatchevents::{
+    error::{ProvideErrorMetadata as _, SdkError},
+    operation::put_events::PutEventsError,
+    types::PutEventsRequestEntry,
+    Client as CwClient,
+};
+use clap::{builder::RangedU64ValueParser, Args};
+use http::StatusCode;
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::timeout,
+};
+use tracing::{info, instrument};
+
+use super::{is_transient_status, EventQueueClient, SendError};
+use crate::events::CheckRequest;
+
+// Prefixed with `event_bus_` to avoid conflict with `GithubApiConfig`.
+#[derive(Debug, Clone, Args)]
+#[group()]
+pub struct AwsEventBusConfig {
+    /// The name of the EventBridge event bus to send events.
+    #[arg(env, long, default_value = "default")]
+    pub event_bus_name: String,
+    /// Timeout for connecting to the event bus.
+    /// See more detail on: https://docs.rs/aws-config/latest/aws_config/timeout/struct.TimeoutConfigBuilder.html
+    /// To customize retry, see: https://docs.aws.amazon.com/sdk-for-rust/latest/dg/retries.html
+    #[arg(env, long)]
+    pub event_bus_connect_timeout: Option<humantime::Duration>,
+    #[arg(env, long)]
+    pub event_bus_read_timeout: Option<humantime::Duration>,
+    #[arg(env, long)]
+    pub event_bus_operation_timeout: Option<humantime::Duration>,
+    #[arg(env, long)]
+    pub event_bus_operation_attempt_timeout: Option<humantime::Duration>,
+    /// Maximum number of concurrent sends to the event bus. Unlimited if none. Must be at least 1.
+    #[arg(env, long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub event_bus_max_concurrency: Option<usize>,
+    /// How long a send waits for a slot when the concurrency is at the limit. Timed out sends are responded with
+    /// 503 so that GitHub retries the delivery.
+    #[arg(env, long, default_value = "5s")]
+    pub event_bus_queue_timeout: humantime::Duration,
+}
+
+#[derive(Debug)]
+pub struct AwsEventBusClient {
+    inner: CwClient,
+    event_bus_name: String,
+    limit: Option<ConcurrencyLimit>,
+}
+
+// Bounds concurrent `put_events` calls so a webhook storm doesn't cascade into throttling.
+#[derive(Debug)]
+struct ConcurrencyLimit {
+    semaphore: Semaphore,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimit {
+    fn new(max: usize, queue_timeout: Duration) -> Self {
+        Self {
+            semaphore: Semaphore::new(max),
+            queue_timeout,
+        }
+    }
+
+    async fn acquire(&self) -> Result<SemaphorePermit<'_>, SendError> {
+        match timeout(self.queue_timeout, self.semaphore.acquire()).await {
+            Ok(permit) => permit.map_err(|e| SendError::Permanent(e.into())),
+            Err(e) => Err(SendError::Transient(
+                anyhow::Error::new(e).context("waiting for event bus concurrency slot timed out"),
+            )),
+        }
+    }
+}
+
+impl AwsEventBusClient {
+    pub async fn new(config: AwsEventBusConfig) -> Self {
+        let mut timeout = TimeoutConfigBuilder::new();
+        if let Some(d) = config.event_bus_connect_timeout {
+            timeout = timeout.connect_timeout(d.into());
+        }
+        if let Some(d) = config.event_bus_read_timeout {
+            timeout = timeout.read_timeout(d.into());
+        }
+        if let Some(d) = config.event_bus_operation_timeout {
+            timeout = timeout.operation_timeout(d.into());
+        }
+        if let Some(d) = config.event_bus_operation_attempt_timeout {
+            timeout = timeout.operation_attempt_timeout(d.into());
+        }
+
+        let sdk_config = aws_config::load_from_env().await;
+        let mut builder = sdk_config.into_builder();
+        builder.set_timeout_config(Some(timeout.build()));
+        Self {
+            inner: CwClient::new(&builder.build()),
+            event_bus_name: config.event_bus_name,
+            limit: config
+                .event_bus_max_concurrency
+                .map(|n| ConcurrencyLimit::new(n, config.event_bus_queue_timeout.into())),
+        }
+    }
+}
+
+const EVENT_SOURCE: &str = "orgu-front";
+const EVENT_TYPE: &str = "orgu.check_request";
+
+// https://docs.aws.amazon.com/eventbridge/latest/APIReference/CommonErrors.html
+const TRANSIENT_ERROR_CODES: &[&str] = &[
+    "ThrottlingException",
+    "InternalException",
+    "InternalFailure",
+];
+
+#[allow(clippy::wildcard_enum_match_arm)] // SdkError is non_exhaustive.
+fn is_transient_sdk_error(e: &SdkError<PutEventsError>) -> bool {
+    match e {
+        SdkError::ServiceError(se) => {
+            is_transient_status(
+                StatusCode::from_u16(se.raw().status().as_u16()).unwrap_or_default(),
+            ) || se
+                .err()
+                .code()
+                .is_some_and(|c| TRANSIENT_ERROR_CODES.contains(&c))
+        }
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl EventQueueClient for AwsEventBusClient {
+    // https://docs.rs/aws-sdk-cloudwatchevents/latest/aws_sdk_cloudwatchevents/types/struct.PutEventsRequestEntry.html
+    //
+    // To propagate trace context, see: https://docs.rs/aws-sdk-cloudwatchevents/latest/aws_sdk_cloudwatchevents/client/customize/index.html
+    #[instrument(skip_all, fields(event_bus_name = %self.event_bus_name))]
+    async fn send(&self, req: CheckRequest) -> Result<(), SendError> {
+        info!("sending event to AWS Event Bus");
+        let detail = serde_json::to_string(&req)
+            .with_context(|| "serializing CheckRequest failed")
+            .map_err(SendError::Permanent)?;
+        let input = PutEventsRequestEntry::builder()
+            .set_event_bus_name(Some(self.event_bus_name.clone()))
+            .set_source(Some(EVENT_SOURCE.to_owned()))
+            .set_detail(Some(detail))
+            .set_detail_type(Some(EVENT_TYPE.to_owned()))
+            .build();
+        let _permit = match &self.limit {
+            Some(l) => Some(l.acquire().await?),
+            None => None,
+        };
+        let out = self
+            .inner
+            .put_events()
+            .entries(input)
+            .send()
+            .await
+            .map_err(|e| {
+                let transient = is_transient_sdk_error(&e);
+                let e = anyhow::Error::new(e).context("sending event to AWS Event Bus failed");
+                SendError::transient_if(transient, e)
+            })?;
+        if out.failed_entry_count > 0 {
+            let codes = out
+                .entries()
+                .iter()
+                .filter_map(|e| e.error_code())
+                .collect::<Vec<_>>();
+            let transient = codes.iter().all(|c| TRANSIENT_ERROR_CODES.contains(c));
+            let e = anyhow!(
+                "event sent to AWS Event Bus but failed: failed_count={}, error_codes={}",
+                out.failed_entry_count,
+                codes.join(",")
+            );
+            return Err(SendError::transient_if(transient, e));
+        }
+        out.entries.into_iter().flatten().for_each(|e| {
+            info!(
+                "event sent to AWS Event Bus: id={}",
+                e.event_id.unwrap_or_default()
+            );
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrency_limit() {
+        let limit = ConcurrencyLimit::new(1, Duration::from_millis(10));
+        let permit = limit.acquire().await.unwrap();
+        assert!(matches!(
+            limit.acquire().await,
+            Err(SendError::Transient(_))
+        ));
+        drop(permit);
+        assert_eq!(limit.acquire().await.unwrap().num_permits(), 1);
+    }
+}
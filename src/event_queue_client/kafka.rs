@@ -0,0 +1,134 @@
+use std::{fmt, time::Duration};
+
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use clap::Args;
+use rdkafka::{
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+    ClientConfig,
+};
+use tracing::{info, instrument};
+
+use super::{EventQueueClient, SendError};
+use crate::events::CheckRequest;
+
+// Prefixed with `kafka_` to avoid conflict with other event queue configs.
+#[derive(Debug, Clone, Args)]
+#[group()]
+pub struct KafkaConfig {
+    /// Comma separated list of Kafka bootstrap brokers, e.g. `broker1:9092,broker2:9092`.
+    #[arg(env, long, default_value = "127.0.0.1:9092")]
+    pub kafka_brokers: String,
+    /// The name of the Kafka topic to send events.
+    #[arg(env, long, default_value = "orgu.check_request")]
+    pub kafka_topic: String,
+    /// Use the repository full name as the message key, so events of the same repository are sent to the same
+    /// partition and consumed in order.
+    #[arg(env, long, default_value = "false")]
+    pub kafka_partition_by_repo: bool,
+    /// Timeout of delivering a message including retries by the producer.
+    #[arg(env, long, default_value = "10s")]
+    pub kafka_message_timeout: humantime::Duration,
+}
+
+pub struct KafkaClient {
+    inner: FutureProducer,
+    topic: String,
+    partition_by_repo: bool,
+}
+
+// FutureProducer doesn't implement Debug.
+impl fmt::Debug for KafkaClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaClient")
+            .field("topic", &self.topic)
+            .field("partition_by_repo", &self.partition_by_repo)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KafkaClient {
+    pub fn new(config: KafkaConfig) -> Result<Self> {
+        let timeout: Duration = config.kafka_message_timeout.into();
+        let inner = ClientConfig::new()
+            .set("bootstrap.servers", &config.kafka_brokers)
+            .set("message.timeout.ms", timeout.as_millis().to_string())
+            .create()
+            .with_context(|| {
+                format!(
+                    "creating Kafka producer failed: brokers={}",
+                    config.kafka_brokers
+                )
+            })?;
+        Ok(Self {
+            inner,
+            topic: config.kafka_topic,
+            partition_by_repo: config.kafka_partition_by_repo,
+        })
+    }
+}
+
+// Errors which the producer or broker may recover from, e.g. broker restarts or leader elections.
+const TRANSIENT_ERROR_CODES: &[RDKafkaErrorCode] = &[
+    RDKafkaErrorCode::MessageTimedOut,
+    RDKafkaErrorCode::QueueFull,
+    RDKafkaErrorCode::BrokerTransportFailure,
+    RDKafkaErrorCode::AllBrokersDown,
+    RDKafkaErrorCode::RequestTimedOut,
+    RDKafkaErrorCode::NetworkException,
+    RDKafkaErrorCode::LeaderNotAvailable,
+    RDKafkaErrorCode::NotLeaderForPartition,
+    RDKafkaErrorCode::NotEnoughReplicas,
+    RDKafkaErrorCode::NotEnoughReplicasAfterAppend,
+    RDKafkaErrorCode::BrokerNotAvailable,
+];
+
+fn is_transient_kafka_error(e: &KafkaError) -> bool {
+    e.rdkafka_error_code()
+        .is_some_and(|c| TRANSIENT_ERROR_CODES.contains(&c))
+}
+
+#[async_trait]
+impl EventQueueClient for KafkaClient {
+    #[instrument(skip_all, fields(topic = %self.topic))]
+    async fn send(&self, req: CheckRequest) -> Result<(), SendError> {
+        info!("sending event to Kafka");
+        let payload = serde_json::to_string(&req)
+            .with_context(|| "serializing CheckRequest failed")
+            .map_err(SendError::Permanent)?;
+        let mut record = FutureRecord::to(&self.topic).payload(&payload);
+        if self.partition_by_repo {
+            record = record.key(&req.repository.full_name);
+        }
+        // Delivery timeout is bounded by `message.timeout.ms`, so don't add another timeout on enqueueing.
+        let (partition, offset) =
+            self.inner
+                .send(record, Timeout::Never)
+                .await
+                .map_err(|(e, _)| {
+                    let transient = is_transient_kafka_error(&e);
+                    let e = anyhow::Error::new(e).context("sending event to Kafka failed");
+                    SendError::transient_if(transient, e)
+                })?;
+        info!("event sent to Kafka: partition={partition}, offset={offset}");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_kafka_error() {
+        assert!(is_transient_kafka_error(&KafkaError::MessageProduction(
+            RDKafkaErrorCode::QueueFull
+        )));
+        assert!(!is_transient_kafka_error(&KafkaError::MessageProduction(
+            RDKafkaErrorCode::MessageSizeTooLarge
+        )));
+    }
+}
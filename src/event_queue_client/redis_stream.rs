@@ -0,0 +1,118 @@
+use std::fmt;
+
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use clap::Args;
+use redis::{
+    aio::ConnectionManager, streams::StreamMaxlen, AsyncCommands as _, Client, ErrorKind,
+    RedisError,
+};
+use tracing::{info, instrument};
+
+use super::{EventQueueClient, SendError};
+use crate::events::CheckRequest;
+
+/// Field name of the stream entry holding the JSON serialized `CheckRequest`.
+pub const REDIS_STREAM_FIELD: &str = "check_request";
+
+// Prefixed with `redis_` to avoid conflict with other event queue configs.
+#[derive(Debug, Clone, Args)]
+#[group()]
+pub struct RedisStreamConfig {
+    /// URL of the Redis server, e.g. `redis://127.0.0.1:6379`.
+    #[arg(env, long, default_value = "redis://127.0.0.1:6379")]
+    pub redis_url: String,
+    /// The name of the Redis stream to send events.
+    #[arg(env, long, default_value = "orgu:check_request")]
+    pub redis_stream: String,
+}
+
+#[derive(Clone)]
+pub struct RedisStreamClient {
+    inner: ConnectionManager,
+    stream: String,
+    max_len: Option<usize>,
+}
+
+// ConnectionManager doesn't implement Debug.
+impl fmt::Debug for RedisStreamClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStreamClient")
+            .field("stream", &self.stream)
+            .field("max_len", &self.max_len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisStreamClient {
+    pub async fn new(config: RedisStreamConfig, max_len: Option<usize>) -> Result<Self> {
+        let inner = connect(&config.redis_url).await?;
+        Ok(Self {
+            inner,
+            stream: config.redis_stream,
+            max_len,
+        })
+    }
+}
+
+pub async fn connect(url: &str) -> Result<ConnectionManager> {
+    let client = Client::open(url).with_context(|| "invalid Redis URL")?;
+    client
+        .get_connection_manager()
+        .await
+        .with_context(|| "connecting to Redis failed")
+}
+
+// Connection failures and server side temporary states such as loading data or failover.
+pub fn is_transient_redis_error(e: &RedisError) -> bool {
+    e.is_io_error()
+        || e.is_timeout()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || matches!(
+            e.kind(),
+            ErrorKind::BusyLoadingError | ErrorKind::TryAgain | ErrorKind::MasterDown
+        )
+}
+
+#[async_trait]
+impl EventQueueClient for RedisStreamClient {
+    #[instrument(skip_all, fields(stream = %self.stream))]
+    async fn send(&self, req: CheckRequest) -> Result<(), SendError> {
+        info!("sending event to Redis stream");
+        let payload = serde_json::to_string(&req)
+            .with_context(|| "serializing CheckRequest failed")
+            .map_err(SendError::Permanent)?;
+        let items = [(REDIS_STREAM_FIELD, payload)];
+        // ConnectionManager is cheap to clone and reconnects on failures.
+        let mut con = self.inner.clone();
+        let res: Result<String, _> = match self.max_len {
+            Some(n) => {
+                con.xadd_maxlen(&self.stream, StreamMaxlen::Approx(n), "*", &items)
+                    .await
+            }
+            None => con.xadd(&self.stream, "*", &items).await,
+        };
+        let id = res.map_err(|e| {
+            let transient = is_transient_redis_error(&e);
+            let e = anyhow::Error::new(e).context("sending event to Redis stream failed");
+            SendError::transient_if(transient, e)
+        })?;
+        info!("event sent to Redis stream: id={id}");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_redis_error() {
+        let e = RedisError::from((ErrorKind::BusyLoadingError, "loading"));
+        assert!(is_transient_redis_error(&e));
+        let e = RedisError::from((ErrorKind::TypeError, "wrong type"));
+        assert!(!is_transient_redis_error(&e));
+    }
+}
@@ -1,9 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use serde::{Deserialize, Serialize};
 
+/// Request sent from front to runners via the event queue.
+///
+/// Front and runners are deployed independently, so events in flight during a rolling deploy can be produced by an
+/// older or newer version than the consumer. To keep them compatible in both directions:
+///
+/// - New fields must be deserializable when missing, i.e. `Option` or `#[serde(default)]`.
+/// - Unknown fields are ignored, never add `#[serde(deny_unknown_fields)]`.
+/// - Don't rename, remove nor change the type of existing fields.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CheckRequest {
+    /// Schema version of the request, see `SCHEMA_VERSION`. 0 for requests sent before versioning.
+    #[serde(default)]
+    pub schema_version: u32,
     // Request id is unique for each event including re-delivery.
     pub request_id: String,
     // Delivery id has same value for re-delivery.
@@ -27,8 +38,25 @@ pub struct CheckRequest {
     /// Pull request number if the event is associated with a pull request. check_suite events can be associated with
     /// multiple PRs and if so, this will be the first PR number.
     pub pull_request_number: Option<u64>,
+    /// Branch name of the pull request head, e.g. `feature/x`. Only set for pull_request and issue_comment events. The
+    /// branch can be of a fork repository.
+    #[serde(default)]
+    pub pull_request_head_ref: Option<String>,
     /// User who triggered the event.
     pub sender: User,
+    /// Check run re-requested by the user. Only set for check_run events, to re-run the specific check run.
+    #[serde(default)]
+    pub check_run: Option<RerequestedCheckRun>,
+    /// ID of the comment which triggered the event by a comment command. Only set for issue_comment events.
+    #[serde(default)]
+    pub comment_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RerequestedCheckRun {
+    pub id: i64,
+    /// Name of the check run, e.g. "run-lint".
+    pub name: String,
 }
 
 // Add prefix to avoid conflict with actual Git repository.
@@ -40,7 +68,44 @@ pub struct GithubRepository {
     pub name: String,
     pub private: bool,
     pub owner: User,
-    pub custom_properties: HashMap<String, String>,
+    pub custom_properties: HashMap<String, CustomPropValue>,
+    /// Archived repositories are read-only, checks are skipped.
+    #[serde(default)]
+    pub archived: bool,
+    /// Disabled repositories can't be accessed, checks are skipped.
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+impl GithubRepository {
+    /// Archived or disabled repositories are not checked.
+    pub const fn is_inactive(&self) -> bool {
+        self.archived || self.disabled
+    }
+}
+
+/// Value of a GitHub custom property. Multi-select properties are given as a list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CustomPropValue {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl From<String> for CustomPropValue {
+    fn from(v: String) -> Self {
+        Self::Single(v)
+    }
+}
+
+// List values are joined with comma, e.g. `a,b`.
+impl fmt::Display for CustomPropValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Single(v) => f.write_str(v),
+            Self::Multiple(vs) => f.write_str(&vs.join(",")),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -48,3 +113,68 @@ pub struct User {
     /// Name of the user or organization e.g. "octocat".
     pub login: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    // CheckRequest serialized by the first release, without fields added later.
+    const MINIMAL: &str = r#"{
+        "request_id": "req",
+        "delivery_id": "delivery",
+        "event_name": "pull_request",
+        "action": "synchronize",
+        "repository": {
+            "full_name": "owner/repo",
+            "name": "repo",
+            "private": true,
+            "owner": {"login": "owner"},
+            "custom_properties": {"team": "infra"}
+        },
+        "head_sha": "head",
+        "base_sha": "base",
+        "base_ref": "main",
+        "before": null,
+        "after": "head",
+        "pull_request_number": 1,
+        "sender": {"login": "octocat"}
+    }"#;
+
+    #[test]
+    fn deserialize_older() {
+        let req: CheckRequest = serde_json::from_str(MINIMAL).unwrap();
+        assert_eq!(req.request_id, "req");
+        assert_eq!(req.schema_version, 0);
+        assert_eq!(
+            req.repository.custom_properties.get("team"),
+            Some(&CustomPropValue::Single("infra".to_owned()))
+        );
+        assert_eq!(req.pull_request_head_ref, None);
+        assert_eq!(req.check_run, None);
+        assert!(!req.repository.is_inactive());
+    }
+
+    #[test]
+    fn deserialize_newer() {
+        let newer = MINIMAL
+            .replacen(
+                '{',
+                r#"{"pull_request_title": "Add a field not known yet","#,
+                1,
+            )
+            .replace(
+                r#""repository": {"#,
+                r#""repository": {"visibility": "internal","#,
+            );
+        let req: CheckRequest = serde_json::from_str(&newer).unwrap();
+        assert_eq!(req.request_id, "req");
+        assert_eq!(req.repository.full_name, "owner/repo");
+
+        // Round trip of the current version.
+        let json = serde_json::to_string(&req).unwrap();
+        let req: CheckRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(req.head_sha, "head");
+    }
+}
@@ -1,6 +1,7 @@
 pub mod cli;
+pub mod config;
+pub mod github_events;
 
-mod config;
-mod github_events;
 mod handlers;
+mod ip_filter;
 mod routes;
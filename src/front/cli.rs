@@ -1,3 +1,5 @@
+mod inspect;
+#[cfg(feature = "aws")]
 mod lambda;
 mod server;
 
@@ -10,12 +12,17 @@ pub enum FrontCommands {
     /// Run front server. This will connect to relay or another local runner server.
     Server(server::ServerArgs),
     /// Run front server in AWS Lambda function.
+    #[cfg(feature = "aws")]
     Lambda(lambda::LambdaArgs),
+    /// Check how the webhook handler treats the given payload offline, and print the resulting check request.
+    Inspect(inspect::InspectArgs),
 }
 
 pub async fn run(global: GlobalArgs, c: FrontCommands) -> CommandResult {
     match c {
         FrontCommands::Server(args) => server::server(global, args).await,
+        #[cfg(feature = "aws")]
         FrontCommands::Lambda(args) => lambda::lambda(global, args).await,
+        FrontCommands::Inspect(args) => inspect::inspect(global, args).await,
     }
 }
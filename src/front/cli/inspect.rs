@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use clap::Args;
+use serde_json::to_string_pretty;
+use tokio::fs::read_to_string;
+
+use crate::{
+    cli::{CommandResult, GlobalArgs, SUCCESS},
+    front::{
+        config::{parse_supported_event, SupportedEvent},
+        handlers::{decide, is_rerequest, Decision},
+    },
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct InspectArgs {
+    /// Path to the webhook payload, e.g. copied from "Recent Deliveries" of the GitHub App settings.
+    #[arg(long, short)]
+    file: PathBuf,
+    /// Event name given as `X-GitHub-Event` header, e.g. `pull_request`.
+    #[arg(long, short)]
+    event: String,
+    /// Same as `front server --supported-event`.
+    #[arg(long = "supported-event", value_parser = parse_supported_event)]
+    supported_events: Vec<SupportedEvent>,
+    /// Same as `front server --comment-command`.
+    #[arg(long)]
+    comment_command: Option<String>,
+}
+
+/// Run the payload through the same filtering as the webhook handler, without signature verification nor sending.
+pub async fn inspect(_global: GlobalArgs, args: InspectArgs) -> CommandResult {
+    let body = read_to_string(&args.file)
+        .await
+        .with_context(|| format!("failed to read payload: {}", args.file.display()))?;
+    let decision = decide(
+        &args.event,
+        &body,
+        &args.supported_events,
+        args.comment_command.as_deref(),
+    )?;
+
+    let event = &args.event;
+    match &decision {
+        Decision::UnsupportedEvent => println!("event: {event} (unsupported)"),
+        Decision::Ping => println!("event: {event} (supported, responded with pong)"),
+        Decision::UnsupportedAction(_)
+        | Decision::PublicRepository(_)
+        | Decision::InactiveRepository(_)
+        | Decision::IgnoredComment(_)
+        | Decision::Publish { .. }
+        | Decision::Comment { .. } => {
+            println!("event: {event} (supported)");
+        }
+    }
+    if let Some(common) = decision.common() {
+        if matches!(decision, Decision::UnsupportedAction(_)) {
+            println!("action: {} (unsupported)", common.action);
+        } else {
+            println!("action: {} (supported)", common.action);
+            let repo = &common.repository;
+            let visibility = if !repo.private {
+                "public, skipped"
+            } else if repo.is_inactive() {
+                "private, archived or disabled, skipped"
+            } else {
+                "private"
+            };
+            println!("repository: {} ({visibility})", repo.full_name);
+        }
+    }
+
+    let (common, event) = match decision {
+        Decision::Publish { common, event } => (common, event),
+        Decision::Comment { .. } => {
+            // The permission and the head of the pull request are resolved via API, which isn't called offline.
+            println!("would publish: yes if the commenter has enough permission (comment command)");
+            return SUCCESS;
+        }
+        Decision::UnsupportedEvent
+        | Decision::Ping
+        | Decision::UnsupportedAction(_)
+        | Decision::PublicRepository(_)
+        | Decision::InactiveRepository(_)
+        | Decision::IgnoredComment(_) => {
+            println!("would publish: no");
+            return SUCCESS;
+        }
+    };
+    println!("would publish: yes");
+    println!("rerequested: {}", is_rerequest(&args.event, &common.action));
+    let req = event.into_check_request("inspect".to_owned(), "inspect".to_owned());
+    println!("check request:\n{}", to_string_pretty(&req)?);
+    SUCCESS
+}
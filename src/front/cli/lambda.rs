@@ -24,10 +24,11 @@ pub struct LambdaArgs {
 }
 
 #[allow(clippy::no_effect_underscore_binding)]
-pub async fn lambda(global: GlobalArgs, args: LambdaArgs) -> CommandResult {
-    init_fmt_with_json(&global.verbose);
+pub async fn lambda(global: GlobalArgs, mut args: LambdaArgs) -> CommandResult {
+    let _guard = init_fmt_with_json(&global)?;
 
     let github_client = OctorustClient::new(args.github_config, args.github_app_config)?;
+    args.config.resolve_github_ips(&github_client).await?;
     let app = build_app(
         args.config,
         AwsEventBusClient::new(args.event_bus_config).await,
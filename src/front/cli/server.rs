@@ -1,18 +1,27 @@
+use std::net::SocketAddr;
+
 use axum::{body::Body, serve, Router, ServiceExt};
+use axum_server::{from_tcp_rustls, tls_rustls::RustlsConfig};
 use clap::Args;
 use http::Request;
 use tokio::net::TcpListener;
 use tower_http::normalize_path::NormalizePath;
 use url::Url;
 
+#[cfg(feature = "aws")]
+use crate::event_queue_client::{AwsEventBusClient, AwsEventBusConfig};
+#[cfg(feature = "kafka")]
+use crate::event_queue_client::{KafkaClient, KafkaConfig};
+#[cfg(feature = "redis")]
+use crate::event_queue_client::{RedisStreamClient, RedisStreamConfig};
 use crate::{
     cli::{CommandResult, GlobalArgs, SUCCESS},
-    event_queue_client::{
-        AwsEventBusClient, AwsEventBusConfig, EventQueueRelayClient, EventQueueRelayConfig,
-    },
+    event_queue_client::{EventQueueRelayClient, EventQueueRelayConfig},
     front::{config::FrontConfig, routes::build_app},
     github_client::OctorustClient,
     github_config::{GithubApiConfig, GithubAppConfig},
+    relay_signature::RelaySignatureConfig,
+    tls::TlsConfig,
     trace::init_fmt_with_pretty,
 };
 
@@ -24,8 +33,25 @@ pub struct ServerArgs {
     github_config: GithubApiConfig,
     #[command(flatten)]
     config: FrontConfig,
+    /// Endpoint of the event queue relay server. Use `unix:///path/to/socket` to send events to the runner server
+    /// listening on Unix domain socket.
     #[arg(long, default_value = "http://127.0.0.1:3001/run")]
     event_queue_relay_endpoint: String,
+    /// Connect timeout for the event queue relay server.
+    #[arg(long, env, default_value = "1s")]
+    event_queue_relay_connect_timeout: humantime::Duration,
+    /// Timeout between reads of the relay server response. Disabled if none.
+    #[arg(long, env)]
+    event_queue_relay_read_timeout: Option<humantime::Duration>,
+    /// Timeout for the whole relay request. The runner server responds after the job, so set this longer than jobs
+    /// when relaying to it directly. Timed out events are responded with an error to GitHub. Disabled if none.
+    #[arg(long, env)]
+    event_queue_relay_timeout: Option<humantime::Duration>,
+    /// Compress events sent to the event queue relay server with gzip. The runner server accepts them.
+    #[arg(long, env, default_value = "false")]
+    event_queue_relay_compress: bool,
+    #[command(flatten)]
+    relay_signature_config: RelaySignatureConfig,
     /// The address to listen on.
     #[arg(long, default_value = "127.0.0.1")]
     address: String,
@@ -33,38 +59,105 @@ pub struct ServerArgs {
     #[arg(long, default_value = "3000")]
     port: u16,
     /// Switch to use AWS EventBus as event bus.
+    #[cfg(feature = "aws")]
     #[arg(long, env, default_value = "false")]
     use_aws_event_bus: bool,
+    #[cfg(feature = "aws")]
     #[command(flatten)]
     event_bus_config: AwsEventBusConfig,
+    /// Switch to use Kafka as event queue.
+    #[cfg(feature = "kafka")]
+    #[arg(long, env, default_value = "false")]
+    use_kafka: bool,
+    #[cfg(feature = "kafka")]
+    #[command(flatten)]
+    kafka_config: KafkaConfig,
+    /// Switch to use Redis stream as event queue.
+    #[cfg(feature = "redis")]
+    #[arg(long, env, default_value = "false")]
+    use_redis_stream: bool,
+    #[cfg(feature = "redis")]
+    #[command(flatten)]
+    redis_config: RedisStreamConfig,
+    /// Approximate maximum length of the Redis stream. Older entries are trimmed on sending.
+    #[cfg(feature = "redis")]
+    #[arg(long, env)]
+    redis_stream_max_len: Option<usize>,
+    #[command(flatten)]
+    tls_config: TlsConfig,
 }
 
-pub async fn server(global: GlobalArgs, args: ServerArgs) -> CommandResult {
-    init_fmt_with_pretty(&global.verbose);
+pub async fn server(global: GlobalArgs, mut args: ServerArgs) -> CommandResult {
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let tls = args.tls_config.load().await?;
 
     let github_client = OctorustClient::new(args.github_config, args.github_app_config)?;
+    args.config.resolve_github_ips(&github_client).await?;
 
-    let app = if args.use_aws_event_bus {
-        build_app(
+    #[cfg(feature = "aws")]
+    if args.use_aws_event_bus {
+        let app = build_app(
             args.config,
             AwsEventBusClient::new(args.event_bus_config).await,
             github_client,
-        )
-    } else {
-        let config = EventQueueRelayConfig {
-            endpoint: Url::parse(&args.event_queue_relay_endpoint)?,
-        };
-        build_app(
+        );
+        return serve_app(app, args.address, args.port, tls).await;
+    }
+    #[cfg(feature = "kafka")]
+    if args.use_kafka {
+        let app = build_app(
             args.config,
-            EventQueueRelayClient::new(config),
+            KafkaClient::new(args.kafka_config)?,
             github_client,
-        )
+        );
+        return serve_app(app, args.address, args.port, tls).await;
+    }
+    #[cfg(feature = "redis")]
+    if args.use_redis_stream {
+        let client = RedisStreamClient::new(args.redis_config, args.redis_stream_max_len).await?;
+        let app = build_app(args.config, client, github_client);
+        return serve_app(app, args.address, args.port, tls).await;
+    }
+
+    let config = EventQueueRelayConfig {
+        endpoint: Url::parse(&args.event_queue_relay_endpoint)?,
+        connect_timeout: args.event_queue_relay_connect_timeout.into(),
+        read_timeout: args.event_queue_relay_read_timeout.map(Into::into),
+        request_timeout: args.event_queue_relay_timeout.map(Into::into),
+        compress_request: args.event_queue_relay_compress,
+        signature: args.relay_signature_config.build(),
     };
-    let app = <NormalizePath<Router> as ServiceExt<Request<Body>>>::into_make_service(app);
+    let app = build_app(
+        args.config,
+        EventQueueRelayClient::new(config)?,
+        github_client,
+    );
+    serve_app(app, args.address, args.port, tls).await
+}
+
+async fn serve_app(
+    app: NormalizePath<Router>,
+    address: String,
+    port: u16,
+    tls: Option<RustlsConfig>,
+) -> CommandResult {
+    // Connect info is used to filter webhook requests by source IP.
+    let app =
+        <NormalizePath<Router> as ServiceExt<Request<Body>>>::into_make_service_with_connect_info::<
+            SocketAddr,
+        >(app);
 
-    let listener = TcpListener::bind([args.address, args.port.to_string()].join(":")).await?;
+    let listener = TcpListener::bind([address, port.to_string()].join(":")).await?;
     println!("listening on {}", listener.local_addr()?);
-    serve(listener, app).await?;
+    match tls {
+        Some(tls) => {
+            from_tcp_rustls(listener.into_std()?, tls)
+                .serve(app)
+                .await?
+        }
+        None => serve(listener, app).await?,
+    }
 
     SUCCESS
 }
@@ -1,22 +1,237 @@
 use std::time::Duration;
 
+use anyhow::{bail, Context as _, Result};
 use clap::Args;
+use ipnet::IpNet;
+use serde::Serialize;
+use tracing::info;
 
-#[derive(Debug, Args, Clone)]
+use crate::{
+    config_serde::{display, display_opt, redact},
+    github_client::{OctorustClient, Permission},
+};
+
+#[derive(Debug, Args, Clone, Serialize)]
 pub struct FrontConfig {
     /// GitHub webhook secret to verify incoming webhook requests.
     #[arg(env = "GITHUB_WEBHOOK_SECRET", hide_env_values = true, long)]
+    #[serde(serialize_with = "redact")]
     pub webhook_secret: String,
+    /// Interval to re-fetch the webhook secret when it's given as `ssm://` value. Disabled if none.
+    #[arg(env, long)]
+    #[serde(serialize_with = "display_opt")]
+    pub webhook_secret_refresh_interval: Option<humantime::Duration>,
     /// Timeout for server to process each request.
     #[arg(env, long, default_value = "15m")]
+    #[serde(serialize_with = "display")]
     pub server_timeout: humantime::Duration,
+    /// Source IP ranges allowed to call the webhook endpoint, e.g. `192.30.252.0/22`. All IPs are allowed if empty.
+    #[arg(env, long, value_delimiter = ',')]
+    pub allowed_webhook_cidrs: Vec<IpNet>,
+    /// Fetch GitHub webhook IP ranges from GitHub meta API at startup and add them to allowed webhook CIDRs.
+    #[arg(env, long, default_value = "false")]
+    pub auto_fetch_github_ips: bool,
+    /// Proxy IP ranges trusted to set X-Forwarded-For header. Used to determine the source IP of webhook requests.
+    /// On Lambda behind ALB, the right-most X-Forwarded-For entry appended by ALB is taken as the peer address.
+    #[arg(env, long, value_delimiter = ',')]
+    pub trusted_proxy_cidrs: Vec<IpNet>,
+    /// Reuse the existing check run for the same delivery instead of creating a new one on redelivery.
+    #[arg(env, long, default_value = "false")]
+    pub reuse_check_runs: bool,
+    /// Create the `orgu-trigger` check run also for rerequested check_suite and check_run events. By default it's
+    /// skipped because the user already sees the re-run check suite.
+    #[arg(env, long, default_value = "false")]
+    pub report_trigger_on_rerequest: bool,
+    /// Path of the webhook endpoint.
+    #[arg(env, long, default_value = DEFAULT_WEBHOOK_PATH, value_parser = parse_route_path)]
+    pub webhook_path: String,
+    /// Path of the health check endpoint.
+    #[arg(env, long, default_value = DEFAULT_HEALTH_CHECK_PATH, value_parser = parse_route_path)]
+    pub health_check_path: String,
+    /// Include the version, git SHA and uptime in the health check response, and serve the build metadata at
+    /// `/version` to verify deployments. Disabled by default not to expose build info publicly.
+    #[arg(env, long, default_value = "false")]
+    pub health_check_build_info: bool,
+    /// Label of this deployment, e.g. `staging`. Suffixed to the check run name and shown in the summary to avoid
+    /// conflicts with other deployments watching the same repository. Not suffixed if empty.
+    #[arg(env, long, default_value = "")]
+    pub environment: String,
+    /// Set `Retry-After` header with this duration in seconds to 503 responses, e.g. when the event queue is
+    /// throttled, to hint GitHub and the relay to back off. Not set if none.
+    #[arg(env, long)]
+    #[serde(serialize_with = "display_opt")]
+    pub retry_after: Option<humantime::Duration>,
+    /// Fraction of successful requests to log at info level, from 0.0 to 1.0. Error responses are always logged.
+    /// Sampled by the request id, so all logs of a request are kept or dropped together.
+    #[arg(env, long, default_value = "1.0", value_parser = parse_sample_rate)]
+    pub log_sample_rate: f64,
+    /// Override actions handled for an event as `event=action1,action2`, e.g. `pull_request=opened,labeled`. Only
+    /// events handled by default can be given. Can be passed multiple times.
+    #[arg(env, long = "supported-event", value_parser = parse_supported_event)]
+    pub supported_events: Vec<SupportedEvent>,
+    /// Re-run checks of a pull request when a comment on it has a line of this command, e.g. `/orgu rerun`. Requires
+    /// the GitHub App to subscribe to issue_comment events with read access to pull requests. Disabled if none.
+    #[arg(env, long)]
+    pub comment_command: Option<String>,
+    /// Minimum repository permission of the commenter to accept `--comment-command`. Comments from other users are
+    /// ignored.
+    #[arg(env, long, default_value = "write")]
+    pub comment_command_permission: Permission,
+}
+
+/// Events and actions handled by default. Every event except `ping` has a parser in `GithubEvent`.
+const DEFAULT_SUPPORTED_EVENTS: &[(&str, &[&str])] = &[
+    ("ping", &[]),
+    ("check_suite", &["requested", "rerequested"]),
+    (
+        "pull_request",
+        &["opened", "synchronize", "reopened", "ready_for_review"],
+    ),
+    ("merge_group", &["checks_requested"]),
+    ("check_run", &["rerequested"]),
+    // Handled only if `--comment-command` is given. Parsed as `IssueCommentEvent`.
+    ("issue_comment", &["created"]),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportedEvent {
+    event: String,
+    actions: Vec<String>,
+}
+
+pub fn parse_supported_event(s: &str) -> Result<SupportedEvent> {
+    let (event, actions) = s
+        .split_once('=')
+        .with_context(|| format!("invalid supported event: no `=` found in `{s}`"))?;
+    let parsable = event != "ping" && DEFAULT_SUPPORTED_EVENTS.iter().any(|(e, _)| *e == event);
+    if !parsable {
+        bail!("unsupported event, no parser for the payload: `{event}`");
+    }
+    let actions = actions
+        .split(',')
+        .filter(|a| !a.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    if actions.is_empty() {
+        bail!("invalid supported event: actions are required: `{s}`");
+    }
+    Ok(SupportedEvent {
+        event: event.to_owned(),
+        actions,
+    })
+}
+
+/// Actions handled for the event. Configured ones take precedence over the defaults. None if the event isn't handled.
+pub fn supported_actions<'cfg>(
+    overrides: &'cfg [SupportedEvent],
+    event: &str,
+) -> Option<Vec<&'cfg str>> {
+    if let Some(o) = overrides.iter().rev().find(|o| o.event == event) {
+        return Some(o.actions.iter().map(String::as_str).collect());
+    }
+    DEFAULT_SUPPORTED_EVENTS
+        .iter()
+        .find(|(e, _)| *e == event)
+        .map(|(_, actions)| actions.to_vec())
+}
+
+const DEFAULT_WEBHOOK_PATH: &str = "/github/events";
+const DEFAULT_HEALTH_CHECK_PATH: &str = "/hc";
+
+fn parse_sample_rate(s: &str) -> Result<f64> {
+    let rate = s
+        .parse::<f64>()
+        .with_context(|| format!("invalid sample rate: {s}"))?;
+    if !(0.0..=1.0).contains(&rate) {
+        bail!("sample rate must be between 0.0 and 1.0: {s}");
+    }
+    Ok(rate)
+}
+
+// Trailing slashes are trimmed from requests by the normalize path layer, so trim them from the routes as well.
+fn parse_route_path(s: &str) -> Result<String> {
+    if !s.starts_with('/') {
+        bail!("path must start with `/`: {s}");
+    }
+    let trimmed = s.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Ok("/".to_owned());
+    }
+    Ok(trimmed.to_owned())
 }
 
 impl Default for FrontConfig {
     fn default() -> Self {
         Self {
             webhook_secret: Default::default(),
+            webhook_secret_refresh_interval: None,
             server_timeout: Duration::from_secs(60 * 15).into(),
+            allowed_webhook_cidrs: Default::default(),
+            auto_fetch_github_ips: false,
+            trusted_proxy_cidrs: Default::default(),
+            reuse_check_runs: false,
+            report_trigger_on_rerequest: false,
+            webhook_path: DEFAULT_WEBHOOK_PATH.to_owned(),
+            health_check_path: DEFAULT_HEALTH_CHECK_PATH.to_owned(),
+            health_check_build_info: false,
+            environment: String::new(),
+            retry_after: None,
+            log_sample_rate: 1.0,
+            supported_events: Vec::new(),
+            comment_command: None,
+            comment_command_permission: Permission::Write,
+        }
+    }
+}
+
+impl FrontConfig {
+    /// Add GitHub webhook IP ranges to allowed CIDRs if `auto_fetch_github_ips` is enabled.
+    pub async fn resolve_github_ips(&mut self, client: &OctorustClient) -> Result<()> {
+        if !self.auto_fetch_github_ips {
+            return Ok(());
         }
+        let cidrs = client.fetch_hook_cidrs().await?;
+        info!("fetched GitHub webhook IP ranges: count={}", cidrs.len());
+        self.allowed_webhook_cidrs.extend(cidrs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_path() {
+        assert_eq!(parse_route_path("/hooks/github/").unwrap(), "/hooks/github");
+        assert_eq!(parse_route_path("/").unwrap(), "/");
+        parse_route_path("hooks").unwrap_err();
+    }
+
+    #[test]
+    fn sample_rate() {
+        assert!((parse_sample_rate("0.25").unwrap() - 0.25).abs() < f64::EPSILON);
+        parse_sample_rate("1.5").unwrap_err();
+        parse_sample_rate("-0.1").unwrap_err();
+        parse_sample_rate("NaN").unwrap_err();
+    }
+
+    #[test]
+    fn supported_events() {
+        let overrides = vec![parse_supported_event("pull_request=opened,labeled").unwrap()];
+        assert_eq!(
+            supported_actions(&overrides, "pull_request"),
+            Some(vec!["opened", "labeled"])
+        );
+        assert_eq!(
+            supported_actions(&overrides, "merge_group"),
+            Some(vec!["checks_requested"])
+        );
+        assert_eq!(supported_actions(&overrides, "issues"), None);
+
+        parse_supported_event("issues=opened").unwrap_err();
+        parse_supported_event("ping=x").unwrap_err();
+        parse_supported_event("pull_request=").unwrap_err();
+        parse_supported_event("pull_request").unwrap_err();
     }
 }
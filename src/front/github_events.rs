@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-use crate::events::{CheckRequest, GithubRepository, User};
+use crate::{
+    event_queue_client::SCHEMA_VERSION,
+    events::{CheckRequest, GithubRepository, RerequestedCheckRun, User},
+    github_client::PullRequestRefs,
+};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WebhookCommonFields {
@@ -15,6 +19,8 @@ pub enum GithubEvent {
     // https://rust-lang.github.io/rust-clippy/master/index.html#/large_enum_variant
     CheckSuite(Box<CheckSuiteEvent>),
     PullRequest(Box<PullRequestEvent>),
+    MergeGroup(Box<MergeGroupEvent>),
+    CheckRun(Box<CheckRunEvent>),
 }
 
 impl GithubEvent {
@@ -22,6 +28,8 @@ impl GithubEvent {
         match self {
             Self::CheckSuite(e) => e.into_check_request(req_id, delivery_id),
             Self::PullRequest(e) => e.into_check_request(req_id, delivery_id),
+            Self::MergeGroup(e) => e.into_check_request(req_id, delivery_id),
+            Self::CheckRun(e) => e.into_check_request(req_id, delivery_id),
         }
     }
 
@@ -29,6 +37,8 @@ impl GithubEvent {
         match self {
             Self::CheckSuite(e) => &e.check_suite.head_sha,
             Self::PullRequest(e) => &e.pull_request.head.sha,
+            Self::MergeGroup(e) => &e.merge_group.head_sha,
+            Self::CheckRun(e) => &e.check_run.head_sha,
         }
     }
 }
@@ -41,8 +51,9 @@ pub struct CheckSuiteEvent {
 }
 
 impl CheckSuiteEvent {
-    fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
+    pub fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
         CheckRequest {
+            schema_version: SCHEMA_VERSION,
             request_id: req_id,
             delivery_id,
             event_name: "check_suite".to_owned(),
@@ -57,7 +68,10 @@ impl CheckSuiteEvent {
             // for specific PR may not be possible. This is rare case and pushing empty commit will be work-around for
             // that case.
             pull_request_number: self.check_suite.pull_requests.first().map(|pr| pr.number),
+            pull_request_head_ref: None,
             sender: self.common.sender,
+            check_run: None,
+            comment_id: None,
         }
     }
 }
@@ -97,10 +111,11 @@ impl PullRequestEvent {
             .or_else(|| Some(self.pull_request.head.sha.clone()))
     }
 
-    fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
+    pub fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
         let before = self.before();
         let after = self.after();
         CheckRequest {
+            schema_version: SCHEMA_VERSION,
             request_id: req_id,
             delivery_id,
             event_name: "pull_request".to_owned(),
@@ -112,11 +127,166 @@ impl PullRequestEvent {
             before,
             after,
             pull_request_number: Some(self.number),
+            pull_request_head_ref: Some(self.pull_request.head.ref_),
+            sender: self.common.sender,
+            check_run: None,
+            comment_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeGroupEvent {
+    #[serde(flatten)]
+    pub common: WebhookCommonFields,
+    pub merge_group: MergeGroup,
+}
+
+impl MergeGroupEvent {
+    pub fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
+        CheckRequest {
+            schema_version: SCHEMA_VERSION,
+            request_id: req_id,
+            delivery_id,
+            event_name: "merge_group".to_owned(),
+            action: self.common.action,
+            repository: self.common.repository,
+            head_sha: self.merge_group.head_sha.clone(),
+            base_sha: Some(self.merge_group.base_sha.clone()),
+            base_ref: Some(self.merge_group.base_ref),
+            before: Some(self.merge_group.base_sha),
+            after: Some(self.merge_group.head_sha),
+            // Merge group can contain multiple pull requests.
+            pull_request_number: None,
+            pull_request_head_ref: None,
             sender: self.common.sender,
+            check_run: None,
+            comment_id: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckRunEvent {
+    #[serde(flatten)]
+    pub common: WebhookCommonFields,
+    pub check_run: CheckRun,
+}
+
+impl CheckRunEvent {
+    pub fn into_check_request(self, req_id: String, delivery_id: String) -> CheckRequest {
+        let suite = self.check_run.check_suite;
+        CheckRequest {
+            schema_version: SCHEMA_VERSION,
+            request_id: req_id,
+            delivery_id,
+            event_name: "check_run".to_owned(),
+            action: self.common.action,
+            repository: self.common.repository,
+            head_sha: self.check_run.head_sha,
+            base_sha: suite.before.clone(),
+            base_ref: None,
+            before: suite.before,
+            after: suite.after,
+            // Same limitation as check_suite events for multiple PRs.
+            pull_request_number: self.check_run.pull_requests.first().map(|pr| pr.number),
+            pull_request_head_ref: None,
+            sender: self.common.sender,
+            check_run: Some(RerequestedCheckRun {
+                id: self.check_run.id,
+                name: self.check_run.name,
+            }),
+            comment_id: None,
+        }
+    }
+}
+
+// Not a variant of `GithubEvent` because the payload lacks the head of the pull request, which is resolved via API.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IssueCommentEvent {
+    #[serde(flatten)]
+    pub common: WebhookCommonFields,
+    pub issue: Issue,
+    pub comment: IssueComment,
+}
+
+impl IssueCommentEvent {
+    /// Whether any line of the comment is the command. Quoted lines don't match as they start with `>`.
+    pub fn has_command(&self, command: &str) -> bool {
+        self.comment.body.lines().any(|l| l.trim() == command)
+    }
+
+    pub const fn on_pull_request(&self) -> bool {
+        self.issue.pull_request.is_some()
+    }
+
+    pub fn into_check_request(
+        self,
+        req_id: String,
+        delivery_id: String,
+        pr: PullRequestRefs,
+    ) -> CheckRequest {
+        CheckRequest {
+            schema_version: SCHEMA_VERSION,
+            request_id: req_id,
+            delivery_id,
+            event_name: "issue_comment".to_owned(),
+            action: self.common.action,
+            repository: self.common.repository,
+            head_sha: pr.head_sha.clone(),
+            base_sha: Some(pr.base_sha.clone()),
+            base_ref: Some(pr.base_ref),
+            // Same as pull_request.opened events, check the whole pull request.
+            before: Some(pr.base_sha),
+            after: Some(pr.head_sha),
+            pull_request_number: Some(self.issue.number),
+            pull_request_head_ref: Some(pr.head_ref),
+            sender: self.common.sender,
+            check_run: None,
+            comment_id: Some(self.comment.id),
+        }
+    }
+}
+
+// https://docs.github.com/en/webhooks/webhook-events-and-payloads?actionType=created#issue_comment
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Issue {
+    pub number: u64,
+    /// Only set if the issue is a pull request.
+    pub pull_request: Option<IssuePullRequest>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IssuePullRequest {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IssueComment {
+    pub id: i64,
+    pub body: String,
+    pub user: User,
+}
+
+// https://docs.github.com/en/webhooks/webhook-events-and-payloads?actionType=rerequested#check_run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckRun {
+    pub id: i64,
+    pub name: String,
+    pub head_sha: String,
+    pub check_suite: CheckSuite,
+    pub pull_requests: Vec<CheckSuitePullRequest>,
+}
+
+// https://docs.github.com/en/webhooks/webhook-events-and-payloads?actionType=checks_requested#merge_group
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeGroup {
+    pub head_sha: String,
+    pub head_ref: String,
+    pub base_sha: String,
+    pub base_ref: String,
+}
+
 // https://docs.github.com/en/webhooks/webhook-events-and-payloads?actionType=requested#check_suite
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CheckSuite {
@@ -163,6 +333,8 @@ mod tests {
 
     use pretty_assertions::assert_eq;
 
+    use crate::events::CustomPropValue;
+
     #[test]
     fn pull_request_before_zero_value() {
         let pr = PullRequestEvent {
@@ -203,4 +375,120 @@ mod tests {
         };
         assert_eq!(pr.before(), Some("before_sha".to_owned()));
     }
+
+    #[test]
+    fn merge_group() {
+        let body = r#"{
+            "action": "checks_requested",
+            "repository": {"full_name": "owner/repo", "name": "repo", "private": true, "owner": {"login": "owner"}, "custom_properties": {}},
+            "sender": {"login": "ferris"},
+            "merge_group": {
+                "head_sha": "head_sha",
+                "head_ref": "refs/heads/gh-readonly-queue/main/pr-1-base_sha",
+                "base_sha": "base_sha",
+                "base_ref": "refs/heads/main"
+            }
+        }"#;
+        let event = serde_json::from_str::<GithubEvent>(body).unwrap();
+        assert_eq!(event.head_sha(), "head_sha");
+        let req = event.into_check_request("req".to_owned(), "delivery".to_owned());
+        assert_eq!(req.event_name, "merge_group");
+        assert_eq!(req.base_sha, Some("base_sha".to_owned()));
+        assert_eq!(req.base_ref, Some("refs/heads/main".to_owned()));
+        assert_eq!(req.pull_request_number, None);
+    }
+
+    #[test]
+    fn check_run_rerequested() {
+        let body = r#"{
+            "action": "rerequested",
+            "repository": {"full_name": "owner/repo", "name": "repo", "private": true, "owner": {"login": "owner"}, "custom_properties": {}},
+            "sender": {"login": "ferris"},
+            "check_run": {
+                "id": 42,
+                "name": "run-lint",
+                "head_sha": "head_sha",
+                "check_suite": {
+                    "id": 1, "head_sha": "head_sha", "before": "before_sha", "after": "head_sha",
+                    "pull_requests": [], "created_at": "", "updated_at": ""
+                },
+                "pull_requests": [{"id": 10, "number": 5}]
+            }
+        }"#;
+        let event = serde_json::from_str::<GithubEvent>(body).unwrap();
+        assert_eq!(event.head_sha(), "head_sha");
+        let req = event.into_check_request("req".to_owned(), "delivery".to_owned());
+        assert_eq!(req.event_name, "check_run");
+        assert_eq!(req.before, Some("before_sha".to_owned()));
+        assert_eq!(req.pull_request_number, Some(5));
+        assert_eq!(
+            req.check_run,
+            Some(RerequestedCheckRun {
+                id: 42,
+                name: "run-lint".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn issue_comment() {
+        let body = r#"{
+            "action": "created",
+            "repository": {"full_name": "owner/repo", "name": "repo", "private": true, "owner": {"login": "owner"}, "custom_properties": {}},
+            "sender": {"login": "ferris"},
+            "issue": {"number": 7, "pull_request": {"url": "https://api.github.com/repos/owner/repo/pulls/7"}},
+            "comment": {"id": 1, "body": "LGTM\r\n /orgu rerun \r\n", "user": {"login": "ferris"}}
+        }"#;
+        let event = serde_json::from_str::<IssueCommentEvent>(body).unwrap();
+        assert!(event.on_pull_request());
+        assert!(event.has_command("/orgu rerun"));
+        assert!(!event.has_command("/orgu"));
+
+        let pr = PullRequestRefs {
+            head_sha: "head_sha".to_owned(),
+            head_ref: "feature".to_owned(),
+            base_sha: "base_sha".to_owned(),
+            base_ref: "main".to_owned(),
+        };
+        let req = event.into_check_request("req".to_owned(), "delivery".to_owned(), pr);
+        assert_eq!(req.event_name, "issue_comment");
+        assert_eq!(req.head_sha, "head_sha");
+        assert_eq!(req.before, Some("base_sha".to_owned()));
+        assert_eq!(req.pull_request_number, Some(7));
+        assert_eq!(req.pull_request_head_ref, Some("feature".to_owned()));
+        assert_eq!(req.comment_id, Some(1));
+    }
+
+    #[test]
+    fn issue_comment_quoted() {
+        let event = IssueCommentEvent {
+            comment: IssueComment {
+                body: "> /orgu rerun\nwhy?".to_owned(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(!event.has_command("/orgu rerun"));
+        assert!(!event.on_pull_request());
+    }
+
+    #[test]
+    fn custom_properties() {
+        let body = r#"{
+            "full_name": "owner/repo", "name": "repo", "private": true, "owner": {"login": "owner"},
+            "custom_properties": {"team": "t-ferris", "teams": ["a", "b"]}
+        }"#;
+        let repo = serde_json::from_str::<GithubRepository>(body).unwrap();
+        let props = &repo.custom_properties;
+        assert_eq!(
+            props.get("team"),
+            Some(&CustomPropValue::Single("t-ferris".to_owned()))
+        );
+        let teams = props.get("teams").unwrap();
+        assert_eq!(
+            teams,
+            &CustomPropValue::Multiple(vec!["a".to_owned(), "b".to_owned()])
+        );
+        assert_eq!(teams.to_string(), "a,b");
+    }
 }
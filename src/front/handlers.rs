@@ -1,16 +1,21 @@
 mod health_check;
 mod webhook;
 
-pub use health_check::health_check;
-pub use webhook::webhook;
+pub use health_check::{health_check, version};
+pub use webhook::{decide, is_rerequest, webhook, Decision};
+
+use std::time::Instant;
 
 use crate::{
     event_queue_client::EventQueueClient, front::config::FrontConfig, github_client::GithubClient,
+    ssmenv::RefreshableSecret,
 };
 
 #[derive(Debug)]
 pub struct AppState<EB: EventQueueClient, GH: GithubClient> {
     pub config: FrontConfig,
+    pub webhook_secret: RefreshableSecret,
     pub event_bus_client: EB,
     pub github_client: GH,
+    pub started_at: Instant,
 }
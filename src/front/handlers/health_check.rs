@@ -1,13 +1,37 @@
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use serde_json::json;
 
-use crate::app_error::AppError;
+use crate::{
+    app_error::AppError,
+    build_info::{BuildInfo, GIT_SHA, VERSION},
+    event_queue_client::EventQueueClient,
+    front::handlers::AppState,
+    github_client::GithubClient,
+};
 
-pub async fn health_check() -> Result<impl IntoResponse, AppError> {
+pub async fn health_check<EB, GH>(
+    State(state): State<Arc<AppState<EB, GH>>>,
+) -> Result<impl IntoResponse, AppError>
+where
+    EB: EventQueueClient,
+    GH: GithubClient,
+{
+    if !state.config.health_check_build_info {
+        return Ok((StatusCode::OK, Json(json!({ "status": "ok" }))));
+    }
     Ok((
         StatusCode::OK,
         Json(json!({
             "status": "ok",
+            "version": VERSION,
+            "git_sha": GIT_SHA,
+            "uptime_secs": state.started_at.elapsed().as_secs(),
         })),
     ))
 }
+
+pub async fn version() -> Json<BuildInfo> {
+    Json(BuildInfo::get())
+}
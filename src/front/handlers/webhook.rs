@@ -14,22 +14,15 @@ use crate::{
     event_queue_client::EventQueueClient,
     events::GithubRepository,
     front::{
-        github_events::{GithubEvent, WebhookCommonFields},
+        config::{supported_actions, SupportedEvent},
+        github_events::{GithubEvent, IssueCommentEvent, WebhookCommonFields},
         handlers::AppState,
     },
-    github_client::{into_update_request, GithubClient},
+    github_client::{into_update_request, name_with_environment, GithubClient},
     github_verifier::GithubRequestVerifier,
 };
 
 const CHECK_RUN_NAME: &str = "orgu-trigger";
-const SUPPORTED_EVENTS: &[(&str, &[&str])] = &[
-    ("ping", &[]),
-    ("check_suite", &["requested", "rerequested"]),
-    (
-        "pull_request",
-        &["opened", "synchronize", "reopened", "ready_for_review"],
-    ),
-];
 
 #[instrument(
     skip_all,
@@ -51,7 +44,7 @@ where
     GH: GithubClient,
     V: GithubRequestVerifier,
 {
-    if let Err(e) = V::verify_request(&headers, &body, &state.config.webhook_secret) {
+    if let Err(e) = V::verify_request(&headers, &body, &state.webhook_secret.load()) {
         warn!("Request verification failed: {e}");
         return Err(AppError::AuthorizationError);
     }
@@ -60,55 +53,93 @@ where
     Span::current().record("delivery_id", delivery_id);
     let event_name = get_header_str(&headers, "x-github-event")?;
     Span::current().record("event_name", event_name);
-    let supported_actions = match SUPPORTED_EVENTS
-        .iter()
-        .find(|(name, _)| name == &event_name)
-    {
-        None => {
+    let config = &state.config;
+    let decision = decide(
+        event_name,
+        &body,
+        &config.supported_events,
+        config.comment_command.as_deref(),
+    )?;
+    if let Some(common) = decision.common() {
+        Span::current().record("action", &common.action);
+        Span::current().record("owner", &common.repository.owner.login);
+        Span::current().record("repo", &common.repository.name);
+    }
+    let request_id = get_header_str(&headers, "x-request-id")?;
+    let (common, req) = match decision {
+        Decision::UnsupportedEvent => {
             info!("unsupported event type");
             return Ok((
                 StatusCode::OK,
                 format!("Unsupported event type, skipping: {event_name}"),
             ));
         }
-        Some(ev) => ev.1,
+        Decision::Ping => return Ok((StatusCode::OK, "pong".to_owned())),
+        Decision::UnsupportedAction(common) => {
+            info!("action not supported");
+            return Ok((
+                StatusCode::OK,
+                format!("Unsupported event action, skipping: {}", common.action),
+            ));
+        }
+        Decision::PublicRepository(_) => {
+            info!("skipping public repository");
+            return Ok((StatusCode::OK, "Public repository, skipping".to_owned()));
+        }
+        Decision::InactiveRepository(_) => {
+            info!("skipping archived or disabled repository");
+            return Ok((
+                StatusCode::OK,
+                "Archived or disabled repository, skipping".to_owned(),
+            ));
+        }
+        Decision::IgnoredComment(_) => {
+            info!("skipping comment without command");
+            return Ok((StatusCode::OK, "Not a command comment, skipping".to_owned()));
+        }
+        Decision::Publish { common, event } => {
+            let req = event.into_check_request(request_id.to_owned(), delivery_id.to_owned());
+            (common, req)
+        }
+        Decision::Comment { common, event } => {
+            let owner = &common.repository.owner.login;
+            let repo = &common.repository.name;
+            let user = &event.comment.user.login;
+            let permission = state
+                .github_client
+                .get_collaborator_permission(owner, repo, user)
+                .await?;
+            if permission < config.comment_command_permission {
+                info!(user, %permission, "skipping comment command by user without enough permission");
+                return Ok((
+                    StatusCode::OK,
+                    format!("Insufficient permission for comment command, skipping: {user}"),
+                ));
+            }
+            let pr = state
+                .github_client
+                .get_pull_request(owner, repo, event.issue.number)
+                .await?;
+            let req = event.into_check_request(request_id.to_owned(), delivery_id.to_owned(), pr);
+            (common, req)
+        }
     };
-    if event_name == "ping" {
-        return Ok((StatusCode::OK, "pong".to_owned()));
-    }
-
-    let event = from_str::<WebhookCommonFields>(&body).with_context(|| {
-        format!("failed to parse payload to common event type: event={event_name}, body:\n{body}")
-    })?;
-    Span::current().record("action", &event.action);
-    Span::current().record("owner", &event.repository.owner.login);
-    Span::current().record("repo", &event.repository.name);
-    if !supported_actions.contains(&event.action.as_ref()) {
-        info!("action not supported");
-        return Ok((
-            StatusCode::OK,
-            format!("Unsupported event action, skipping: {}", event.action),
-        ));
-    }
-    if !event.repository.private {
-        info!("skipping public repository");
-        return Ok((StatusCode::OK, "Public repository, skipping".to_owned()));
-    }
 
-    let repository = event.repository;
-    let event = from_str::<GithubEvent>(&body).with_context(|| {
-        format!("failed to parse payload to concret event type: event={event_name}, body={body}")
-    })?;
+    let rerequested = is_rerequest(event_name, &common.action);
+    let repository = common.repository;
+    let head_sha = req.head_sha.clone();
 
-    let request_id = get_header_str(&headers, "x-request-id")?;
-    let req = event
-        .clone()
-        .into_check_request(request_id.to_owned(), delivery_id.to_owned());
     info!("publishing event");
     state.event_bus_client.send(req).await?;
 
+    if rerequested && !state.config.report_trigger_on_rerequest {
+        info!("skipping trigger check run for rerequested event");
+        return Ok((StatusCode::OK, "ok".to_owned()));
+    }
+
     // Creating checkrun can fail so ignore the error because it's not must-have.
-    if let Err(e) = report_via_check_run(&state, &event, &repository, delivery_id, request_id).await
+    if let Err(e) =
+        report_via_check_run(&state, &head_sha, &repository, delivery_id, request_id).await
     {
         warn!(error = ?e, "failed to report via check_run API and safely ignored");
         return Ok((
@@ -120,6 +151,95 @@ where
     Ok((StatusCode::OK, "ok".to_owned()))
 }
 
+/// Outcome of filtering a webhook payload. Shared with `front inspect` to debug payloads offline.
+#[derive(Debug)]
+pub enum Decision {
+    UnsupportedEvent,
+    Ping,
+    UnsupportedAction(WebhookCommonFields),
+    PublicRepository(WebhookCommonFields),
+    InactiveRepository(WebhookCommonFields),
+    /// issue_comment not on a pull request or without the command.
+    IgnoredComment(WebhookCommonFields),
+    Publish {
+        common: WebhookCommonFields,
+        event: GithubEvent,
+    },
+    /// Command comment on a pull request. The permission of the commenter is checked and the head is resolved via API
+    /// on publishing.
+    Comment {
+        common: WebhookCommonFields,
+        event: Box<IssueCommentEvent>,
+    },
+}
+
+impl Decision {
+    pub const fn common(&self) -> Option<&WebhookCommonFields> {
+        match self {
+            Self::UnsupportedEvent | Self::Ping => None,
+            Self::UnsupportedAction(c)
+            | Self::PublicRepository(c)
+            | Self::InactiveRepository(c)
+            | Self::IgnoredComment(c)
+            | Self::Publish { common: c, .. }
+            | Self::Comment { common: c, .. } => Some(c),
+        }
+    }
+}
+
+/// Parse the payload and decide whether to publish it, without side effects.
+pub fn decide(
+    event_name: &str,
+    body: &str,
+    supported_events: &[SupportedEvent],
+    comment_command: Option<&str>,
+) -> Result<Decision> {
+    let Some(supported_actions) = supported_actions(supported_events, event_name) else {
+        return Ok(Decision::UnsupportedEvent);
+    };
+    if event_name == "ping" {
+        return Ok(Decision::Ping);
+    }
+    if event_name == "issue_comment" && comment_command.is_none() {
+        return Ok(Decision::UnsupportedEvent);
+    }
+
+    let common = from_str::<WebhookCommonFields>(body).with_context(|| {
+        format!("failed to parse payload to common event type: event={event_name}, body:\n{body}")
+    })?;
+    if !supported_actions.contains(&common.action.as_ref()) {
+        return Ok(Decision::UnsupportedAction(common));
+    }
+    if !common.repository.private {
+        return Ok(Decision::PublicRepository(common));
+    }
+    if common.repository.is_inactive() {
+        return Ok(Decision::InactiveRepository(common));
+    }
+    if event_name == "issue_comment" {
+        let event = from_str::<IssueCommentEvent>(body).with_context(|| {
+            format!("failed to parse payload to issue comment event: body={body}")
+        })?;
+        let is_command = comment_command.is_some_and(|c| event.has_command(c));
+        if !event.on_pull_request() || !is_command {
+            return Ok(Decision::IgnoredComment(common));
+        }
+        return Ok(Decision::Comment {
+            common,
+            event: Box::new(event),
+        });
+    }
+
+    let event = from_str::<GithubEvent>(body).with_context(|| {
+        format!("failed to parse payload to concret event type: event={event_name}, body={body}")
+    })?;
+    Ok(Decision::Publish { common, event })
+}
+
+pub fn is_rerequest(event_name: &str, action: &str) -> bool {
+    matches!(event_name, "check_suite" | "check_run") && action == "rerequested"
+}
+
 fn get_header_str<'hdr>(headers: &'hdr HeaderMap, key: &str) -> Result<&'hdr str> {
     headers
         .get(key)
@@ -130,14 +250,16 @@ fn get_header_str<'hdr>(headers: &'hdr HeaderMap, key: &str) -> Result<&'hdr str
 
 async fn report_via_check_run<EB: EventQueueClient, GH: GithubClient>(
     state: &AppState<EB, GH>,
-    event: &GithubEvent,
+    head_sha: &str,
     repository: &GithubRepository,
     delivery_id: &str,
     requiest_id: &str,
 ) -> Result<()> {
+    let environment = &state.config.environment;
+    let name = name_with_environment(CHECK_RUN_NAME, environment);
     let input = ChecksCreateRequest {
-        name: CHECK_RUN_NAME.to_owned(),
-        head_sha: event.head_sha().to_owned(),
+        name: name.clone(),
+        head_sha: head_sha.to_owned(),
         status: Some(JobStatus::InProgress),
         conclusion: None,
         output: None,
@@ -145,23 +267,46 @@ async fn report_via_check_run<EB: EventQueueClient, GH: GithubClient>(
         completed_at: None,
         started_at: None,
         details_url: Default::default(),
-        external_id: Default::default(),
+        // Delivery ID is the same for re-delivery, so use it to find the existing check run.
+        external_id: delivery_id.to_owned(),
     };
     let owner = &repository.owner.login;
     let repo = &repository.name;
-    let res = state
-        .github_client
-        .create_check_run(owner, repo, &input)
-        .await?;
+    let existing = if state.config.reuse_check_runs {
+        state
+            .github_client
+            .find_check_run(owner, repo, &input.head_sha, &name, delivery_id)
+            .await?
+    } else {
+        None
+    };
+    let res = match existing {
+        Some(r) => {
+            info!(check_run_id = r.id, "reusing existing check run");
+            r
+        }
+        None => {
+            state
+                .github_client
+                .create_check_run(owner, repo, &input)
+                .await?
+        }
+    };
 
     let mut input = into_update_request(input);
     input.status = Some(JobStatus::Completed);
     input.conclusion = Some(ChecksCreateRequestConclusion::Success);
+    let env_line = if environment.is_empty() {
+        String::new()
+    } else {
+        format!("Environment: `{environment}`\n")
+    };
+    let summary = format!(
+        "{env_line}Delivery ID (not unique for re-delivery): {delivery_id}\nRequest ID (unique for re-delivery): {requiest_id}"
+    );
     input.output = Some(ChecksUpdateRequestOutput {
         title: "orgu-front queued".to_owned(),
-        summary: format!(
-            "Delivery ID (not unique for re-delivery): {delivery_id}\nRequest ID (unique for re-delivery): {requiest_id}"
-        ),
+        summary,
         text: Default::default(),
         annotations: Default::default(),
         images: Default::default(),
@@ -176,18 +321,24 @@ async fn report_via_check_run<EB: EventQueueClient, GH: GithubClient>(
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::time::Instant;
 
-    use anyhow::bail;
+    use anyhow::{anyhow, bail};
     use axum::{routing::post, Router};
     use axum_test::{TestResponse, TestServer};
     use serde::Serialize;
 
     use crate::{
-        event_queue_client::{EventQueueClient, MockEventQueueClient},
-        front::{config::FrontConfig, github_events::PullRequestEvent},
-        github_client::{empty_checkrun, MockGithubClient},
+        event_queue_client::{EventQueueClient, MockEventQueueClient, SendError},
+        front::{
+            config::FrontConfig,
+            github_events::{
+                CheckSuiteEvent, Issue, IssueComment, IssuePullRequest, PullRequestEvent,
+            },
+        },
+        github_client::{empty_checkrun, MockGithubClient, Permission, PullRequestRefs},
         github_verifier::test::NullVerifier,
+        ssmenv::RefreshableSecret,
     };
 
     use super::*;
@@ -198,12 +349,11 @@ mod tests {
         GH: GithubClient,
     {
         Arc::new(AppState {
-            config: FrontConfig {
-                webhook_secret: "test_secret".to_owned(),
-                server_timeout: Duration::from_secs(0).into(),
-            },
+            config: Default::default(),
+            webhook_secret: RefreshableSecret::new("test_secret".to_owned()),
             event_bus_client: eb,
             github_client: gh,
+            started_at: Instant::now(),
         })
     }
 
@@ -236,6 +386,47 @@ mod tests {
 
     // vefify_ng case is in routes.rs
 
+    fn decide_default(event_name: &str, body: &str) -> Result<Decision> {
+        decide(event_name, body, &[], None)
+    }
+
+    #[test]
+    fn decide_steps() {
+        let body = |action: &str, private: bool| {
+            let mut e = PullRequestEvent::default();
+            e.common.action = action.to_owned();
+            e.common.repository.private = private;
+            serde_json::to_string(&e).unwrap()
+        };
+        let archived = {
+            let mut e = PullRequestEvent::default();
+            e.common.action = "opened".to_owned();
+            e.common.repository.private = true;
+            e.common.repository.archived = true;
+            serde_json::to_string(&e).unwrap()
+        };
+        assert!(matches!(
+            decide_default("issues", &body("opened", true)).unwrap(),
+            Decision::UnsupportedEvent
+        ));
+        assert!(matches!(
+            decide_default("pull_request", &body("labeled", true)).unwrap(),
+            Decision::UnsupportedAction(_)
+        ));
+        assert!(matches!(
+            decide_default("pull_request", &body("opened", false)).unwrap(),
+            Decision::PublicRepository(_)
+        ));
+        assert!(matches!(
+            decide_default("pull_request", &archived).unwrap(),
+            Decision::InactiveRepository(_)
+        ));
+        assert!(matches!(
+            decide_default("pull_request", &body("opened", true)).unwrap(),
+            Decision::Publish { .. }
+        ));
+    }
+
     #[tokio::test]
     async fn invalid_request_body() -> Result<()> {
         let res = call(init_state_never(), Default::default(), "invalid json").await?;
@@ -295,6 +486,25 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn archived_repository() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "pull_request".parse().unwrap());
+        let payload = WebhookCommonFields {
+            action: "synchronize".to_owned(),
+            repository: GithubRepository {
+                private: true,
+                archived: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let res = call(init_state_never(), headers, &payload).await?;
+        res.assert_status_ok();
+        res.assert_text("Archived or disabled repository, skipping");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn pull_request() -> Result<()> {
         let mut headers = HeaderMap::new();
@@ -333,6 +543,249 @@ mod tests {
         Ok(())
     }
 
+    fn check_suite_rerequested() -> CheckSuiteEvent {
+        CheckSuiteEvent {
+            common: WebhookCommonFields {
+                action: "rerequested".to_owned(),
+                repository: GithubRepository {
+                    private: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn check_suite_rerequested_skips_trigger() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "check_suite".parse().unwrap());
+
+        let mut mock_event_bus_client = MockEventQueueClient::new();
+        mock_event_bus_client
+            .expect_send()
+            .once()
+            .returning(|_| Ok(()));
+        let mut mock_github_client = MockGithubClient::new();
+        mock_github_client.expect_create_check_run().never();
+        let state = init_state(mock_event_bus_client, mock_github_client);
+
+        let res = call(state, headers, &check_suite_rerequested()).await?;
+        res.assert_status_ok();
+        res.assert_text("ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_suite_rerequested_reports_trigger() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "check_suite".parse().unwrap());
+
+        let mut mock_event_bus_client = MockEventQueueClient::new();
+        mock_event_bus_client
+            .expect_send()
+            .once()
+            .returning(|_| Ok(()));
+        let mut mock_github_client = MockGithubClient::new();
+        mock_github_client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        mock_github_client
+            .expect_update_check_run()
+            .once()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let state = Arc::new(AppState {
+            config: FrontConfig {
+                report_trigger_on_rerequest: true,
+                ..Default::default()
+            },
+            webhook_secret: RefreshableSecret::new("test_secret".to_owned()),
+            event_bus_client: mock_event_bus_client,
+            github_client: mock_github_client,
+            started_at: Instant::now(),
+        });
+
+        let res = call(state, headers, &check_suite_rerequested()).await?;
+        res.assert_status_ok();
+        res.assert_text("ok");
+        Ok(())
+    }
+
+    fn comment(body: &str) -> IssueCommentEvent {
+        IssueCommentEvent {
+            common: WebhookCommonFields {
+                action: "created".to_owned(),
+                repository: GithubRepository {
+                    private: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            issue: Issue {
+                number: 7,
+                pull_request: Some(IssuePullRequest::default()),
+            },
+            comment: IssueComment {
+                body: body.to_owned(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn init_state_with_command<EB, GH>(eb: EB, gh: GH) -> Arc<AppState<EB, GH>>
+    where
+        EB: EventQueueClient,
+        GH: GithubClient,
+    {
+        Arc::new(AppState {
+            config: FrontConfig {
+                comment_command: Some("/orgu rerun".to_owned()),
+                ..Default::default()
+            },
+            webhook_secret: RefreshableSecret::new("test_secret".to_owned()),
+            event_bus_client: eb,
+            github_client: gh,
+            started_at: Instant::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn comment_command() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "issue_comment".parse().unwrap());
+
+        let mut mock_event_bus_client = MockEventQueueClient::new();
+        mock_event_bus_client
+            .expect_send()
+            .withf(|req| {
+                req.event_name == "issue_comment"
+                    && req.head_sha == "head_sha"
+                    && req.pull_request_number == Some(7)
+            })
+            .once()
+            .returning(|_| Ok(()));
+        let mut mock_github_client = MockGithubClient::new();
+        mock_github_client
+            .expect_get_collaborator_permission()
+            .once()
+            .returning(|_, _, _| Ok(Permission::Admin));
+        mock_github_client
+            .expect_get_pull_request()
+            .withf(|_, _, number| *number == 7)
+            .once()
+            .returning(|_, _, _| {
+                Ok(PullRequestRefs {
+                    head_sha: "head_sha".to_owned(),
+                    ..Default::default()
+                })
+            });
+        mock_github_client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        mock_github_client
+            .expect_update_check_run()
+            .once()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let state = init_state_with_command(mock_event_bus_client, mock_github_client);
+
+        let res = call(state, headers, &comment("/orgu rerun")).await?;
+        res.assert_status_ok();
+        res.assert_text("ok");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn comment_ignored() -> Result<()> {
+        let cases = [
+            comment("LGTM"),
+            IssueCommentEvent {
+                issue: Issue {
+                    number: 7,
+                    pull_request: None,
+                },
+                ..comment("/orgu rerun")
+            },
+        ];
+        for payload in cases {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-github-event", "issue_comment".parse().unwrap());
+            let mut mock_github_client = MockGithubClient::new();
+            mock_github_client.expect_get_pull_request().never();
+            let mut mock_event_bus_client = MockEventQueueClient::new();
+            mock_event_bus_client.expect_send().never();
+            let state = init_state_with_command(mock_event_bus_client, mock_github_client);
+
+            let res = call(state, headers, &payload).await?;
+            res.assert_status_ok();
+            res.assert_text("Not a command comment, skipping");
+        }
+
+        // Comment commands are disabled by default.
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "issue_comment".parse().unwrap());
+        let res = call(init_state_never(), headers, &comment("/orgu rerun")).await?;
+        res.assert_status_ok();
+        res.assert_text("Unsupported event type, skipping: issue_comment");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn comment_insufficient_permission() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "issue_comment".parse().unwrap());
+
+        let mut mock_event_bus_client = MockEventQueueClient::new();
+        mock_event_bus_client.expect_send().never();
+        let mut mock_github_client = MockGithubClient::new();
+        mock_github_client
+            .expect_get_collaborator_permission()
+            .withf(|_, _, user| user == "ferris")
+            .once()
+            .returning(|_, _, _| Ok(Permission::Read));
+        mock_github_client.expect_get_pull_request().never();
+        let state = init_state_with_command(mock_event_bus_client, mock_github_client);
+
+        let mut payload = comment("/orgu rerun");
+        payload.comment.user.login = "ferris".to_owned();
+        let res = call(state, headers, &payload).await?;
+        res.assert_status_ok();
+        res.assert_text("Insufficient permission for comment command, skipping: ferris");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transient_send_failure() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", "pull_request".parse().unwrap());
+        let payload = PullRequestEvent {
+            common: WebhookCommonFields {
+                action: "synchronize".to_owned(),
+                repository: GithubRepository {
+                    private: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut mock_event_bus_client = MockEventQueueClient::new();
+        mock_event_bus_client
+            .expect_send()
+            .once()
+            .returning(|_| Err(SendError::Transient(anyhow!("throttled"))));
+        let mut mock_github_client = MockGithubClient::new();
+        mock_github_client.expect_create_check_run().never();
+        let state = init_state(mock_event_bus_client, mock_github_client);
+
+        let res = call(state, headers, &payload).await?;
+        res.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn success_if_github_api_fails() -> Result<()> {
         let mut headers = HeaderMap::new();
@@ -0,0 +1,194 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+#[cfg(feature = "aws")]
+use lambda_http::request::RequestContext;
+use tracing::{debug, warn};
+
+use crate::app_error::AppError;
+
+/// Restricts webhook requests to the allowed source IP ranges, e.g. GitHub webhook IP ranges.
+#[derive(Debug, Clone)]
+pub struct IpFilter {
+    allowed: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub const fn new(allowed: Vec<IpNet>, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            allowed,
+            trusted_proxies,
+        }
+    }
+
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.allowed.iter().any(|n| n.contains(&ip))
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|n| n.contains(&ip))
+    }
+
+    // Walk X-Forwarded-For from the right-most entry while the current hop is a trusted proxy. Entries added by
+    // untrusted hops can be spoofed, so the first untrusted address is the client IP.
+    fn client_ip(&self, peer: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let mut ip = peer;
+        let hops = forwarded_for
+            .map(|v| v.split(',').map(str::trim).rev().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for hop in hops {
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
+            match hop.parse() {
+                Ok(v) => ip = v,
+                Err(_) => break,
+            }
+        }
+        ip
+    }
+
+    // Without the peer address, e.g. ALB invoking Lambda, the source IP is the right-most X-Forwarded-For entry
+    // appended by ALB. Other sources without the peer address are not trusted.
+    fn source_ip(
+        &self,
+        peer: Option<IpAddr>,
+        via_alb: bool,
+        forwarded_for: Option<&str>,
+    ) -> Option<IpAddr> {
+        if let Some(peer) = peer {
+            return Some(self.client_ip(peer, forwarded_for));
+        }
+        if !via_alb {
+            return None;
+        }
+        let hops = forwarded_for?;
+        let (rest, last) = hops
+            .rsplit_once(',')
+            .map_or((None, hops), |(rest, last)| (Some(rest), last));
+        let peer = last.trim().parse().ok()?;
+        Some(self.client_ip(peer, rest))
+    }
+}
+
+pub async fn filter_ip(
+    State(filter): State<Arc<IpFilter>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let peer = peer_ip(&req);
+    let forwarded_for = req
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok());
+    let Some(ip) = filter.source_ip(peer, via_alb(&req), forwarded_for) else {
+        warn!("could not determine source IP, rejecting request");
+        return Err(AppError::Forbidden);
+    };
+    if !filter.is_allowed(ip) {
+        warn!(%ip, ?peer, "source IP is not allowed");
+        return Err(AppError::Forbidden);
+    }
+    debug!(%ip, ?peer, "source IP is allowed");
+    Ok(next.run(req).await)
+}
+
+// Peer address is given by ConnectInfo in server mode or by request context in AWS Lambda.
+fn peer_ip(req: &Request) -> Option<IpAddr> {
+    if let Some(ConnectInfo(addr)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        return Some(addr.ip());
+    }
+    lambda_source_ip(req)
+}
+
+#[cfg(feature = "aws")]
+fn lambda_source_ip(req: &Request) -> Option<IpAddr> {
+    let source_ip = match req.extensions().get::<RequestContext>()? {
+        RequestContext::ApiGatewayV1(ctx) => ctx.identity.source_ip.as_deref(),
+        RequestContext::ApiGatewayV2(ctx) => ctx.http.source_ip.as_deref(),
+        RequestContext::WebSocket(ctx) => ctx.identity.source_ip.as_deref(),
+        // ALB gives the source IP only in X-Forwarded-For, see `IpFilter::source_ip`.
+        RequestContext::Alb(_) => None,
+    };
+    source_ip?.parse().ok()
+}
+
+#[cfg(not(feature = "aws"))]
+const fn lambda_source_ip(_req: &Request) -> Option<IpAddr> {
+    None
+}
+
+#[cfg(feature = "aws")]
+fn via_alb(req: &Request) -> bool {
+    matches!(
+        req.extensions().get::<RequestContext>(),
+        Some(RequestContext::Alb(_))
+    )
+}
+
+#[cfg(not(feature = "aws"))]
+const fn via_alb(_req: &Request) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn filter() -> IpFilter {
+        IpFilter::new(
+            vec!["192.30.252.0/22".parse().unwrap()],
+            vec!["10.0.0.0/8".parse().unwrap()],
+        )
+    }
+
+    #[test]
+    fn client_ip_without_proxy() {
+        let peer = "192.30.252.1".parse().unwrap();
+        assert_eq!(filter().client_ip(peer, Some("10.0.0.1")), peer);
+    }
+
+    #[test]
+    fn client_ip_via_trusted_proxy() {
+        let peer = "10.0.0.1".parse().unwrap();
+        let actual = filter().client_ip(peer, Some("1.1.1.1, 192.30.252.1, 10.0.0.2"));
+        assert_eq!(actual, "192.30.252.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn source_ip_without_peer() {
+        let f = filter();
+        let client = "192.30.252.1".parse::<IpAddr>().unwrap();
+        assert_eq!(f.source_ip(None, false, Some("192.30.252.1")), None);
+        assert_eq!(f.source_ip(None, true, None), None);
+        assert_eq!(f.source_ip(None, true, Some("192.30.252.1")), Some(client));
+        // Entries left of the one appended by ALB are trusted only via trusted proxies.
+        assert_eq!(
+            f.source_ip(None, true, Some("192.30.252.1, 1.1.1.1")),
+            Some("1.1.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            f.source_ip(None, true, Some("1.1.1.1, 192.30.252.1, 10.0.0.2")),
+            Some(client)
+        );
+        assert_eq!(f.source_ip(None, true, Some("unknown")), None);
+    }
+
+    #[test]
+    fn is_allowed() {
+        let f = filter();
+        assert!(f.is_allowed("192.30.253.255".parse().unwrap()));
+        assert!(!f.is_allowed("192.31.0.1".parse().unwrap()));
+    }
+}
@@ -1,32 +1,44 @@
-use std::sync::Arc;
+use std::{
+    hash::{DefaultHasher, Hash as _, Hasher as _},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
-    http::{header, HeaderValue, Request},
+    body::Body,
+    extract::MatchedPath,
+    http::{header, HeaderValue, Request, Response, StatusCode},
+    middleware::{from_fn, from_fn_with_state, Next},
     routing::{get, post},
     Router,
 };
 use http::HeaderName;
+#[cfg(feature = "aws")]
 use lambda_http::Context;
 use tower::{Layer, ServiceBuilder};
 use tower_http::{
+    compression::CompressionLayer,
+    decompression::RequestDecompressionLayer,
     normalize_path::{NormalizePath, NormalizePathLayer},
     request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
     sensitive_headers::SetSensitiveRequestHeadersLayer,
-    set_header::SetRequestHeaderLayer,
+    set_header::{SetRequestHeaderLayer, SetResponseHeaderLayer},
     timeout::TimeoutLayer,
-    trace::{DefaultOnResponse, TraceLayer},
+    trace::TraceLayer,
 };
-use tracing::{info_span, Level};
+use tracing::{debug_span, info, info_span, Span};
 use uuid::Uuid;
 
 use crate::{
     event_queue_client::EventQueueClient,
     front::{
         config::FrontConfig,
-        handlers::{health_check, webhook, AppState},
+        handlers::{health_check, version, webhook, AppState},
+        ip_filter::{filter_ip, IpFilter},
     },
     github_client::GithubClient,
     github_verifier::DefaultVerifier,
+    ssmenv::RefreshableSecret,
 };
 
 pub fn build_app<EB, GH>(
@@ -38,27 +50,49 @@ where
     EB: EventQueueClient + 'static,
     GH: GithubClient + 'static,
 {
+    let webhook_secret = RefreshableSecret::new(config.webhook_secret.clone());
+    if let Some(interval) = config.webhook_secret_refresh_interval {
+        webhook_secret.spawn_refresh("GITHUB_WEBHOOK_SECRET", interval.into());
+    }
     let shared_state = Arc::new(AppState {
         config: config.clone(),
+        webhook_secret,
         event_bus_client,
         github_client,
+        started_at: Instant::now(),
     });
 
-    let router = Router::new()
-        .route("/hc", get(health_check))
-        .route("/github/events", post(webhook::<_, _, DefaultVerifier>))
-        .with_state(shared_state);
+    let mut webhook_route = post(webhook::<_, _, DefaultVerifier>);
+    // Reject disallowed source IPs before verifying signature.
+    if !config.allowed_webhook_cidrs.is_empty() {
+        let filter = IpFilter::new(
+            config.allowed_webhook_cidrs.clone(),
+            config.trusted_proxy_cidrs.clone(),
+        );
+        webhook_route = webhook_route.layer(from_fn_with_state(Arc::new(filter), filter_ip));
+    }
+
+    let mut router = Router::new()
+        .route(&config.health_check_path, get(health_check::<EB, GH>))
+        .route(&config.webhook_path, webhook_route);
+    if config.health_check_build_info {
+        router = router.route(VERSION_PATH, get(version));
+    }
+    let router = router.with_state(shared_state);
 
     let router = apply_middleware(router, &config);
     NormalizePathLayer::trim_trailing_slash().layer(router)
 }
 
+const VERSION_PATH: &str = "/version";
+
 fn apply_middleware(router: Router, config: &FrontConfig) -> Router {
     let headers = ["x-hub-signature", "x-hub-signature-256"]
         .into_iter()
         .flat_map(str::parse)
         .chain([header::AUTHORIZATION, header::COOKIE])
         .collect::<Vec<_>>();
+    let sample_rate = config.log_sample_rate;
     let middleware = ServiceBuilder::new()
         .layer(SetSensitiveRequestHeadersLayer::new(headers))
         .layer(SetRequestIdLayer::new(
@@ -70,28 +104,110 @@ fn apply_middleware(router: Router, config: &FrontConfig) -> Router {
         )))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(|req: &Request<_>| {
+                .make_span_with(move |req: &Request<_>| {
                     // This expects the request id is already set by the previous RequestId middleware.
                     let id = get_request_id_or_default(req);
-                    info_span!(
-                        "request",
-                        method = %req.method(),
-                        uri = %req.uri(),
-                        version = ?req.version(),
-                        request_id = id,
-                    )
+                    // Unsampled spans are still recorded at debug level.
+                    if is_sampled(&id, sample_rate) {
+                        info_span!(
+                            "request",
+                            method = %req.method(),
+                            uri = %req.uri(),
+                            version = ?req.version(),
+                            request_id = id,
+                        )
+                    } else {
+                        debug_span!(
+                            "request",
+                            method = %req.method(),
+                            uri = %req.uri(),
+                            version = ?req.version(),
+                            request_id = id,
+                        )
+                    }
                 })
-                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                .on_response(move |res: &Response<_>, latency, _: &Span| {
+                    log_access(res, latency, sample_rate);
+                }),
         )
+        .layer(from_fn(keep_request_info))
         .layer(SetRequestHeaderLayer::if_not_present(
             header::CONTENT_TYPE,
             HeaderValue::from_static("application/json"),
         ))
+        // Signatures are computed over the uncompressed payload, so decompress before verifying.
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new())
+        .option_layer(config.retry_after.map(|d| retry_after_layer(d.into())))
         .layer(TimeoutLayer::new(config.server_timeout.into()));
 
     router.layer(middleware)
 }
 
+// Hint GitHub and the relay when to retry 503 responses, e.g. when the event queue is throttled.
+fn retry_after_layer(
+    d: Duration,
+) -> SetResponseHeaderLayer<impl Fn(&Response<Body>) -> Option<HeaderValue> + Clone> {
+    let value = HeaderValue::from(d.as_secs());
+    SetResponseHeaderLayer::if_not_present(header::RETRY_AFTER, move |res: &Response<Body>| {
+        (res.status() == StatusCode::SERVICE_UNAVAILABLE).then(|| value.clone())
+    })
+}
+
+// Access log line with fields for log-based metrics. The request info is kept in the response by
+// `keep_request_info`. Error responses are always logged, successful ones only if sampled.
+fn log_access<B>(res: &Response<B>, latency: Duration, sample_rate: f64) {
+    let request_id = res
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+    let status = res.status();
+    if !(status.is_client_error()
+        || status.is_server_error()
+        || is_sampled(request_id, sample_rate))
+    {
+        return;
+    }
+    let route = res
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or_default();
+    info!(
+        status = status.as_u16(),
+        latency_ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX),
+        request_id,
+        route,
+        "finished processing request"
+    );
+}
+
+// Sample by the hash of the request id, so the span and the access log of a request are sampled consistently.
+fn is_sampled(request_id: &str, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    let mut hasher = DefaultHasher::new();
+    request_id.hash(&mut hasher);
+    let bucket = u32::try_from(hasher.finish() >> 32).unwrap_or(u32::MAX);
+    f64::from(bucket) / f64::from(u32::MAX) < sample_rate
+}
+
+// TraceLayer's on_response gets only the response, so copy the request info to log into it.
+async fn keep_request_info(req: Request<Body>, next: Next) -> Response<Body> {
+    let request_id = req.extensions().get::<RequestId>().cloned();
+    let route = req.extensions().get::<MatchedPath>().cloned();
+    let mut res = next.run(req).await;
+    if let Some(id) = request_id {
+        res.extensions_mut().insert(id);
+    }
+    if let Some(route) = route {
+        res.extensions_mut().insert(route);
+    }
+    res
+}
+
 fn get_request_id_or_default<T>(req: &Request<T>) -> String {
     req.extensions()
         .get::<RequestId>()
@@ -106,18 +222,30 @@ impl MakeRequestId for OrguReqeustIdMaker {
     // If we have a lambda context, use the request id from there. Otherwise, generate a new one.
     // Use Extensions::get which is more general than lambda_http::RequestExt.
     fn make_request_id<B>(&mut self, req: &Request<B>) -> Option<RequestId> {
-        let id = req
-            .extensions()
-            .get::<Context>()
-            .map_or_else(|| Uuid::new_v4().to_string(), |ctx| ctx.request_id.clone());
+        let id = lambda_request_id(req).unwrap_or_else(|| Uuid::new_v4().to_string());
         id.parse().map(RequestId::new).ok()
     }
 }
 
+#[cfg(feature = "aws")]
+fn lambda_request_id<B>(req: &Request<B>) -> Option<String> {
+    req.extensions()
+        .get::<Context>()
+        .map(|ctx| ctx.request_id.clone())
+}
+
+#[cfg(not(feature = "aws"))]
+const fn lambda_request_id<B>(_req: &Request<B>) -> Option<String> {
+    None
+}
+
 #[cfg(test)]
 mod tests {
-    use axum::body::Body;
-    use http::{Method, StatusCode};
+    use std::net::SocketAddr;
+
+    use axum::extract::ConnectInfo;
+    use http::Method;
+    use http_body_util::BodyExt as _;
     use tower::ServiceExt as _;
 
     use super::*;
@@ -153,6 +281,82 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn health_check_build_info() {
+        for (build_info, expected) in [(false, false), (true, true)] {
+            let config = FrontConfig {
+                health_check_build_info: build_info,
+                ..Default::default()
+            };
+            let app = build_app(config, MockEventQueueClient::new(), NullClient);
+            let req = Request::builder()
+                .method(Method::GET)
+                .uri("/hc")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let json = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            assert_eq!(json.get("status").and_then(|v| v.as_str()), Some("ok"));
+            assert_eq!(json.get("version").is_some(), expected);
+            assert_eq!(json.get("uptime_secs").is_some(), expected);
+
+            let req = Request::builder()
+                .method(Method::GET)
+                .uri("/version")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(req).await.unwrap();
+            let status = if expected {
+                StatusCode::OK
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            assert_eq!(response.status(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_info_for_access_log() {
+        let response = call_app(Method::GET, "/hc", Body::empty()).await;
+        let route = response.extensions().get::<MatchedPath>().unwrap();
+        assert_eq!(route.as_str(), "/hc");
+        assert!(response.extensions().get::<RequestId>().is_some());
+    }
+
+    #[test]
+    fn sampling() {
+        let ids = (0..1000).map(|i| format!("req-{i}")).collect::<Vec<_>>();
+        let count = |rate| ids.iter().filter(|id| is_sampled(id, rate)).count();
+        assert_eq!(count(1.0), 1000);
+        assert_eq!(count(0.0), 0);
+        let sampled = count(0.3);
+        assert!((200..400).contains(&sampled), "sampled: {sampled}");
+        // Deterministic per request.
+        assert_eq!(is_sampled("req-1", 0.5), is_sampled("req-1", 0.5));
+    }
+
+    #[tokio::test]
+    async fn retry_after() {
+        let app = Router::new()
+            .route(
+                "/unavailable",
+                get(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+            )
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .layer(retry_after_layer(Duration::from_secs(30)));
+        for (path, expected) in [("/unavailable", Some("30")), ("/ok", None)] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(req).await.unwrap();
+            let actual = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .map(|v| v.to_str().unwrap());
+            assert_eq!(actual, expected, "path: {path}");
+        }
+    }
+
     #[tokio::test]
     async fn routes_github_events_get() {
         let response = call_app(Method::GET, "/github/events", Body::empty()).await;
@@ -165,6 +369,47 @@ mod tests {
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[tokio::test]
+    async fn routes_github_events_ip_not_allowed() {
+        let config = FrontConfig {
+            allowed_webhook_cidrs: vec!["192.30.252.0/22".parse().unwrap()],
+            ..Default::default()
+        };
+        let app = build_app(config, MockEventQueueClient::new(), NullClient);
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/github/events")
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 12345))))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn custom_paths() {
+        let config = FrontConfig {
+            webhook_path: "/hooks/github".to_owned(),
+            health_check_path: "/healthz".to_owned(),
+            ..Default::default()
+        };
+        let app = build_app(config, MockEventQueueClient::new(), NullClient);
+        for (method, path, status) in [
+            (Method::POST, "/hooks/github/", StatusCode::UNAUTHORIZED),
+            (Method::POST, "/github/events", StatusCode::NOT_FOUND),
+            (Method::GET, "//healthz/", StatusCode::OK),
+            (Method::GET, "/hc", StatusCode::NOT_FOUND),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(response.status(), status, "path: {path}");
+        }
+    }
+
     #[tokio::test]
     async fn normalize_path() {
         let response = call_app(Method::GET, "//hc/", Body::empty()).await;
@@ -1,17 +1,34 @@
+mod coalesce;
+
+use std::sync::Arc;
+
 use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use clap::ValueEnum;
+use ipnet::IpNet;
 use octorust::auth::{Credentials, InstallationTokenGenerator, JWTCredentials};
 use octorust::checks::Checks;
+use octorust::issues::Issues;
+use octorust::pulls::Pulls;
 use octorust::repos::Repos;
-use octorust::types::{CheckRun, ChecksUpdateRequestOutput, JobStatus};
+use octorust::types::{
+    ActionsListJobsWorkflowRunFilter, CheckRun, ChecksUpdateRequestOutput, JobStatus,
+    PullsUpdateReviewRequest,
+};
 use octorust::types::{ChecksCreateRequest, ChecksUpdateRequest, Output};
 use reqwest::Method;
-use reqwest_middleware::ClientWithMiddleware;
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use strum::Display;
 use tracing::info;
 use url::Url;
 
 use crate::events::GithubRepository;
 use crate::github_config::{reqwest_client, GithubApiConfig, GithubAppConfig};
+use crate::ssmenv::RefreshableSecret;
+
+use self::coalesce::UpdateCoalescer;
 
 #[allow(clippy::indexing_slicing)] // For automock.
 #[cfg_attr(test, mockall::automock)]
@@ -31,33 +48,267 @@ pub trait GithubClient: Send + Sync {
         check_run_id: i64,
         input: &ChecksUpdateRequest,
     ) -> Result<CheckRun>;
+
+    /// Find the check run created for the commit with given name and external ID.
+    async fn find_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        head_sha: &str,
+        name: &str,
+        external_id: &str,
+    ) -> Result<Option<CheckRun>>;
+
+    /// List check runs of the commit in given status across all pages. `git_ref` can be a SHA, branch or tag name.
+    async fn list_check_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
+        status: JobStatus,
+    ) -> Result<Vec<CheckRun>>;
+
+    /// Create a comment on the issue or pull request, or update the existing one containing the marker.
+    /// The marker is prepended to the body to find the comment next time, so it should be invisible, e.g. HTML comment.
+    async fn upsert_issue_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        issue_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> Result<()>;
+
+    /// Create a deployment and return its ID. Takes the installation token as the request is sent without octorust,
+    /// same as `get_repo`.
+    async fn create_deployment(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        input: &DeploymentInput,
+    ) -> Result<i64>;
+
+    async fn create_deployment_status(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        deployment_id: i64,
+        input: &DeploymentStatusInput,
+    ) -> Result<()>;
+
+    /// Get the head and base of the pull request, for events whose payload doesn't have them, e.g. issue_comment.
+    async fn get_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u64,
+    ) -> Result<PullRequestRefs>;
+
+    /// Permission of the user on the repository. `None` if the user has no access.
+    async fn get_collaborator_permission(
+        &self,
+        owner: &str,
+        repo: &str,
+        user: &str,
+    ) -> Result<Permission>;
+
+    /// React to the issue comment. Takes the installation token, same as `create_deployment`.
+    async fn create_reaction(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        comment_id: i64,
+        content: ReactionContent,
+    ) -> Result<()>;
+}
+
+/// Head and base commits of a pull request.
+#[derive(Debug, Clone, Default)]
+pub struct PullRequestRefs {
+    pub head_sha: String,
+    /// Branch name of the head, can be of a fork repository.
+    pub head_ref: String,
+    pub base_sha: String,
+    pub base_ref: String,
+}
+
+/// Repository permission of a user, in ascending order. Maintain and triage roles are mapped to write and read
+/// respectively, same as the `permission` field of the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ValueEnum, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Permission {
+    None,
+    Read,
+    Write,
+    Admin,
+}
+
+impl Permission {
+    // https://docs.github.com/en/rest/collaborators/collaborators?apiVersion=2022-11-28#get-repository-permissions-for-a-user
+    fn from_api(s: &str) -> Self {
+        match s {
+            "admin" => Self::Admin,
+            "maintain" | "write" => Self::Write,
+            "triage" | "read" => Self::Read,
+            _ => Self::None,
+        }
+    }
+}
+
+// https://docs.github.com/en/rest/deployments/deployments?apiVersion=2022-11-28#create-a-deployment
+#[derive(Debug, Clone, Serialize)]
+pub struct DeploymentInput {
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub environment: String,
+    pub description: String,
+    /// Merging the default branch into the ref doesn't make sense for a commit SHA.
+    pub auto_merge: bool,
+    /// Empty to skip verifying the commit statuses, which include the check run of the deploying job itself.
+    pub required_contexts: Vec<String>,
+}
+
+// https://docs.github.com/en/rest/deployments/statuses?apiVersion=2022-11-28#create-a-deployment-status
+#[derive(Debug, Clone, Serialize)]
+pub struct DeploymentStatusInput {
+    pub state: DeploymentState,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    Pending,
+    InProgress,
+    Success,
+    Failure,
+    Error,
+}
+
+// https://docs.github.com/en/rest/reactions/reactions?apiVersion=2022-11-28#create-reaction-for-an-issue-comment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReactionContent {
+    Rocket,
+    #[serde(rename = "-1")]
+    ThumbsDown,
+}
+
+#[derive(Debug, Serialize)]
+struct ReactionInput {
+    content: ReactionContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeploymentResponse {
+    id: i64,
 }
 
 pub struct OctorustClient {
-    checks: Checks,
-    repos: Repos,
+    api: ArcSwap<Api>,
+    app_key: Option<AppKey>,
     http: ClientWithMiddleware,
+    coalescer: Option<UpdateCoalescer>,
+}
+
+// Octorust API clients sharing the credentials they are built with.
+struct Api {
+    checks: Arc<Checks>,
+    repos: Repos,
+    issues: Issues,
+    pulls: Pulls,
+    // Private key the credentials are built from. None if authenticated with a fixed token.
+    private_key: Option<Arc<String>>,
+}
+
+impl Api {
+    fn new(
+        http: &ClientWithMiddleware,
+        credential: Credentials,
+        private_key: Option<Arc<String>>,
+    ) -> Self {
+        let inner = octorust::Client::custom(
+            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
+            credential,
+            http.clone(),
+        );
+        // checks() clones the inner client so initializing it here to avoid cloning it multiple times.
+        Self {
+            checks: Arc::new(inner.checks()),
+            repos: inner.repos(),
+            issues: inner.issues(),
+            pulls: inner.pulls(),
+            private_key,
+        }
+    }
+}
+
+// GitHub App private key to rebuild the credentials after `--private-key-refresh-interval` swapped it.
+struct AppKey {
+    app_id: i64,
+    installation_id: i64,
+    private_key: RefreshableSecret,
+}
+
+impl AppKey {
+    fn credentials(&self, private_key: &str) -> Result<Credentials> {
+        let p = pem::parse(private_key).with_context(|| "failed to parse GitHub private key")?;
+        let jwt_c = JWTCredentials::new(self.app_id, p.contents().to_owned())
+            .with_context(|| "failed to create JWT credentials")?;
+        let token_generator = InstallationTokenGenerator::new(self.installation_id, jwt_c);
+        Ok(Credentials::InstallationToken(token_generator))
+    }
 }
 
 impl OctorustClient {
     pub fn new(config: GithubApiConfig, app: GithubAppConfig) -> Result<Self> {
-        let p =
-            pem::parse(app.private_key).with_context(|| "failed to parse GitHub private key")?;
-        let jwt_c = JWTCredentials::new(app.app_id, p.contents().to_owned())
-            .with_context(|| "failed to create JWT credentials")?;
-        let token_generator = InstallationTokenGenerator::new(app.installation_id, jwt_c);
-        Self::build(config, Credentials::InstallationToken(token_generator))
+        let private_key = RefreshableSecret::new(app.private_key);
+        if let Some(interval) = app.private_key_refresh_interval {
+            private_key.spawn_refresh("GITHUB_PRIVATE_KEY", interval.into());
+        }
+        let app_key = AppKey {
+            app_id: app.app_id,
+            installation_id: app.installation_id,
+            private_key,
+        };
+        let key = app_key.private_key.load();
+        let credential = app_key.credentials(&key)?;
+        Self::build(config, credential, Some(app_key))
     }
 
     pub fn new_with_token(config: GithubApiConfig, token: String) -> Result<Self> {
-        Self::build(config, Credentials::Token(token))
+        Self::build(config, Credentials::Token(token), None)
+    }
+
+    // API clients with the current private key. Rebuilt when the private key has been refreshed, not to keep
+    // signing with the old key after rotation.
+    fn api(&self) -> Result<Arc<Api>> {
+        let api = self.api.load_full();
+        let Some(app_key) = &self.app_key else {
+            return Ok(api);
+        };
+        let key = app_key.private_key.load();
+        if api.private_key.as_ref().is_some_and(|k| k == &key) {
+            return Ok(api);
+        }
+        info!("GitHub private key has been refreshed, rebuilding the client");
+        let credential = app_key.credentials(&key)?;
+        let api = Arc::new(Api::new(&self.http, credential, Some(key)));
+        self.api.store(Arc::clone(&api));
+        Ok(api)
     }
 
     pub async fn fetch_head_sha(&self, owner: &str, repo: &str) -> Result<String> {
         let res = self
+            .api()?
             .repos
             .list_commits(owner, repo, "", "", "", None, None, 1, 0)
-            .await?;
+            .await
+            .with_context(|| format!("failed to list commits: owner={owner}, repo={repo}"))?;
         let commit = res
             .body
             .first()
@@ -65,38 +316,113 @@ impl OctorustClient {
         Ok(commit.sha.to_owned())
     }
 
+    // Raw request for the APIs octorust doesn't support well. Authenticated with the given installation token.
+    fn api_request(&self, method: Method, path: &str, token: &str) -> Result<RequestBuilder> {
+        let url = Url::parse(Self::GITHUB_API_URL)?.join(path)?;
+        Ok(self
+            .http
+            .request(method, url)
+            .header("accept", "application/vnd.github+json")
+            .bearer_auth(token)
+            .header("x-github-api-version", Self::GITHUB_API_VERSION)
+            .header("user-agent", Self::OUR_USER_AGENT))
+    }
+
     const GITHUB_API_URL: &'static str = "https://api.github.com";
     const GITHUB_API_VERSION: &'static str = "2022-11-28";
     const OUR_USER_AGENT: &'static str = "orgu-github-client";
 
     // XXX: Use raw reqwest Client instead of octorust until it supports Custom Properties.
+    // Transient errors are retried by the middleware of `http`.
     pub async fn get_repo(&self, token: &str, owner: &str, repo: &str) -> Result<GithubRepository> {
-        let path = format!("/repos/{owner}/{repo}");
-        let url = Url::parse(Self::GITHUB_API_URL)?.join(&path)?;
+        let base = Url::parse(Self::GITHUB_API_URL)?;
+        request_repo(&self.http, &base, token, owner, repo)
+            .await
+            .with_context(|| format!("failed to get repository: owner={owner}, repo={repo}"))
+    }
+
+    // Meta API doesn't require authentication.
+    pub async fn fetch_hook_cidrs(&self) -> Result<Vec<IpNet>> {
+        let url = Url::parse(Self::GITHUB_API_URL)?.join("/meta")?;
         let req = self
             .http
             .request(Method::GET, url)
             .header("accept", "application/vnd.github+json")
-            .bearer_auth(token)
             .header("x-github-api-version", Self::GITHUB_API_VERSION)
             .header("user-agent", Self::OUR_USER_AGENT);
-        Ok(req.send().await?.json().await?)
+        let meta: Meta = req
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| "failed to parse GitHub meta API response")?;
+        Ok(meta.hooks)
     }
 
-    fn build(config: GithubApiConfig, credential: Credentials) -> Result<Self> {
+    fn build(
+        config: GithubApiConfig,
+        credential: Credentials,
+        app_key: Option<AppKey>,
+    ) -> Result<Self> {
+        let coalescer = config
+            .update_coalesce_interval
+            .map(|d| UpdateCoalescer::new(d.into()));
         let http = reqwest_client(config)?;
-        let inner = octorust::Client::custom(
-            concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")),
-            credential,
-            http.clone(),
-        );
-        // checks() clones the inner client so initializing it here to avoid cloning it multiple times.
+        let private_key = app_key.as_ref().map(|k| k.private_key.load());
+        let api = Api::new(&http, credential, private_key);
         Ok(Self {
-            checks: inner.checks(),
-            repos: inner.repos(),
+            api: ArcSwap::from_pointee(api),
+            app_key,
             http,
+            coalescer,
         })
     }
+
+    // Check runs of the ref in all pages. Filtered by the check name if non-empty.
+    async fn list_all_check_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
+        check_name: &str,
+        status: JobStatus,
+    ) -> Result<Vec<CheckRun>> {
+        let mut runs = Vec::new();
+        for page in 1.. {
+            let res = self
+                .api()?
+                .checks
+                .list_for_ref(
+                    owner,
+                    repo,
+                    git_ref,
+                    check_name,
+                    status.clone(),
+                    ActionsListJobsWorkflowRunFilter::All,
+                    100,
+                    page,
+                    0,
+                )
+                .await
+                .with_context(|| {
+                    format!("failed to list check_runs: owner={owner}, repo={repo}, ref={git_ref}")
+                })?;
+            let body = res.body;
+            let last = body.check_runs.is_empty();
+            runs.extend(body.check_runs);
+            if last || i64::try_from(runs.len())? >= body.total_count {
+                break;
+            }
+        }
+        Ok(runs)
+    }
+}
+
+// https://docs.github.com/en/rest/meta/meta?apiVersion=2022-11-28#get-github-meta-information
+#[derive(Debug, Deserialize)]
+struct Meta {
+    hooks: Vec<IpNet>,
 }
 
 #[async_trait]
@@ -113,7 +439,8 @@ impl GithubClient for OctorustClient {
             validate_text_length(&output.text)?;
         }
 
-        self.checks
+        self.api()?
+            .checks
             .create(owner, repo, input)
             .await
             .with_context(|| {
@@ -138,16 +465,209 @@ impl GithubClient for OctorustClient {
             validate_text_length(&output.text)?;
         }
 
-        self.checks
-            .update(owner, repo, check_run_id, input)
+        let checks = Arc::clone(&self.api()?.checks);
+        let (owner, repo) = (owner.to_owned(), repo.to_owned());
+        let send = move |input: ChecksUpdateRequest| async move {
+            checks
+                .update(&owner, &repo, check_run_id, &input)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to update check_run: owner={}, repo={}, id={}, ",
+                        owner, repo, check_run_id
+                    )
+                })
+                .map(|r| r.body)
+        };
+        match &self.coalescer {
+            Some(c) => c.update(check_run_id, input, send).await,
+            None => send(input.clone()).await,
+        }
+    }
+
+    async fn find_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        head_sha: &str,
+        name: &str,
+        external_id: &str,
+    ) -> Result<Option<CheckRun>> {
+        info!(owner, repo, name, external_id, "finding check run");
+        let runs = self
+            .list_all_check_runs(owner, repo, head_sha, name, JobStatus::Noop)
+            .await?;
+        Ok(runs.into_iter().find(|r| r.external_id == external_id))
+    }
+
+    async fn list_check_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
+        status: JobStatus,
+    ) -> Result<Vec<CheckRun>> {
+        info!(owner, repo, git_ref, "listing check runs");
+        self.list_all_check_runs(owner, repo, git_ref, "", status)
+            .await
+    }
+
+    async fn upsert_issue_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        issue_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> Result<()> {
+        info!(owner, repo, issue_number, "upserting issue comment");
+        let number = i64::try_from(issue_number)?;
+        let input = PullsUpdateReviewRequest {
+            body: format!("{marker}\n{body}"),
+        };
+        let api = self.api()?;
+        let comments = api
+            .issues
+            .list_all_comments(owner, repo, number, None)
+            .await
+            .with_context(|| {
+                format!("failed to list issue comments: owner={owner}, repo={repo}, number={issue_number}")
+            })?;
+        let own = comments
+            .body
+            .into_iter()
+            .find(|c| is_own_comment(c.user.as_ref().map(|u| u.type_.as_str()), &c.body, marker));
+        match own {
+            Some(c) => api
+                .issues
+                .update_comment(owner, repo, c.id, &input)
+                .await
+                .with_context(|| {
+                    format!("failed to update issue comment: owner={owner}, repo={repo}, id={}", c.id)
+                })
+                .map(|_| ()),
+            None => api
+                .issues
+                .create_comment(owner, repo, number, &input)
+                .await
+                .with_context(|| {
+                    format!("failed to create issue comment: owner={owner}, repo={repo}, number={issue_number}")
+                })
+                .map(|_| ()),
+        }
+    }
+
+    async fn create_deployment(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        input: &DeploymentInput,
+    ) -> Result<i64> {
+        info!(
+            owner,
+            repo,
+            environment = input.environment,
+            "creating deployment"
+        );
+        let res: DeploymentResponse = self
+            .api_request(
+                Method::POST,
+                &format!("/repos/{owner}/{repo}/deployments"),
+                token,
+            )?
+            .json(input)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
             .await
+            .with_context(|| format!("failed to create deployment: owner={owner}, repo={repo}"))?;
+        Ok(res.id)
+    }
+
+    async fn create_deployment_status(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        deployment_id: i64,
+        input: &DeploymentStatusInput,
+    ) -> Result<()> {
+        info!(owner, repo, deployment_id, state = ?input.state, "creating deployment status");
+        let path = format!("/repos/{owner}/{repo}/deployments/{deployment_id}/statuses");
+        self.api_request(Method::POST, &path, token)?
+            .json(input)
+            .send()
+            .await?
+            .error_for_status()
             .with_context(|| {
-                format!(
-                    "failed to update check_run: owner={}, repo={}, id={}, ",
-                    owner, repo, check_run_id
-                )
-            })
-            .map(|r| r.body)
+                format!("failed to create deployment status: owner={owner}, repo={repo}, id={deployment_id}")
+            })?;
+        Ok(())
+    }
+
+    async fn get_pull_request(
+        &self,
+        owner: &str,
+        repo: &str,
+        number: u64,
+    ) -> Result<PullRequestRefs> {
+        info!(owner, repo, number, "getting pull request");
+        let pr = self
+            .api()?
+            .pulls
+            .get(owner, repo, i64::try_from(number)?)
+            .await
+            .with_context(|| {
+                format!("failed to get pull request: owner={owner}, repo={repo}, number={number}")
+            })?
+            .body;
+        Ok(PullRequestRefs {
+            head_sha: pr.head.sha,
+            head_ref: pr.head.ref_,
+            base_sha: pr.base.sha,
+            base_ref: pr.base.ref_,
+        })
+    }
+
+    async fn get_collaborator_permission(
+        &self,
+        owner: &str,
+        repo: &str,
+        user: &str,
+    ) -> Result<Permission> {
+        info!(owner, repo, user, "getting collaborator permission");
+        let res = self
+            .api()?
+            .repos
+            .get_collaborator_permission_level(owner, repo, user)
+            .await
+            .with_context(|| {
+                format!("failed to get collaborator permission: owner={owner}, repo={repo}, user={user}")
+            })?;
+        Ok(Permission::from_api(&res.body.permission))
+    }
+
+    async fn create_reaction(
+        &self,
+        token: &str,
+        owner: &str,
+        repo: &str,
+        comment_id: i64,
+        content: ReactionContent,
+    ) -> Result<()> {
+        info!(owner, repo, comment_id, ?content, "creating reaction");
+        let path = format!("/repos/{owner}/{repo}/issues/comments/{comment_id}/reactions");
+        self.api_request(Method::POST, &path, token)?
+            .json(&ReactionInput { content })
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| {
+                format!("failed to create reaction: owner={owner}, repo={repo}, comment_id={comment_id}")
+            })?;
+        Ok(())
     }
 }
 
@@ -177,6 +697,97 @@ impl GithubClient for NullClient {
     ) -> Result<CheckRun> {
         Ok(empty_checkrun())
     }
+
+    async fn find_check_run(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _head_sha: &str,
+        _name: &str,
+        _external_id: &str,
+    ) -> Result<Option<CheckRun>> {
+        Ok(None)
+    }
+
+    async fn list_check_runs(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _git_ref: &str,
+        _status: JobStatus,
+    ) -> Result<Vec<CheckRun>> {
+        Ok(Vec::new())
+    }
+
+    async fn upsert_issue_comment(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _issue_number: u64,
+        _marker: &str,
+        _body: &str,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_deployment(
+        &self,
+        _token: &str,
+        _owner: &str,
+        _repo: &str,
+        _input: &DeploymentInput,
+    ) -> Result<i64> {
+        Ok(0)
+    }
+
+    async fn create_deployment_status(
+        &self,
+        _token: &str,
+        _owner: &str,
+        _repo: &str,
+        _deployment_id: i64,
+        _input: &DeploymentStatusInput,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_pull_request(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _number: u64,
+    ) -> Result<PullRequestRefs> {
+        Ok(PullRequestRefs::default())
+    }
+
+    async fn get_collaborator_permission(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _user: &str,
+    ) -> Result<Permission> {
+        Ok(Permission::None)
+    }
+
+    async fn create_reaction(
+        &self,
+        _token: &str,
+        _owner: &str,
+        _repo: &str,
+        _comment_id: i64,
+        _content: ReactionContent,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Suffix the check run name with the environment label, e.g. `run-lint [staging]`. Not suffixed if empty.
+pub fn name_with_environment(name: &str, environment: &str) -> String {
+    if environment.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{name} [{environment}]")
+    }
 }
 
 pub fn into_update_request(r: ChecksCreateRequest) -> ChecksUpdateRequest {
@@ -227,9 +838,144 @@ pub const fn empty_checkrun() -> CheckRun {
     }
 }
 
+async fn request_repo(
+    http: &ClientWithMiddleware,
+    base: &Url,
+    token: &str,
+    owner: &str,
+    repo: &str,
+) -> Result<GithubRepository> {
+    let url = base.join(&format!("/repos/{owner}/{repo}"))?;
+    let req = http
+        .request(Method::GET, url)
+        .header("accept", "application/vnd.github+json")
+        .bearer_auth(token)
+        .header("x-github-api-version", OctorustClient::GITHUB_API_VERSION)
+        .header("user-agent", OctorustClient::OUR_USER_AGENT);
+    let res = req.send().await?.error_for_status()?;
+    res.json()
+        .await
+        .with_context(|| "failed to parse repository response")
+}
+
+// Comments written by the app start with the marker. Others may contain it too, e.g. by quoting the comment.
+fn is_own_comment(user_type: Option<&str>, body: &str, marker: &str) -> bool {
+    user_type == Some("Bot") && body.starts_with(marker)
+}
+
 fn validate_text_length(text: &str) -> Result<()> {
     if text.len() > 65535 {
         bail!("text length must be less than 65536 characters");
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+    use clap::Parser;
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value};
+    use tokio::{net::TcpListener, spawn};
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        config: GithubApiConfig,
+    }
+
+    async fn flaky_repo(State(calls): State<Arc<AtomicUsize>>) -> Result<Json<Value>, StatusCode> {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+        Ok(Json(json!({
+            "full_name": "owner/repo",
+            "name": "repo",
+            "private": false,
+            "owner": { "login": "owner" },
+            "custom_properties": {},
+        })))
+    }
+
+    #[test]
+    fn permission() {
+        assert_eq!(Permission::from_api("maintain"), Permission::Write);
+        assert_eq!(Permission::from_api("triage"), Permission::Read);
+        assert_eq!(Permission::from_api("none"), Permission::None);
+        assert!(Permission::Admin > Permission::Write);
+        assert!(Permission::Read < Permission::Write);
+    }
+
+    #[test]
+    fn own_comment() {
+        let marker = "<!-- orgu: run-lint -->";
+        let body = format!("{marker}\nCommand failed");
+        assert!(is_own_comment(Some("Bot"), &body, marker));
+        assert!(!is_own_comment(Some("User"), &body, marker));
+        assert!(!is_own_comment(None, &body, marker));
+        // Quoted in a reply.
+        assert!(!is_own_comment(Some("Bot"), &format!("> {body}"), marker));
+    }
+
+    #[test]
+    fn environment_suffix() {
+        assert_eq!(name_with_environment("run-lint", ""), "run-lint");
+        assert_eq!(
+            name_with_environment("run-lint", "staging"),
+            "run-lint [staging]"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_repo_retries_transient_error() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route("/repos/:owner/:repo", get(flaky_repo))
+            .with_state(Arc::clone(&calls));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        spawn(async move { axum::serve(listener, app).await });
+
+        let args = Args::parse_from([
+            "test",
+            "--github-min-retry-interval",
+            "1ms",
+            "--github-max-retry-interval",
+            "1ms",
+        ]);
+        let http = reqwest_client(args.config).unwrap();
+        let repo = request_repo(&http, &base, "token", "owner", "repo")
+            .await
+            .unwrap();
+        assert_eq!(repo.full_name, "owner/repo");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn refreshed_private_key() {
+        let private_key = RefreshableSecret::new("old".to_owned());
+        let app_key = AppKey {
+            app_id: 1,
+            installation_id: 2,
+            private_key: private_key.clone(),
+        };
+        let config = Args::parse_from(["test"]).config;
+        let client = OctorustClient::build(
+            config,
+            Credentials::Token("token".to_owned()),
+            Some(app_key),
+        )
+        .unwrap();
+        let api = client.api().unwrap();
+        assert!(Arc::ptr_eq(&api, &client.api().unwrap()));
+
+        // The swapped key reaches the credentials, failing to parse this invalid key.
+        private_key.store("new".to_owned());
+        let e = client.api().err().unwrap();
+        assert_eq!(e.to_string(), "failed to parse GitHub private key");
+    }
+}
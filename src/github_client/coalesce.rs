@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::{BoxFuture, FutureExt as _, Shared};
+use octorust::types::{CheckRun, ChecksUpdateRequest};
+use tokio::time::sleep;
+use tracing::debug;
+
+// anyhow::Error isn't Clone, so errors are shared as formatted strings.
+type SharedFlush = Shared<BoxFuture<'static, Result<CheckRun, String>>>;
+type PendingMap = Mutex<HashMap<i64, Pending>>;
+
+struct Pending {
+    input: ChecksUpdateRequest,
+    // Set when the flush took `input` and is calling the API. Later updates wait for it and start a new window.
+    sending: bool,
+    flush: SharedFlush,
+}
+
+impl fmt::Debug for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pending")
+            .field("sending", &self.sending)
+            .finish_non_exhaustive()
+    }
+}
+
+enum Enqueued<F> {
+    // Queued in the current window.
+    Joined(SharedFlush),
+    // A terminal update with nothing queued, send it now.
+    Immediate(F),
+    // An update is being sent, the caller gets `send` back to retry after it.
+    InFlight(SharedFlush, F),
+}
+
+/// Collapses check run updates for the same check run queued within `interval` into the latest one. All callers in
+/// a window get the result of the collapsed update. Updates with a conclusion are never replaced by updates without
+/// one, and are sent immediately if nothing is queued.
+#[derive(Debug, Clone)]
+pub struct UpdateCoalescer {
+    interval: Duration,
+    pending: Arc<PendingMap>,
+}
+
+impl UpdateCoalescer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: Arc::default(),
+        }
+    }
+
+    pub async fn update<F, Fut>(
+        &self,
+        check_run_id: i64,
+        input: &ChecksUpdateRequest,
+        send: F,
+    ) -> Result<CheckRun>
+    where
+        F: FnOnce(ChecksUpdateRequest) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<CheckRun>> + Send + 'static,
+    {
+        let mut send = send;
+        loop {
+            match self.enqueue(check_run_id, input, send) {
+                Enqueued::Joined(flush) => return flush.await.map_err(|e| anyhow!(e)),
+                Enqueued::Immediate(send) => return send(input.clone()).await,
+                Enqueued::InFlight(flush, s) => {
+                    // The result is for the earlier update, wait for it then queue ours in a new window.
+                    drop(flush.await);
+                    send = s;
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::significant_drop_tightening)] // Check and insert under the same lock.
+    fn enqueue<F, Fut>(
+        &self,
+        check_run_id: i64,
+        input: &ChecksUpdateRequest,
+        send: F,
+    ) -> Enqueued<F>
+    where
+        F: FnOnce(ChecksUpdateRequest) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<CheckRun>> + Send + 'static,
+    {
+        let terminal = input.conclusion.is_some();
+        let mut pending = lock(&self.pending);
+        match pending.get_mut(&check_run_id) {
+            Some(p) if p.sending => Enqueued::InFlight(p.flush.clone(), send),
+            Some(p) => {
+                if terminal || p.input.conclusion.is_none() {
+                    p.input = input.clone();
+                }
+                Enqueued::Joined(p.flush.clone())
+            }
+            None if terminal => Enqueued::Immediate(send),
+            None => {
+                let flush = self.flush(check_run_id, send).boxed().shared();
+                pending.insert(
+                    check_run_id,
+                    Pending {
+                        input: input.clone(),
+                        sending: false,
+                        flush: flush.clone(),
+                    },
+                );
+                Enqueued::Joined(flush)
+            }
+        }
+    }
+
+    fn flush<F, Fut>(
+        &self,
+        check_run_id: i64,
+        send: F,
+    ) -> impl Future<Output = Result<CheckRun, String>> + Send + 'static
+    where
+        F: FnOnce(ChecksUpdateRequest) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<CheckRun>> + Send + 'static,
+    {
+        let pending = Arc::clone(&self.pending);
+        let interval = self.interval;
+        async move {
+            sleep(interval).await;
+            let input = lock(&pending).get_mut(&check_run_id).map(|p| {
+                p.sending = true;
+                p.input.clone()
+            });
+            let Some(input) = input else {
+                return Err(format!("no pending update: check_run_id={check_run_id}"));
+            };
+            debug!(check_run_id, "sending coalesced check run update");
+            let res = send(input).await.map_err(|e| format!("{e:#}"));
+            lock(&pending).remove(&check_run_id);
+            res
+        }
+    }
+}
+
+// The map is consistent on panic, so ignore poisoning.
+fn lock(m: &PendingMap) -> MutexGuard<'_, HashMap<i64, Pending>> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::future::join_all;
+    use octorust::types::{ChecksCreateRequestConclusion, ChecksUpdateRequestOutput};
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::github_client::empty_checkrun;
+
+    fn input(
+        title: &str,
+        conclusion: Option<ChecksCreateRequestConclusion>,
+    ) -> ChecksUpdateRequest {
+        ChecksUpdateRequest {
+            name: String::new(),
+            status: None,
+            conclusion,
+            output: Some(ChecksUpdateRequestOutput {
+                title: title.to_owned(),
+                summary: String::new(),
+                text: String::new(),
+                annotations: Vec::new(),
+                images: Vec::new(),
+            }),
+            actions: Vec::new(),
+            completed_at: None,
+            started_at: None,
+            details_url: String::new(),
+            external_id: String::new(),
+        }
+    }
+
+    // Record sent titles as the check run name to assert which update won.
+    async fn update(
+        c: &UpdateCoalescer,
+        calls: &Arc<AtomicUsize>,
+        input: ChecksUpdateRequest,
+    ) -> Result<CheckRun> {
+        let calls = Arc::clone(calls);
+        c.update(1, &input, move |i| async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let mut run = empty_checkrun();
+            run.name = i.output.map(|o| o.title).unwrap_or_default();
+            Ok(run)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn coalesce_to_latest() {
+        let c = UpdateCoalescer::new(Duration::from_millis(10));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let res = join_all(["a", "b", "c"].map(|t| update(&c, &calls, input(t, None)))).await;
+        let names: Vec<_> = res.into_iter().map(|r| r.unwrap().name).collect();
+        assert_eq!(names, vec!["c", "c", "c"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn keep_terminal() {
+        let c = UpdateCoalescer::new(Duration::from_millis(10));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let res = join_all([
+            update(&c, &calls, input("running", None)),
+            update(
+                &c,
+                &calls,
+                input("done", Some(ChecksCreateRequestConclusion::Success)),
+            ),
+            update(&c, &calls, input("late", None)),
+        ])
+        .await;
+        let names: Vec<_> = res.into_iter().map(|r| r.unwrap().name).collect();
+        assert_eq!(names, vec!["done", "done", "done"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Terminal update without queued updates is sent immediately.
+        let res = update(
+            &c,
+            &calls,
+            input("done", Some(ChecksCreateRequestConclusion::Failure)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.name, "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
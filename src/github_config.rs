@@ -1,9 +1,13 @@
-use anyhow::Result;
-use clap::{Args, ValueEnum};
+use anyhow::{Context as _, Result};
+use clap::{ArgAction, Args, ValueEnum};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, Jitter, RetryTransientMiddleware};
+use serde::{Serialize, Serializer};
 
-#[derive(Debug, Args, Clone)]
+use crate::config_serde::{display, display_opt, redact, REDACTED};
+
+#[derive(Debug, Args, Clone, Serialize)]
 pub struct GithubAppConfig {
     /// GitHub App ID.
     #[arg(env = "GITHUB_APP_ID", long)]
@@ -13,26 +17,35 @@ pub struct GithubAppConfig {
     pub installation_id: i64,
     /// GitHub App private key.
     #[arg(env = "GITHUB_PRIVATE_KEY", hide_env_values = true, long)]
+    #[serde(serialize_with = "redact")]
     pub private_key: String,
+    /// Interval to re-fetch the private key when it's given as `ssm://` value. Disabled if none.
+    #[arg(env, long)]
+    #[serde(serialize_with = "display_opt")]
+    pub private_key_refresh_interval: Option<humantime::Duration>,
 }
 
 // Default retry config is from retry-policies crate except for retry.
-#[derive(Debug, Args, Clone)]
+#[derive(Debug, Args, Clone, Serialize)]
 pub struct GithubApiConfig {
     /// Connect timeout for GitHub API requests.
     #[arg(env, long, default_value = "1s")]
+    #[serde(serialize_with = "display")]
     pub github_connect_timeout: humantime::Duration,
     /// Read timeout for GitHub API requests. Currently applied from connect to read operation.
     #[arg(env, long, default_value = "10s")]
+    #[serde(serialize_with = "display")]
     pub github_read_timeout: humantime::Duration,
     /// Number of retries for GitHub API requests.
     #[arg(env, long, default_value = "3")]
     pub github_max_retry: u32,
     /// Minimum interval between retries.
     #[arg(env, long, default_value = "1s")]
+    #[serde(serialize_with = "display")]
     pub github_min_retry_interval: humantime::Duration,
     /// Maximum interval between retries.
     #[arg(env, long, default_value = "5m")]
+    #[serde(serialize_with = "display")]
     pub github_max_retry_interval: humantime::Duration,
     /// Jitter configuration for retry interval.
     #[arg(env, long, default_value = "full")]
@@ -40,9 +53,57 @@ pub struct GithubApiConfig {
     /// Base for exponential backoff.
     #[arg(env, long, default_value = "2")]
     pub github_retry_base: u32,
+    /// Timeout for idle sockets in the connection pool being kept-alive.
+    #[arg(env, long, default_value = "90s")]
+    #[serde(serialize_with = "display")]
+    pub github_pool_idle_timeout: humantime::Duration,
+    /// Maximum idle connections per host in the connection pool. Unlimited if none.
+    #[arg(env, long)]
+    pub github_pool_max_idle_per_host: Option<usize>,
+    /// Use HTTP/2 without negotiation via ALPN.
+    #[arg(env, long, default_value = "false")]
+    pub github_http2_prior_knowledge: bool,
+    /// Accept gzip compressed responses from GitHub. Request bodies are never compressed as GitHub doesn't accept them.
+    #[arg(env, long, default_value = "true", action = ArgAction::Set)]
+    pub github_gzip: bool,
+    /// Collapse check run updates for the same check run within this interval into the latest one to reduce API
+    /// calls. Updates with a conclusion are never dropped. Disabled if none.
+    #[arg(env, long)]
+    #[serde(serialize_with = "display_opt")]
+    pub update_coalesce_interval: Option<humantime::Duration>,
+    /// Extra HTTP header added to every GitHub request as `name:value`, e.g. for a proxy in front of GitHub. Can be
+    /// passed multiple times.
+    #[arg(env, long = "github-header", value_parser = parse_github_header)]
+    pub github_headers: Vec<GithubHeader>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GithubHeader {
+    name: HeaderName,
+    value: HeaderValue,
 }
 
-#[derive(Debug, Clone, ValueEnum)]
+// Values can be credentials of the proxy.
+impl Serialize for GithubHeader {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(&format_args!("{}:{REDACTED}", self.name))
+    }
+}
+
+fn parse_github_header(s: &str) -> Result<GithubHeader> {
+    let (name, value) = s
+        .split_once(':')
+        .with_context(|| "invalid header: no `:` found, expected `name:value`")?;
+    let name = HeaderName::try_from(name.trim())
+        .with_context(|| format!("invalid header name: {name}"))?;
+    let mut value = HeaderValue::try_from(value.trim())
+        .with_context(|| format!("invalid header value: name={name}"))?;
+    value.set_sensitive(true);
+    Ok(GithubHeader { name, value })
+}
+
+#[derive(Debug, Clone, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum JitterConfig {
     /// Don't apply any jitter.
     None,
@@ -63,11 +124,27 @@ impl From<JitterConfig> for Jitter {
 }
 
 pub fn reqwest_client(config: GithubApiConfig) -> Result<ClientWithMiddleware> {
-    let http = reqwest::Client::builder()
+    let mut builder = reqwest::Client::builder()
         .connect_timeout(config.github_connect_timeout.into())
         // XXX: update octorust to use latest reqwest-middleware then use read_timeout.
         .timeout(config.github_read_timeout.into())
-        .build()?;
+        .pool_idle_timeout(Some(config.github_pool_idle_timeout.into()))
+        .gzip(config.github_gzip);
+    if let Some(n) = config.github_pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(n);
+    }
+    if config.github_http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if !config.github_headers.is_empty() {
+        let headers: HeaderMap = config
+            .github_headers
+            .into_iter()
+            .map(|h| (h.name, h.value))
+            .collect();
+        builder = builder.default_headers(headers);
+    }
+    let http = builder.build()?;
     let retry_policy = ExponentialBackoff::builder()
         .jitter(config.github_retry_jitter.into())
         .base(config.github_retry_base)
@@ -81,3 +158,25 @@ pub fn reqwest_client(config: GithubApiConfig) -> Result<ClientWithMiddleware> {
         .with(RetryTransientMiddleware::new_with_policy(retry_policy))
         .build())
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn github_header() {
+        let h = parse_github_header("X-Proxy-Auth: secret").unwrap();
+        assert_eq!(h.name, "x-proxy-auth");
+        assert_eq!(h.value, "secret");
+        assert_eq!(
+            serde_json::to_value(&h).unwrap(),
+            serde_json::json!("x-proxy-auth:(redacted)")
+        );
+
+        parse_github_header("no-colon").unwrap_err();
+        parse_github_header("bad name:v").unwrap_err();
+        parse_github_header("x-bad-value:a\nb").unwrap_err();
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use anyhow::{bail, Context as _, Result};
 use chrono::{Duration, Utc};
 use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
@@ -8,7 +10,10 @@ use serde_json::Value;
 use tracing::debug;
 use url::Url;
 
-use crate::github_config::{reqwest_client, GithubApiConfig, GithubAppConfig};
+use crate::{
+    github_config::{reqwest_client, GithubApiConfig, GithubAppConfig},
+    ssmenv::RefreshableSecret,
+};
 
 #[derive(Debug, Serialize)]
 struct Claims {
@@ -21,12 +26,21 @@ struct Claims {
 #[derive(Debug, Deserialize)]
 struct InstallationAccessTokenResponse {
     token: String,
+    // e.g. `{"checks": "write", "contents": "read"}`.
+    #[serde(default)]
+    permissions: HashMap<String, String>,
 }
 
+// Futures are not required to be Send. See `future_not_send` in Cargo.toml.
+#[allow(async_fn_in_trait)]
 #[allow(clippy::indexing_slicing)]
 #[cfg_attr(test, mockall::automock)]
 pub trait TokenFetcher {
     async fn fetch_token(&self) -> Result<String>;
+    /// Permissions granted to the token, e.g. `checks` to `write`.
+    async fn fetch_permissions(&self) -> Result<HashMap<String, String>> {
+        bail!("fetching token permissions is not supported")
+    }
 }
 
 // ClientWithMiddleware can be cloned, it's like Arc::clone.
@@ -34,6 +48,7 @@ pub trait TokenFetcher {
 pub struct DefaultTokenFetcher {
     client: ClientWithMiddleware,
     config: GithubAppConfig,
+    private_key: RefreshableSecret,
 }
 
 const GITHUB_API_URL: &str = "https://api.github.com";
@@ -44,19 +59,32 @@ impl TokenFetcher for DefaultTokenFetcher {
     async fn fetch_token(&self) -> Result<String> {
         self.do_fetch_token().await
     }
+
+    async fn fetch_permissions(&self) -> Result<HashMap<String, String>> {
+        Ok(self.fetch_token_response().await?.permissions)
+    }
 }
 
 impl DefaultTokenFetcher {
     pub fn new(config: GithubApiConfig, app: GithubAppConfig) -> Result<Self> {
+        let private_key = RefreshableSecret::new(app.private_key.clone());
+        if let Some(interval) = app.private_key_refresh_interval {
+            private_key.spawn_refresh("GITHUB_PRIVATE_KEY", interval.into());
+        }
         Ok(Self {
             client: reqwest_client(config)?,
             config: app,
+            private_key,
         })
     }
 
     /// Fetch installation access token from GitHub App private key.
     /// Use this method before making actual API requests to GitHub.
     pub async fn do_fetch_token(&self) -> Result<String> {
+        Ok(self.fetch_token_response().await?.token)
+    }
+
+    async fn fetch_token_response(&self) -> Result<InstallationAccessTokenResponse> {
         let id = self.config.installation_id;
         let jwt = self.jwt()?;
 
@@ -71,8 +99,9 @@ impl DefaultTokenFetcher {
                 String::from_utf8_lossy(&body)
             );
         }
-        let r = serde_json::from_slice::<InstallationAccessTokenResponse>(&body)?;
-        Ok(r.token)
+        Ok(serde_json::from_slice::<InstallationAccessTokenResponse>(
+            &body,
+        )?)
     }
 
     fn jwt(&self) -> Result<String> {
@@ -87,7 +116,7 @@ impl DefaultTokenFetcher {
             ),
         };
         let header = Header::new(Algorithm::RS256);
-        let key = EncodingKey::from_rsa_pem(self.config.private_key.as_bytes())
+        let key = EncodingKey::from_rsa_pem(self.private_key.load().as_bytes())
             .with_context(|| "failed to parse GitHub private key")?;
         Ok(encode(&header, &claims, &key)?)
     }
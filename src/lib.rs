@@ -1,14 +1,21 @@
 pub mod cli;
 pub mod events;
+pub mod runner;
 
 mod app_error;
+mod build_info;
 mod checkout;
+mod config_serde;
 mod event_queue_client;
 mod front;
 mod github_client;
 mod github_config;
 mod github_token;
 mod github_verifier;
-mod runner;
+mod relay_signature;
 mod ssmenv;
+mod tls;
 mod trace;
+
+/// GitHub webhook payload types and conversions into `events::CheckRequest`.
+pub use front::github_events;
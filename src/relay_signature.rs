@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, Utc};
+use clap::Args;
+use hex::encode;
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// Signing of requests relayed from front to the runner server, shared by both sides.
+#[derive(Debug, Clone, Args)]
+pub struct RelaySignatureConfig {
+    /// Secret to sign relayed events with HMAC-SHA256. Front signs events sent to `--event-queue-relay-endpoint`, and
+    /// the runner server rejects unsigned, tampered or replayed requests. Set the same value to both. Disabled if
+    /// none.
+    #[arg(long, env, hide_env_values = true)]
+    relay_secret: Option<String>,
+    /// Header of the relay signature, `sha256=<hex>` of HMAC over `<timestamp>.<body>`.
+    #[arg(long, env, default_value = "x-orgu-signature-256")]
+    relay_signature_header: HeaderName,
+    /// Header of the unix timestamp in seconds when the relay request is signed.
+    #[arg(long, env, default_value = "x-orgu-timestamp")]
+    relay_timestamp_header: HeaderName,
+}
+
+impl RelaySignatureConfig {
+    pub fn build(self) -> Option<RelaySignature> {
+        let secret = self.relay_secret.filter(|s| !s.is_empty())?;
+        Some(RelaySignature {
+            secret,
+            signature_header: self.relay_signature_header,
+            timestamp_header: self.relay_timestamp_header,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelaySignature {
+    secret: String,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+}
+
+impl RelaySignature {
+    /// Headers to sign the body at the time. The timestamp is signed together to detect replays.
+    pub fn headers(
+        &self,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<[(HeaderName, HeaderValue); 2]> {
+        let timestamp = now.timestamp();
+        let signature = self.sign(timestamp, body)?;
+        Ok([
+            (self.timestamp_header.clone(), HeaderValue::from(timestamp)),
+            (
+                self.signature_header.clone(),
+                HeaderValue::try_from(signature)?,
+            ),
+        ])
+    }
+
+    /// Verify the signature and that the timestamp is within `max_skew` from now, in either direction.
+    pub fn verify(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: DateTime<Utc>,
+        max_skew: Duration,
+    ) -> Result<()> {
+        let header = |name: &HeaderName| {
+            headers
+                .get(name)
+                .with_context(|| format!("missing {name} header field"))?
+                .to_str()
+                .with_context(|| format!("invalid {name} header field"))
+        };
+        let timestamp = header(&self.timestamp_header)?
+            .parse::<i64>()
+            .with_context(|| format!("invalid {} header field", self.timestamp_header))?;
+        let skew = now.timestamp().abs_diff(timestamp);
+        if skew > max_skew.as_secs() {
+            bail!("timestamp is out of the allowed skew: timestamp={timestamp}, skew={skew}s");
+        }
+
+        let signature = header(&self.signature_header)?;
+        let computed = self.sign(timestamp, body)?;
+        // Into bool will be false if it's ok, so we need to negate it.
+        let choice = !computed.as_bytes().ct_eq(signature.as_bytes());
+        if choice.into() {
+            bail!("signature mismatch: signature={signature}");
+        }
+        Ok(())
+    }
+
+    fn sign(&self, timestamp: i64, body: &[u8]) -> Result<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .with_context(|| "HMAC creation failed")?;
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        Ok(format!("sha256={}", encode(mac.finalize().into_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_SKEW: Duration = Duration::from_secs(300);
+
+    fn signature() -> RelaySignature {
+        RelaySignatureConfig {
+            relay_secret: Some("test_secret".to_owned()),
+            relay_signature_header: HeaderName::from_static("x-orgu-signature-256"),
+            relay_timestamp_header: HeaderName::from_static("x-orgu-timestamp"),
+        }
+        .build()
+        .unwrap()
+    }
+
+    fn signed(body: &[u8], at: DateTime<Utc>) -> HeaderMap {
+        signature().headers(body, at).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn fresh() {
+        let now = Utc::now();
+        let headers = signed(b"{}", now - chrono::Duration::seconds(10));
+        signature().verify(&headers, b"{}", now, MAX_SKEW).unwrap();
+    }
+
+    #[test]
+    fn expired() {
+        let now = Utc::now();
+        let s = signature();
+        let headers = signed(b"{}", now - chrono::Duration::seconds(301));
+        s.verify(&headers, b"{}", now, MAX_SKEW).unwrap_err();
+        let headers = signed(b"{}", now + chrono::Duration::seconds(301));
+        s.verify(&headers, b"{}", now, MAX_SKEW).unwrap_err();
+    }
+
+    #[test]
+    fn tampered() {
+        let now = Utc::now();
+        let s = signature();
+        let headers = signed(b"{}", now);
+        s.verify(&headers, br#"{"a":1}"#, now, MAX_SKEW)
+            .unwrap_err();
+
+        // Refreshing the timestamp invalidates the signature.
+        let mut headers = signed(b"{}", now - chrono::Duration::seconds(3600));
+        headers.insert("x-orgu-timestamp", HeaderValue::from(now.timestamp()));
+        s.verify(&headers, b"{}", now, MAX_SKEW).unwrap_err();
+
+        s.verify(&HeaderMap::new(), b"{}", now, MAX_SKEW)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn disabled_if_empty() {
+        let config = RelaySignatureConfig {
+            relay_secret: Some(String::new()),
+            relay_signature_header: HeaderName::from_static("x-orgu-signature-256"),
+            relay_timestamp_header: HeaderName::from_static("x-orgu-timestamp"),
+        };
+        assert!(config.build().is_none());
+    }
+}
@@ -1,4 +1,25 @@
 pub mod cli;
+pub mod handler;
 
-mod handler;
+mod active_jobs;
+mod annotations;
+mod audit;
+mod builder;
+mod executor;
 mod hanlder_view;
+mod redelivery;
+
+pub use builder::{DefaultHandler, RunnerBuilder};
+pub use executor::{CommandExecutor, ExecResult, LocalExecutor};
+pub use handler::{Config, Handler, Matrix};
+pub use hanlder_view::ViewConfig;
+
+pub use crate::{
+    checkout::{
+        Checkout, CheckoutConfig, CheckoutError, CheckoutInput, CommitInfo, Libgit2Checkout,
+        ShallowSince, Submodules, WorkDir,
+    },
+    github_client::{GithubClient, OctorustClient},
+    github_config::{GithubApiConfig, GithubAppConfig, JitterConfig},
+    github_token::{DefaultTokenFetcher, TokenFetcher},
+};
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+
+use crate::events::CheckRequest;
+
+/// Jobs running in this process by repository, pull request and job name, to cancel the obsolete one when a newer
+/// event of the same pull request arrives. Not shared between processes.
+#[derive(Debug, Default)]
+pub struct ActiveJobs {
+    jobs: Mutex<Jobs>,
+}
+
+#[derive(Debug, Default)]
+struct Jobs {
+    active: HashMap<JobKey, (u64, Sender<()>)>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct JobKey {
+    repository: String,
+    pull_request_number: u64,
+    job_name: String,
+}
+
+impl ActiveJobs {
+    /// Register the job of the event, cancelling the older job of the same key if running. None if the event isn't
+    /// associated with a pull request.
+    pub fn register(&self, req: &CheckRequest, job_name: &str) -> Option<ActiveJob<'_>> {
+        let key = JobKey {
+            repository: req.repository.full_name.clone(),
+            pull_request_number: req.pull_request_number?,
+            job_name: job_name.to_owned(),
+        };
+        let (tx, rx) = channel();
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = jobs.next_id;
+        jobs.next_id = id.wrapping_add(1);
+        if let Some((_, older)) = jobs.active.insert(key.clone(), (id, tx)) {
+            // The older job may have just completed and dropped the receiver, which is fine.
+            older.send(()).unwrap_or_default();
+        }
+        Some(ActiveJob {
+            registry: self,
+            key,
+            id,
+            cancelled: rx,
+        })
+    }
+}
+
+/// Registration of a running job. Unregistered on drop unless replaced by a newer job.
+#[derive(Debug)]
+pub struct ActiveJob<'jobs> {
+    registry: &'jobs ActiveJobs,
+    key: JobKey,
+    id: u64,
+    cancelled: Receiver<()>,
+}
+
+impl ActiveJob<'_> {
+    /// Resolves when a newer job of the same key is registered.
+    pub async fn cancelled(&mut self) {
+        // The sender is kept while registered and only consumed to cancel.
+        (&mut self.cancelled).await.unwrap_or_default();
+    }
+}
+
+impl Drop for ActiveJob<'_> {
+    fn drop(&mut self) {
+        let mut jobs = self
+            .registry
+            .jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if jobs
+            .active
+            .get(&self.key)
+            .is_some_and(|(id, _)| *id == self.id)
+        {
+            jobs.active.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::{timeout, Duration};
+
+    use super::*;
+
+    fn req(pull_request_number: Option<u64>) -> CheckRequest {
+        CheckRequest {
+            pull_request_number,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn register() {
+        let jobs = ActiveJobs::default();
+        assert!(jobs.register(&req(None), "lint").is_none());
+
+        let mut older = jobs.register(&req(Some(1)), "lint").unwrap();
+        let mut other = jobs.register(&req(Some(2)), "lint").unwrap();
+        let _newer = jobs.register(&req(Some(1)), "lint").unwrap();
+        timeout(Duration::from_secs(1), older.cancelled())
+            .await
+            .unwrap();
+        timeout(Duration::from_millis(10), other.cancelled())
+            .await
+            .unwrap_err();
+
+        // Dropping the cancelled job doesn't unregister the newer one.
+        drop(older);
+        assert_eq!(jobs.jobs.lock().unwrap().active.len(), 2);
+        drop(other);
+        assert_eq!(jobs.jobs.lock().unwrap().active.len(), 1);
+    }
+}
@@ -0,0 +1,312 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use anyhow::{bail, Context as _, Result};
+use clap::{Args, ValueEnum};
+use octorust::types::{AnnotationLevel, ChecksCreateRequestOutputAnnotations};
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Default, Args, Serialize)]
+pub struct AnnotationConfig {
+    /// Parse annotations from stdout of the command in this format and attach them to the check run. Lines not in
+    /// the format are ignored, so the command can print logs too.
+    #[clap(long, env, value_enum, default_value = "none")]
+    pub annotation_format: AnnotationFormat,
+    /// Levels of the annotations by the severity reported by the tool, as a JSON object of severities to `notice`,
+    /// `warning` or `failure`, e.g. `{"error":"failure","warn":"warning","info":"notice"}`. Severities are matched
+    /// case-insensitively. Unmapped ones are `failure` for `error`, `notice` for `info` and `note`, or `warning`.
+    #[clap(long, env, value_parser = parse_level_map)]
+    pub annotation_level_map: Option<LevelMap>,
+    /// Conclude the check run as failure when any annotation at or above this level is reported, even if the command
+    /// exited successfully. Disabled if none.
+    #[clap(long, env, value_enum)]
+    pub annotations_fail_on_level: Option<Level>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationFormat {
+    /// Don't parse annotations.
+    #[default]
+    None,
+    /// JSON lines of the reviewdog diagnostic format, e.g. the input of `reviewdog -f=rdjsonl`.
+    Rdjsonl,
+}
+
+/// Level of a check run annotation, in ascending order.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize, Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Level {
+    Notice,
+    Warning,
+    Failure,
+}
+
+impl From<Level> for AnnotationLevel {
+    fn from(v: Level) -> Self {
+        match v {
+            Level::Notice => Self::Notice,
+            Level::Warning => Self::Warning,
+            Level::Failure => Self::Failure,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LevelMap(BTreeMap<String, Level>);
+
+fn parse_level_map(s: &str) -> Result<LevelMap> {
+    let map = serde_json::from_str::<BTreeMap<String, Level>>(s).with_context(|| {
+        format!(
+            "invalid annotation level map: expected a JSON object of severities to levels: `{s}`"
+        )
+    })?;
+    if map.keys().any(String::is_empty) {
+        bail!("invalid annotation level map: empty key: `{s}`");
+    }
+    Ok(LevelMap(
+        map.into_iter()
+            .map(|(k, v)| (k.to_lowercase(), v))
+            .collect(),
+    ))
+}
+
+// Checks API accepts up to 50 annotations per request.
+const MAX_ANNOTATIONS: usize = 50;
+
+/// Annotations parsed from the command output.
+#[derive(Debug, Default)]
+pub struct Annotations {
+    /// Higher levels first, up to the limit of the Checks API.
+    pub items: Vec<ChecksCreateRequestOutputAnnotations>,
+    /// Highest level of all the parsed annotations, including the ones over the limit.
+    pub max_level: Option<Level>,
+}
+
+impl AnnotationConfig {
+    /// Parse annotations from stdout of the command. Empty if disabled.
+    pub fn parse(&self, stdout: &[u8]) -> Annotations {
+        let mut parsed = match self.annotation_format {
+            AnnotationFormat::None => return Annotations::default(),
+            AnnotationFormat::Rdjsonl => String::from_utf8_lossy(stdout)
+                .lines()
+                .filter_map(|line| serde_json::from_str::<Diagnostic>(line).ok())
+                .map(|d| {
+                    let level = self.level_of(&d.severity);
+                    (level, d.into_annotation(level))
+                })
+                .collect::<Vec<_>>(),
+        };
+        // Keep higher levels when over the limit.
+        parsed.sort_by_key(|(level, _)| Reverse(*level));
+        let max_level = parsed.first().map(|(level, _)| *level);
+        if parsed.len() > MAX_ANNOTATIONS {
+            warn!(
+                annotations = parsed.len(),
+                max = MAX_ANNOTATIONS,
+                "too many annotations, dropping lower levels over the limit"
+            );
+        }
+        debug!(annotations = parsed.len(), "parsed annotations");
+        Annotations {
+            items: parsed
+                .into_iter()
+                .take(MAX_ANNOTATIONS)
+                .map(|(_, a)| a)
+                .collect(),
+            max_level,
+        }
+    }
+
+    /// The level of `--annotations-fail-on-level` if the annotations reach it.
+    pub fn failing_level(&self, annotations: &Annotations) -> Option<Level> {
+        self.annotations_fail_on_level
+            .filter(|l| annotations.max_level.is_some_and(|max| max >= *l))
+    }
+
+    fn level_of(&self, severity: &str) -> Level {
+        let severity = severity.to_lowercase();
+        let mapped = self
+            .annotation_level_map
+            .as_ref()
+            .and_then(|m| m.0.get(&severity));
+        mapped.copied().unwrap_or(match severity.as_str() {
+            "error" => Level::Failure,
+            "info" | "note" => Level::Notice,
+            _ => Level::Warning,
+        })
+    }
+}
+
+// https://github.com/reviewdog/reviewdog/tree/master/proto/rdf
+#[derive(Debug, Deserialize)]
+struct Diagnostic {
+    message: String,
+    location: Location,
+    #[serde(default)]
+    severity: String,
+    source: Option<Source>,
+    code: Option<Code>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Location {
+    path: String,
+    range: Option<Range>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Range {
+    start: Position,
+    end: Option<Position>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Position {
+    line: i64,
+    #[serde(default)]
+    column: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Source {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Code {
+    value: String,
+}
+
+impl Diagnostic {
+    // Without a range, annotate the first line of the file. The Checks API accepts columns only on a single line.
+    fn into_annotation(self, level: Level) -> ChecksCreateRequestOutputAnnotations {
+        let (start, end) = self.location.range.map_or_else(
+            || {
+                (
+                    Position { line: 1, column: 0 },
+                    Position { line: 1, column: 0 },
+                )
+            },
+            |r| {
+                let end = r.end.unwrap_or(Position {
+                    line: r.start.line,
+                    column: r.start.column,
+                });
+                (r.start, end)
+            },
+        );
+        let (start_column, end_column) = if start.line == end.line {
+            (start.column, end.column)
+        } else {
+            (0, 0)
+        };
+        let title = match (self.source, self.code) {
+            (Some(s), Some(c)) => format!("{} ({})", s.name, c.value),
+            (Some(s), None) => s.name,
+            (None, Some(c)) => c.value,
+            (None, None) => String::new(),
+        };
+        ChecksCreateRequestOutputAnnotations {
+            path: self.location.path,
+            start_line: start.line,
+            end_line: end.line,
+            start_column,
+            end_column,
+            annotation_level: level.into(),
+            message: self.message,
+            title,
+            raw_details: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(map: Option<&str>, fail_on: Option<Level>) -> AnnotationConfig {
+        AnnotationConfig {
+            annotation_format: AnnotationFormat::Rdjsonl,
+            annotation_level_map: map.map(|m| parse_level_map(m).unwrap()),
+            annotations_fail_on_level: fail_on,
+        }
+    }
+
+    #[test]
+    fn parse_rdjsonl() {
+        let stdout = [
+            "running lint",
+            r#"{"message":"unused","location":{"path":"a.go","range":{"start":{"line":3,"column":5},"end":{"line":3,"column":9}}},"severity":"WARNING","source":{"name":"staticcheck"},"code":{"value":"U1000"}}"#,
+            r#"{"message":"bad","location":{"path":"b.go","range":{"start":{"line":1},"end":{"line":4,"column":2}}},"severity":"ERROR"}"#,
+            r#"{"message":"whole file","location":{"path":"c.go"}}"#,
+        ]
+        .join("\n");
+        let annotations = config(None, None).parse(stdout.as_bytes());
+        assert_eq!(annotations.max_level, Some(Level::Failure));
+
+        let items = annotations.items;
+        assert_eq!(items.len(), 3);
+        let first = items.first().unwrap();
+        assert_eq!(first.path, "b.go");
+        assert_eq!(first.annotation_level, AnnotationLevel::Failure);
+        assert_eq!((first.start_line, first.end_line), (1, 4));
+        assert_eq!((first.start_column, first.end_column), (0, 0));
+        let second = items.get(1).unwrap();
+        assert_eq!(second.title, "staticcheck (U1000)");
+        assert_eq!((second.start_column, second.end_column), (5, 9));
+        let third = items.get(2).unwrap();
+        assert_eq!(third.annotation_level, AnnotationLevel::Warning);
+        assert_eq!((third.start_line, third.end_line), (1, 1));
+
+        let disabled = AnnotationConfig::default().parse(stdout.as_bytes());
+        assert!(disabled.items.is_empty());
+        assert_eq!(disabled.max_level, None);
+    }
+
+    #[test]
+    fn level_map() {
+        let config = config(Some(r#"{"Warn":"notice","ERROR":"warning"}"#), None);
+        assert_eq!(config.level_of("warn"), Level::Notice);
+        assert_eq!(config.level_of("error"), Level::Warning);
+        assert_eq!(config.level_of("INFO"), Level::Notice);
+        assert_eq!(config.level_of("UNKNOWN_SEVERITY"), Level::Warning);
+
+        parse_level_map(r#"{"":"notice"}"#).unwrap_err();
+        parse_level_map(r#"{"error":"fatal"}"#).unwrap_err();
+        parse_level_map("[]").unwrap_err();
+    }
+
+    #[test]
+    fn fail_on_level() {
+        let line = |severity: &str| {
+            format!(r#"{{"message":"m","location":{{"path":"a"}},"severity":"{severity}"}}"#)
+        };
+        let config = config(None, Some(Level::Warning));
+        let notice = config.parse(line("INFO").as_bytes());
+        assert_eq!(config.failing_level(&notice), None);
+        let warning = config.parse(line("WARNING").as_bytes());
+        assert_eq!(config.failing_level(&warning), Some(Level::Warning));
+        let failure = config.parse(line("ERROR").as_bytes());
+        assert_eq!(config.failing_level(&failure), Some(Level::Warning));
+        assert_eq!(config.failing_level(&Annotations::default()), None);
+    }
+
+    #[test]
+    fn max_annotations() {
+        let stdout = (0..60)
+            .map(|i| {
+                let severity = if i == 59 { "ERROR" } else { "INFO" };
+                format!(r#"{{"message":"m{i}","location":{{"path":"a"}},"severity":"{severity}"}}"#)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let annotations = config(None, None).parse(stdout.as_bytes());
+        assert_eq!(annotations.items.len(), MAX_ANNOTATIONS);
+        assert_eq!(annotations.items.first().unwrap().message, "m59");
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fs::OpenOptions, io::Write as _, path::Path};
+
+use anyhow::{Context as _, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::task::spawn_blocking;
+
+use crate::events::CheckRequest;
+
+/// Version of the audit record schema. Fields may be added without bumping it, but never renamed nor removed.
+const AUDIT_VERSION: u32 = 1;
+
+/// Outcome of handling an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    /// All the commands succeeded.
+    Succeeded,
+    /// Any of the commands failed or timed out, or the job couldn't run for reasons to be fixed by the repository
+    /// owner, e.g. checkout permissions.
+    Failed,
+    /// The command didn't run by the filters, e.g. `--skip-senders`.
+    Skipped,
+    /// Handling the whole event timed out. See `--request-timeout`.
+    TimedOut,
+    /// Cancelled by a newer event of the same pull request. See `runner server --cancel-in-progress`.
+    Cancelled,
+    /// orgu or infrastructure error.
+    Error,
+}
+
+/// A line of the audit log.
+#[derive(Debug, Serialize)]
+pub struct AuditRecord<'req> {
+    version: u32,
+    request_id: &'req str,
+    delivery_id: &'req str,
+    event_name: &'req str,
+    action: &'req str,
+    repository: &'req str,
+    head_sha: &'req str,
+    pull_request_number: Option<u64>,
+    sender: &'req str,
+    job_name: &'req str,
+    command: &'req [String],
+    outcome: JobOutcome,
+    started_at: DateTime<Utc>,
+    completed_at: DateTime<Utc>,
+    duration_ms: i64,
+}
+
+impl<'req> AuditRecord<'req> {
+    pub fn new(
+        req: &'req CheckRequest,
+        job_name: &'req str,
+        command: &'req [String],
+        outcome: JobOutcome,
+        started_at: DateTime<Utc>,
+        completed_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            version: AUDIT_VERSION,
+            request_id: &req.request_id,
+            delivery_id: &req.delivery_id,
+            event_name: &req.event_name,
+            action: &req.action,
+            repository: &req.repository.full_name,
+            head_sha: &req.head_sha,
+            pull_request_number: req.pull_request_number,
+            sender: &req.sender.login,
+            job_name,
+            command,
+            outcome,
+            started_at,
+            completed_at,
+            duration_ms: (completed_at - started_at).num_milliseconds(),
+        }
+    }
+}
+
+/// Append the record to the file as a JSON line. The file is opened for each record, so it can be rotated outside.
+pub async fn append(path: &Path, record: &AuditRecord<'_>) -> Result<()> {
+    let mut line = serde_json::to_vec(record).with_context(|| "serializing audit record failed")?;
+    line.push(b'\n');
+    let path = path.to_path_buf();
+    // Write the line at once in append mode not to interleave with records of concurrent events. tokio's File may
+    // split a write into chunks, so write with std in a blocking thread instead.
+    spawn_blocking(move || {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening audit log failed: {}", path.display()))?;
+        file.write_all(&line)
+            .with_context(|| format!("writing audit log failed: {}", path.display()))
+    })
+    .await
+    .with_context(|| "Failed to spawn blocking task")?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+
+    use chrono::Duration;
+    use futures::future::join_all;
+    use serde_json::Value;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn append_concurrently() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let req = CheckRequest {
+            pull_request_number: Some(55),
+            ..Default::default()
+        };
+        let command = vec!["x".repeat(64 * 1024)];
+        let started_at = Utc::now();
+        let record = AuditRecord::new(
+            &req,
+            "lint",
+            &command,
+            JobOutcome::Succeeded,
+            started_at,
+            started_at + Duration::milliseconds(1500),
+        );
+
+        let results = join_all((0..16).map(|_| append(&path, &record))).await;
+        results.into_iter().for_each(|r| r.unwrap());
+
+        let content = read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 16);
+        for line in lines {
+            let v = serde_json::from_str::<Value>(line).unwrap();
+            assert_eq!(v["version"], AUDIT_VERSION);
+            assert_eq!(v["outcome"], "succeeded");
+            assert_eq!(v["pull_request_number"], 55);
+            assert_eq!(v["duration_ms"], 1500);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use clap::Parser;
+
+use crate::{
+    checkout::{CheckoutConfig, Libgit2Checkout},
+    github_client::OctorustClient,
+    github_config::{GithubApiConfig, GithubAppConfig},
+    github_token::DefaultTokenFetcher,
+    runner::{
+        executor::LocalExecutor,
+        handler::{Config, Handler},
+    },
+};
+
+/// Handler with the default implementations which the runner commands use.
+pub type DefaultHandler =
+    Handler<OctorustClient, Libgit2Checkout, DefaultTokenFetcher, LocalExecutor>;
+
+/// Builds a runner handler to handle `CheckRequest` in-process without the CLI.
+///
+/// ```no_run
+/// # async fn run(req: orgu::events::CheckRequest) -> anyhow::Result<()> {
+/// use clap::Parser as _;
+/// use orgu::runner::RunnerBuilder;
+///
+/// // Same flags and env vars as `orgu runner server` are accepted.
+/// let args = ["orgu", "--job-name", "lint", "--", "make", "lint"];
+/// let handler = RunnerBuilder::try_parse_from(args)?.build()?;
+/// handler.handle_event(req).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Parser)]
+pub struct RunnerBuilder {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    checkout_config: CheckoutConfig,
+    #[command(flatten)]
+    handler_config: Config,
+}
+
+impl RunnerBuilder {
+    pub const fn new(
+        github_app_config: GithubAppConfig,
+        github_config: GithubApiConfig,
+        checkout_config: CheckoutConfig,
+        handler_config: Config,
+    ) -> Self {
+        Self {
+            github_app_config,
+            github_config,
+            checkout_config,
+            handler_config,
+        }
+    }
+
+    pub fn build(self) -> Result<DefaultHandler> {
+        self.handler_config.validate()?;
+        let client =
+            OctorustClient::new(self.github_config.clone(), self.github_app_config.clone())?;
+        let checkout = Libgit2Checkout::new(self.checkout_config);
+        let fetcher = DefaultTokenFetcher::new(self.github_config, self.github_app_config)?;
+        Ok(Handler::new(
+            self.handler_config,
+            client,
+            checkout,
+            fetcher,
+            LocalExecutor,
+        ))
+    }
+}
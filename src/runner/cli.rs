@@ -1,6 +1,14 @@
+#[cfg(feature = "redis")]
+mod consume;
+mod gc_checks;
+#[cfg(feature = "aws")]
 mod lambda;
 mod oneshot;
+#[cfg(feature = "aws")]
+mod redrive;
 mod server;
+#[cfg(feature = "aws")]
+mod sqs;
 
 use clap::Subcommand;
 
@@ -12,14 +20,35 @@ pub enum RunnerCommands {
     Server(server::ServerArgs),
     /// Run CI job as oneshot task. Use this to develop CI job locally.
     Oneshot(oneshot::OneshotArgs),
-    /// Run runner in AWS Lambda function. Triggered by EventBridge events.
+    /// Run runner in AWS Lambda function. Triggered by EventBridge events or SQS, see `--lambda-input`.
+    #[cfg(feature = "aws")]
     Lambda(lambda::LambdaArgs),
+    /// Run runner as a consumer of Redis stream. Events are sent by front server with `--use-redis-stream`.
+    #[cfg(feature = "redis")]
+    Consume(consume::ConsumeArgs),
+    /// Run runner as a long-polling consumer of SQS queue.
+    #[cfg(feature = "aws")]
+    Sqs(sqs::SqsArgs),
+    /// Resubmit events in the dead-letter SQS queue to the event bus or the runner server, e.g. after fixing the
+    /// cause of the failures.
+    #[cfg(feature = "aws")]
+    Redrive(redrive::RedriveArgs),
+    /// Conclude check runs stuck in progress as cancelled, e.g. after runners crashed.
+    GcChecks(gc_checks::GcChecksArgs),
 }
 
 pub async fn run(global: GlobalArgs, c: RunnerCommands) -> CommandResult {
     match c {
         RunnerCommands::Server(args) => server::server(global, args).await,
         RunnerCommands::Oneshot(args) => oneshot::oneshot(global, args).await,
+        #[cfg(feature = "aws")]
         RunnerCommands::Lambda(args) => lambda::lambda(global, args).await,
+        #[cfg(feature = "redis")]
+        RunnerCommands::Consume(args) => consume::consume(global, args).await,
+        #[cfg(feature = "aws")]
+        RunnerCommands::Sqs(args) => sqs::sqs(global, args).await,
+        #[cfg(feature = "aws")]
+        RunnerCommands::Redrive(args) => redrive::redrive(global, args).await,
+        RunnerCommands::GcChecks(args) => gc_checks::gc_checks(global, args).await,
     }
 }
@@ -0,0 +1,192 @@
+use anyhow::{Context as _, Result};
+use clap::Args;
+use redis::{
+    aio::ConnectionManager,
+    streams::{StreamId, StreamReadOptions, StreamReadReply},
+    AsyncCommands as _, RedisResult,
+};
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+use super::server::Selection;
+use crate::{
+    checkout::CheckoutConfig,
+    cli::{CommandResult, GlobalArgs},
+    event_queue_client::{
+        is_transient_redis_error, redis_connect, RedisStreamConfig, REDIS_STREAM_FIELD,
+    },
+    events::CheckRequest,
+    github_config::{GithubApiConfig, GithubAppConfig},
+    runner::{handler::Config, DefaultHandler, RunnerBuilder},
+    trace::init_fmt_with_pretty,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct ConsumeArgs {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    checkout_config: CheckoutConfig,
+    #[command(flatten)]
+    handler_config: Config,
+    #[command(flatten)]
+    redis_config: RedisStreamConfig,
+    /// Filter events to process.
+    #[arg(short, long, default_value = "pull_request")]
+    select: Selection,
+    /// Name of the consumer group. Runners in the same group share events, each group receives every event.
+    #[arg(long, env, default_value = "orgu-runner")]
+    redis_group: String,
+    /// Name of this consumer in the group. Must be stable across restarts to retry unacknowledged events.
+    #[arg(long, env)]
+    redis_consumer: String,
+    /// How long to block waiting for new events in a single read.
+    #[arg(long, env, default_value = "5s")]
+    redis_block_timeout: humantime::Duration,
+    /// How long to wait before reading again after a Redis error.
+    #[arg(long, env, default_value = "5s")]
+    redis_retry_interval: humantime::Duration,
+}
+
+// Read pending (delivered but not acknowledged) entries of this consumer from the beginning.
+const PENDING_ID: &str = "0";
+// Read entries never delivered to any consumer in the group.
+const NEW_ID: &str = ">";
+
+struct Consumer {
+    con: ConnectionManager,
+    handler: DefaultHandler,
+    selection: Selection,
+    stream: String,
+    group: String,
+    consumer: String,
+}
+
+/// Consume events from Redis stream with consumer group. Events are acknowledged only after handled successfully,
+/// so failed or interrupted events are retried from the pending entries list on restart (at-least-once).
+pub async fn consume(global: GlobalArgs, args: ConsumeArgs) -> CommandResult {
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let handler = RunnerBuilder::new(
+        args.github_app_config,
+        args.github_config,
+        args.checkout_config,
+        args.handler_config,
+    )
+    .build()?;
+    handler.preflight().await?;
+    let con = redis_connect(&args.redis_config.redis_url).await?;
+    let mut consumer = Consumer {
+        con,
+        handler,
+        selection: args.select,
+        stream: args.redis_config.redis_stream,
+        group: args.redis_group,
+        consumer: args.redis_consumer,
+    };
+    consumer.create_group().await?;
+
+    let block = args.redis_block_timeout.as_millis().try_into()?;
+    // Start from pending entries left by the previous run, then move on to new entries.
+    let mut pending_cursor = Some(PENDING_ID.to_owned());
+    info!(
+        stream = consumer.stream,
+        group = consumer.group,
+        consumer = consumer.consumer,
+        "consuming events from Redis stream"
+    );
+    loop {
+        let id = pending_cursor.as_deref().unwrap_or(NEW_ID);
+        match consumer.read(id, block).await {
+            Ok(entries) => {
+                if pending_cursor.is_some() {
+                    // Advance the cursor so failed pending entries are not read again in this run.
+                    pending_cursor = entries.last().map(|e| e.id.clone());
+                }
+                for entry in entries {
+                    consumer.process(entry).await?;
+                }
+            }
+            Err(e) if is_transient_redis_error(&e) => {
+                warn!(error = ?e, "reading from Redis stream failed, retrying");
+                sleep(*args.redis_retry_interval).await;
+            }
+            Err(e) => return Err(e).with_context(|| "reading from Redis stream failed"),
+        }
+    }
+}
+
+impl Consumer {
+    async fn create_group(&mut self) -> Result<()> {
+        let res: Result<(), _> = self
+            .con
+            .xgroup_create_mkstream(&self.stream, &self.group, "$")
+            .await;
+        match res {
+            Ok(()) => info!(group = self.group, "created consumer group"),
+            Err(e) if e.code() == Some("BUSYGROUP") => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("creating consumer group failed: group={}", self.group)
+                })
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self, id: &str, block: usize) -> RedisResult<Vec<StreamId>> {
+        let mut opts = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .count(1);
+        // Pending entries are returned immediately, blocking is only for new entries.
+        if id == NEW_ID {
+            opts = opts.block(block);
+        }
+        let reply: Option<StreamReadReply> = self
+            .con
+            .xread_options(&[&self.stream], &[id], &opts)
+            .await?;
+        Ok(reply
+            .into_iter()
+            .flat_map(|r| r.keys)
+            .flat_map(|k| k.ids)
+            .collect())
+    }
+
+    async fn process(&mut self, entry: StreamId) -> Result<()> {
+        let payload: Option<String> = entry.get(REDIS_STREAM_FIELD);
+        let req = payload
+            .with_context(|| format!("no `{REDIS_STREAM_FIELD}` field"))
+            .and_then(|p| {
+                serde_json::from_str::<CheckRequest>(&p)
+                    .with_context(|| "deserializing CheckRequest failed")
+            });
+        match req {
+            // Retrying won't fix malformed entries, so acknowledge them to not block the stream.
+            Err(e) => error!(error = ?e, id = entry.id, "dropping malformed entry"),
+            Ok(req) if !self.selection.matches(&req) => info!(
+                "skipping event: selection={}, event={}, action={}",
+                self.selection, req.event_name, req.action
+            ),
+            Ok(req) => {
+                if let Err(e) = self.handler.handle_event(req).await {
+                    // Keep in the pending entries list to retry on restart.
+                    error!(error = ?e, id = entry.id, "handling event failed, not acknowledging");
+                    return Ok(());
+                }
+            }
+        }
+        self.ack(&entry.id).await
+    }
+
+    async fn ack(&mut self, id: &str) -> Result<()> {
+        let _: usize = self
+            .con
+            .xack(&self.stream, &self.group, &[id])
+            .await
+            .with_context(|| format!("acknowledging entry failed: id={id}"))?;
+        Ok(())
+    }
+}
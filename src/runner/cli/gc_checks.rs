@@ -0,0 +1,198 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use octorust::types::{
+    ChecksCreateRequestConclusion, ChecksUpdateRequest, ChecksUpdateRequestOutput, JobStatus,
+};
+use tracing::info;
+
+use crate::{
+    cli::{CommandResult, GlobalArgs, SUCCESS},
+    github_client::{GithubClient, OctorustClient},
+    github_config::{GithubApiConfig, GithubAppConfig},
+    trace::init_fmt_with_pretty,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct GcChecksArgs {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    target: Target,
+}
+
+#[derive(Debug, Clone, Args)]
+struct Target {
+    /// GitHub repository owner name.
+    #[arg(long)]
+    owner: String,
+    /// GitHub repository name.
+    #[arg(long)]
+    repo: String,
+    /// SHA, branch or tag to look for stale check runs. GitHub lists check runs only per commit, so give head SHAs
+    /// of the affected pull requests. Can be passed multiple times.
+    #[arg(long = "ref", required = true)]
+    refs: Vec<String>,
+    /// Only check runs whose name starts with this prefix are concluded, i.e. ones created by orgu runners.
+    #[arg(long, default_value = "run-")]
+    name_prefix: String,
+    /// Check runs started longer ago than this and still in progress are concluded as cancelled.
+    #[arg(long, default_value = "1h")]
+    older_than: humantime::Duration,
+    /// Only log stale check runs without concluding them.
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+}
+
+/// Conclude orgu check runs stuck in progress, e.g. left by crashed runners.
+pub async fn gc_checks(global: GlobalArgs, args: GcChecksArgs) -> CommandResult {
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let client = OctorustClient::new(args.github_config, args.github_app_config)?;
+    let count = gc_check_runs(&client, &args.target, Utc::now()).await?;
+    let (cancelled, skipped) = if args.target.dry_run {
+        (0, count)
+    } else {
+        (count, 0)
+    };
+    info!(
+        cancelled,
+        skipped,
+        dry_run = args.target.dry_run,
+        "garbage collected stale check runs"
+    );
+    SUCCESS
+}
+
+// Returns the number of stale check runs.
+async fn gc_check_runs(
+    client: &impl GithubClient,
+    target: &Target,
+    now: DateTime<Utc>,
+) -> Result<usize> {
+    let threshold = now - chrono::Duration::from_std(*target.older_than)?;
+    let mut count = 0_usize;
+    for git_ref in &target.refs {
+        let runs = client
+            .list_check_runs(&target.owner, &target.repo, git_ref, JobStatus::InProgress)
+            .await?;
+        let stale = runs.into_iter().filter(|r| {
+            r.name.starts_with(&target.name_prefix) && r.started_at.is_some_and(|t| t < threshold)
+        });
+        for r in stale {
+            count = count.saturating_add(1);
+            info!(check_run_id = r.id, name = r.name, git_ref, started_at = ?r.started_at, "found stale check run");
+            if target.dry_run {
+                continue;
+            }
+            client
+                .update_check_run(
+                    &target.owner,
+                    &target.repo,
+                    r.id,
+                    &cancel_request(r.name, target.older_than, now),
+                )
+                .await?;
+        }
+    }
+    Ok(count)
+}
+
+fn cancel_request(
+    name: String,
+    older_than: humantime::Duration,
+    now: DateTime<Utc>,
+) -> ChecksUpdateRequest {
+    ChecksUpdateRequest {
+        name,
+        status: Some(JobStatus::Completed),
+        conclusion: Some(ChecksCreateRequestConclusion::Cancelled),
+        output: Some(ChecksUpdateRequestOutput {
+            title: "Runner stopped reporting".to_owned(),
+            summary: format!(
+                "Check run has been in progress for more than {older_than}, so it was cancelled by `orgu runner gc-checks`. The runner likely crashed, re-run the job if needed."
+            ),
+            text: String::new(),
+            annotations: Vec::new(),
+            images: Vec::new(),
+        }),
+        actions: Vec::new(),
+        completed_at: Some(now),
+        started_at: None,
+        details_url: String::new(),
+        external_id: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use octorust::types::CheckRun;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::github_client::{empty_checkrun, MockGithubClient};
+
+    fn target(dry_run: bool) -> Target {
+        Target {
+            owner: "owner".to_owned(),
+            repo: "repo".to_owned(),
+            refs: vec!["sha1".to_owned()],
+            name_prefix: "run-".to_owned(),
+            older_than: Duration::from_secs(60 * 60).into(),
+            dry_run,
+        }
+    }
+
+    fn check_run(id: i64, name: &str, started_at: DateTime<Utc>) -> CheckRun {
+        CheckRun {
+            id,
+            name: name.to_owned(),
+            started_at: Some(started_at),
+            ..empty_checkrun()
+        }
+    }
+
+    fn stuck_runs(now: DateTime<Utc>) -> Vec<CheckRun> {
+        vec![
+            check_run(1, "run-lint", now - chrono::Duration::hours(2)),
+            check_run(2, "run-lint", now - chrono::Duration::minutes(10)),
+            check_run(3, "other-ci", now - chrono::Duration::hours(2)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn cancel_stale_check_runs() {
+        let now = Utc::now();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_list_check_runs()
+            .withf(|_, _, git_ref, status| git_ref == "sha1" && *status == JobStatus::InProgress)
+            .returning(move |_, _, _, _| Ok(stuck_runs(now)));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, id, input| {
+                *id == 1 && input.conclusion == Some(ChecksCreateRequestConclusion::Cancelled)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        assert_eq!(
+            gc_check_runs(&client, &target(false), now).await.unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let now = Utc::now();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_list_check_runs()
+            .returning(move |_, _, _, _| Ok(stuck_runs(now)));
+        client.expect_update_check_run().never();
+        assert_eq!(gc_check_runs(&client, &target(true), now).await.unwrap(), 1);
+    }
+}
@@ -1,19 +1,21 @@
-use std::error::Error;
+use std::{error::Error, future::Future};
 
-use anyhow::bail;
-use aws_lambda_events::eventbridge::EventBridgeEvent;
-use clap::Args;
+use anyhow::{bail, Context as _};
+use aws_lambda_events::{
+    eventbridge::EventBridgeEvent,
+    sqs::{BatchItemFailure, SqsBatchResponse, SqsEvent, SqsMessage},
+};
+use clap::{Args, ValueEnum};
 use lambda_runtime::{run, service_fn, LambdaEvent};
-use tracing::error;
+use tracing::{error, info};
 
+use super::sqs::parse_body;
 use crate::{
-    checkout::{CheckoutConfig, Libgit2Checkout},
+    checkout::CheckoutConfig,
     cli::{CommandResult, GlobalArgs, FAILURE},
     events::CheckRequest,
-    github_client::OctorustClient,
     github_config::{GithubApiConfig, GithubAppConfig},
-    github_token::DefaultTokenFetcher,
-    runner::handler::{Config, Handler},
+    runner::{handler::Config, DefaultHandler, RunnerBuilder},
     trace::init_fmt_with_json,
 };
 
@@ -27,31 +29,145 @@ pub struct LambdaArgs {
     checkout_config: CheckoutConfig,
     #[command(flatten)]
     handler_config: Config,
+    /// Shape of the Lambda event payload.
+    #[arg(long, env, value_enum, default_value = "eventbridge")]
+    lambda_input: LambdaInput,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LambdaInput {
+    /// EventBridge event with `CheckRequest` as the detail, triggered by an EventBridge rule.
+    Eventbridge,
+    /// SQS event source or EventBridge Pipes with SQS source. Each record body is a `CheckRequest` sent directly or
+    /// an EventBridge event with it as the detail. Failed records are reported as partial batch failures, enable
+    /// `ReportBatchItemFailures` of the event source mapping.
+    Sqs,
 }
 
+type LambdaResult<T = ()> = Result<T, Box<dyn Error>>;
+
 pub async fn lambda(global: GlobalArgs, args: LambdaArgs) -> CommandResult {
-    init_fmt_with_json(&global.verbose);
-
-    let client = OctorustClient::new(args.github_config.clone(), args.github_app_config.clone())?;
-    let checkout = Libgit2Checkout::new(args.checkout_config);
-    let fetcher =
-        DefaultTokenFetcher::new(args.github_config.clone(), args.github_app_config.clone())?;
-    let handler = Handler::new(args.handler_config, client, checkout, fetcher);
-
-    let service = service_fn(|event: LambdaEvent<EventBridgeEvent<CheckRequest>>| {
-        let h = &handler;
-        async move {
-            h.handle_event(event.payload.detail)
-                .await
-                .map_err(Into::<Box<dyn Error>>::into)
-        }
-    });
+    let _guard = init_fmt_with_json(&global)?;
 
+    let handler = RunnerBuilder::new(
+        args.github_app_config,
+        args.github_config,
+        args.checkout_config,
+        args.handler_config,
+    )
+    .build()?;
+    handler.preflight().await?;
+
+    let res = match args.lambda_input {
+        LambdaInput::Eventbridge => run(service_fn(|e| handle_eventbridge(&handler, e))).await,
+        LambdaInput::Sqs => run(service_fn(|e| handle_sqs(&handler, e))).await,
+    };
     // Use bail! because run returns unmachable type.
-    if let Err(e) = run(service).await {
+    if let Err(e) = res {
         bail!("lambda_runtime::run error: {:?}", e);
     }
 
     error!("lambda_runtime::run returned unexpectedly");
     FAILURE
 }
+
+async fn handle_eventbridge(
+    handler: &DefaultHandler,
+    event: LambdaEvent<EventBridgeEvent<CheckRequest>>,
+) -> LambdaResult {
+    handler
+        .handle_event(event.payload.detail)
+        .await
+        .map_err(Into::into)
+}
+
+// Records are handled in order. Failed records are reported as partial batch failures to be retried, or redriven
+// to the dead-letter queue, without retrying the succeeded ones. Requires `ReportBatchItemFailures` of the event
+// source mapping.
+async fn handle_sqs(
+    handler: &DefaultHandler,
+    event: LambdaEvent<SqsEvent>,
+) -> LambdaResult<SqsBatchResponse> {
+    Ok(handle_records(event.payload.records, |req| handler.handle_event(req)).await)
+}
+
+async fn handle_records<F, Fut>(records: Vec<SqsMessage>, mut handle: F) -> SqsBatchResponse
+where
+    F: FnMut(CheckRequest) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut batch_item_failures = Vec::new();
+    for record in records {
+        let Some(id) = record.message_id else {
+            // Can't be reported without the ID, so it's deleted as succeeded.
+            error!("SQS record has no message ID, skipping");
+            continue;
+        };
+        info!(id, "handling SQS record");
+        let req = record
+            .body
+            .with_context(|| "SQS record has no body")
+            .and_then(|b| parse_body(&b));
+        let res = match req {
+            Ok(req) => handle(req).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            error!(error = ?e, id, "handling SQS record failed, reporting as batch item failure");
+            batch_item_failures.push(BatchItemFailure {
+                item_identifier: id,
+            });
+        }
+    }
+    SqsBatchResponse {
+        batch_item_failures,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn partial_batch_failure() {
+        let record = |id: Option<&str>, body: Option<&str>| SqsMessage {
+            message_id: id.map(ToOwned::to_owned),
+            body: body.map(ToOwned::to_owned),
+            ..Default::default()
+        };
+        let body = |request_id: &str| {
+            serde_json::to_string(&CheckRequest {
+                request_id: request_id.to_owned(),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let records = vec![
+            record(Some("ok"), Some(&body("ok"))),
+            record(Some("failed"), Some(&body("failed"))),
+            record(Some("malformed"), Some("{")),
+            record(Some("empty"), None),
+            record(None, Some(&body("no-id"))),
+        ];
+        let mut handled = Vec::new();
+        let res = handle_records(records, |req| {
+            handled.push(req.request_id.clone());
+            async move {
+                if req.request_id == "failed" {
+                    bail!("failed");
+                }
+                Ok(())
+            }
+        })
+        .await;
+        assert_eq!(handled, ["ok", "failed"]);
+        let failed = res
+            .batch_item_failures
+            .into_iter()
+            .map(|f| f.item_identifier)
+            .collect::<Vec<_>>();
+        assert_eq!(failed, ["failed", "malformed", "empty"]);
+    }
+}
@@ -3,11 +3,15 @@ use clap::Args;
 use crate::{
     checkout::{CheckoutConfig, Libgit2Checkout},
     cli::{CommandResult, GlobalArgs, SUCCESS},
+    event_queue_client::SCHEMA_VERSION,
     events::{CheckRequest, User},
     github_client::{NullClient, OctorustClient},
     github_config::{GithubApiConfig, GithubAppConfig},
     github_token::{DefaultTokenFetcher, TokenFetcher as _},
-    runner::handler::{Config, Handler},
+    runner::{
+        executor::LocalExecutor,
+        handler::{Config, Handler},
+    },
     trace::init_fmt_with_pretty,
 };
 
@@ -30,15 +34,31 @@ pub struct OneshotArgs {
     /// SHA of the commit to be checked out. If none, remote HEAD will be checked-out.
     #[arg(env, long)]
     head_sha: Option<String>,
+    /// Pull request number to run jobs as if triggered by the pull request.
+    #[arg(env, long)]
+    pull_request_number: Option<u64>,
+    /// SHA of the base commit of the pull request.
+    #[arg(env, long)]
+    base_sha: Option<String>,
+    /// Git reference of the base commit of the pull request. e.g. `main`.
+    #[arg(env, long)]
+    base_ref: Option<String>,
 }
 
 pub async fn oneshot(global: GlobalArgs, args: OneshotArgs) -> CommandResult {
-    init_fmt_with_pretty(&global.verbose);
+    let _guard = init_fmt_with_pretty(&global)?;
 
+    args.handler_config.validate()?;
     let checkout = Libgit2Checkout::new(args.checkout_config);
     let fetcher =
         DefaultTokenFetcher::new(args.github_config.clone(), args.github_app_config.clone())?;
-    let handler = Handler::new(args.handler_config, NullClient, checkout, fetcher.clone());
+    let handler = Handler::new(
+        args.handler_config,
+        NullClient,
+        checkout,
+        fetcher.clone(),
+        LocalExecutor,
+    );
 
     let token = fetcher.fetch_token().await?;
     let github_client = OctorustClient::new_with_token(args.github_config, token.clone())?;
@@ -56,20 +76,24 @@ pub async fn oneshot(global: GlobalArgs, args: OneshotArgs) -> CommandResult {
         .await?;
 
     let req = CheckRequest {
+        schema_version: SCHEMA_VERSION,
         request_id: "oneshot".to_owned(),
         delivery_id: "oneshot".to_owned(),
         event_name: "pull_request".to_owned(),
         action: "synchronize".to_owned(),
         head_sha: head_sha.clone(),
-        base_sha: None,
-        base_ref: None,
+        base_sha: args.base_sha,
+        base_ref: args.base_ref,
         before: None,
         after: Some(head_sha.clone()),
-        pull_request_number: None,
+        pull_request_number: args.pull_request_number,
+        pull_request_head_ref: None,
         repository: repo,
         sender: User {
             login: "octocat".to_owned(),
         },
+        check_run: None,
+        comment_id: None,
     };
 
     handler.handle_event(req).await?;
@@ -0,0 +1,282 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::{bail, Context as _, Result};
+use aws_sdk_sqs::{types::Message, Client};
+use clap::Args;
+use tracing::{error, info};
+use url::Url;
+
+use super::sqs::{parse_body, visibility_secs};
+use crate::{
+    cli::{CommandResult, GlobalArgs, FAILURE, SUCCESS},
+    event_queue_client::{
+        AwsEventBusClient, AwsEventBusConfig, EventQueueClient, EventQueueRelayClient,
+        EventQueueRelayConfig,
+    },
+    relay_signature::RelaySignatureConfig,
+    trace::init_fmt_with_pretty,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct RedriveArgs {
+    /// URL of the dead-letter SQS queue to redrive events from.
+    #[arg(long, env)]
+    dlq_url: String,
+    /// Where to resubmit events: `event-bus` to put them back onto the EventBridge event bus of `--event-bus-name`,
+    /// or the runner server endpoint, e.g. `http://127.0.0.1:3001/run` or `unix:///path/to/socket`.
+    #[arg(long, env, value_parser = parse_target, required_unless_present = "dry_run")]
+    target: Option<Target>,
+    #[command(flatten)]
+    event_bus_config: AwsEventBusConfig,
+    /// Connect timeout for the runner server endpoint.
+    #[arg(long, env, default_value = "1s")]
+    target_connect_timeout: humantime::Duration,
+    // Sign events resubmitted to the runner server endpoint, same as `front server`.
+    #[command(flatten)]
+    relay_signature_config: RelaySignatureConfig,
+    /// Received messages are hidden from other consumers for this duration, and redriven again after it if
+    /// resubmitting failed. Also how long counted messages are hidden after `--dry-run`.
+    #[arg(long, env, default_value = "60s")]
+    dlq_visibility_timeout: humantime::Duration,
+    /// Only count and print events in the queue without resubmitting nor deleting them.
+    #[arg(long, default_value = "false")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    EventBus,
+    Endpoint(Url),
+}
+
+fn parse_target(s: &str) -> Result<Target> {
+    if s == "event-bus" {
+        return Ok(Target::EventBus);
+    }
+    let url = Url::parse(s).with_context(|| format!("target must be `event-bus` or a URL: {s}"))?;
+    if !matches!(url.scheme(), "http" | "https" | "unix") {
+        bail!("unsupported target scheme, use http, https or unix: {s}");
+    }
+    Ok(Target::Endpoint(url))
+}
+
+// Maximum number of messages SQS returns at once.
+const MAX_MESSAGES: i32 = 10;
+// Short wait to drain the queue, an empty receive means the queue has no visible messages.
+const WAIT_TIME_SECONDS: i32 = 1;
+
+#[derive(Debug, Default)]
+struct Counts {
+    succeeded: usize,
+    failed: usize,
+}
+
+/// Receive events from the dead-letter queue until it's drained and resubmit each of them. Messages are deleted only
+/// after resubmitted successfully, failed ones are left in the queue.
+pub async fn redrive(global: GlobalArgs, args: RedriveArgs) -> CommandResult {
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let sdk_config = aws_config::load_from_env().await;
+    let dlq = Dlq {
+        client: Client::new(&sdk_config),
+        queue_url: args.dlq_url,
+        visibility_timeout: *args.dlq_visibility_timeout,
+    };
+
+    if args.dry_run {
+        let counts = dlq.count().await?;
+        println!("events: {}", counts.succeeded);
+        println!("malformed messages: {}", counts.failed);
+        return SUCCESS;
+    }
+
+    let counts = match args.target {
+        Some(Target::EventBus) => {
+            let client = AwsEventBusClient::new(args.event_bus_config).await;
+            dlq.redrive(&client).await?
+        }
+        Some(Target::Endpoint(endpoint)) => {
+            let client = EventQueueRelayClient::new(EventQueueRelayConfig {
+                endpoint,
+                connect_timeout: args.target_connect_timeout.into(),
+                read_timeout: None,
+                request_timeout: None,
+                compress_request: false,
+                signature: args.relay_signature_config.build(),
+            })?;
+            dlq.redrive(&client).await?
+        }
+        None => bail!("--target is required unless --dry-run"),
+    };
+    println!("redriven events: {}", counts.succeeded);
+    println!("failed events: {}", counts.failed);
+    if counts.failed > 0 {
+        return FAILURE;
+    }
+    SUCCESS
+}
+
+// Received messages reappear after the visibility timeout, e.g. failed ones or counted ones while counting a large
+// queue, so the queue is drained when a receive is empty or returns a message seen before. Returns unseen messages
+// and whether drained.
+fn unseen(messages: Vec<Message>, seen: &mut HashSet<String>) -> (Vec<Message>, bool) {
+    let mut drained = messages.is_empty();
+    let messages = messages
+        .into_iter()
+        .filter(|m| {
+            let first = seen.insert(m.message_id.clone().unwrap_or_default());
+            drained |= !first;
+            first
+        })
+        .collect();
+    (messages, drained)
+}
+
+struct Dlq {
+    client: Client,
+    queue_url: String,
+    visibility_timeout: Duration,
+}
+
+impl Dlq {
+    async fn receive(&self) -> Result<Vec<Message>> {
+        let out = self
+            .client
+            .receive_message()
+            .queue_url(&self.queue_url)
+            .max_number_of_messages(MAX_MESSAGES)
+            .wait_time_seconds(WAIT_TIME_SECONDS)
+            .visibility_timeout(visibility_secs(self.visibility_timeout))
+            .send()
+            .await
+            .with_context(|| format!("receiving messages failed: queue_url={}", self.queue_url))?;
+        Ok(out.messages.unwrap_or_default())
+    }
+
+    async fn count(&self) -> Result<Counts> {
+        let mut counts = Counts::default();
+        let mut seen = HashSet::new();
+        loop {
+            let (messages, drained) = unseen(self.receive().await?, &mut seen);
+            for message in messages {
+                let id = message.message_id.unwrap_or_default();
+                let req = message
+                    .body
+                    .with_context(|| "message has no body")
+                    .and_then(|b| parse_body(&b));
+                match req {
+                    Ok(req) => {
+                        info!(id, request_id = req.request_id, "found event");
+                        counts.succeeded = counts.succeeded.saturating_add(1);
+                    }
+                    Err(e) => {
+                        error!(error = ?e, id, "malformed message");
+                        counts.failed = counts.failed.saturating_add(1);
+                    }
+                }
+            }
+            if drained {
+                return Ok(counts);
+            }
+        }
+    }
+
+    async fn redrive(&self, client: &impl EventQueueClient) -> Result<Counts> {
+        let mut counts = Counts::default();
+        let mut seen = HashSet::new();
+        loop {
+            let (messages, drained) = unseen(self.receive().await?, &mut seen);
+            for message in messages {
+                let id = message.message_id.clone().unwrap_or_default();
+                match self.redrive_message(client, message).await {
+                    Ok(()) => {
+                        info!(id, "redriven event");
+                        counts.succeeded = counts.succeeded.saturating_add(1);
+                    }
+                    Err(e) => {
+                        error!(error = ?e, id, "redriving event failed, leaving in the queue");
+                        counts.failed = counts.failed.saturating_add(1);
+                    }
+                }
+            }
+            if drained {
+                return Ok(counts);
+            }
+        }
+    }
+
+    async fn redrive_message(
+        &self,
+        client: &impl EventQueueClient,
+        message: Message,
+    ) -> Result<()> {
+        let req = message
+            .body
+            .with_context(|| "message has no body")
+            .and_then(|b| parse_body(&b))?;
+        let receipt_handle = message
+            .receipt_handle
+            .with_context(|| "message has no receipt handle")?;
+        client.send(req).await?;
+        self.client
+            .delete_message()
+            .queue_url(&self.queue_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await
+            .with_context(|| "deleting redriven message failed, it will be redriven again")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_target() {
+        assert!(matches!(
+            super::parse_target("event-bus").unwrap(),
+            Target::EventBus
+        ));
+        assert!(matches!(
+            super::parse_target("http://127.0.0.1:3001/run").unwrap(),
+            Target::Endpoint(_)
+        ));
+        assert!(matches!(
+            super::parse_target("unix:///tmp/orgu-runner.sock").unwrap(),
+            Target::Endpoint(_)
+        ));
+        super::parse_target("sqs://queue").unwrap_err();
+        super::parse_target("queue").unwrap_err();
+    }
+
+    #[test]
+    fn unseen() {
+        let messages = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| Message::builder().message_id(*id).build())
+                .collect::<Vec<_>>()
+        };
+        let ids = |messages: Vec<Message>| {
+            messages
+                .into_iter()
+                .filter_map(|m| m.message_id)
+                .collect::<Vec<_>>()
+        };
+        let mut seen = HashSet::new();
+
+        let (unseen, drained) = super::unseen(messages(&["a", "b"]), &mut seen);
+        assert_eq!(ids(unseen), ["a", "b"]);
+        assert!(!drained);
+
+        // "a" reappeared after the visibility timeout.
+        let (unseen, drained) = super::unseen(messages(&["c", "a"]), &mut seen);
+        assert_eq!(ids(unseen), ["c"]);
+        assert!(drained);
+
+        let (unseen, drained) = super::unseen(vec![], &mut seen);
+        assert!(unseen.is_empty());
+        assert!(drained);
+    }
+}
@@ -1,33 +1,54 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    io::ErrorKind,
+    os::unix::fs::FileTypeExt as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::{bail, Context as _};
 use axum::{
-    extract::State,
+    body::Bytes,
+    extract::{Request, State},
+    response::{IntoResponse as _, Response},
     routing::{get, post},
     Router,
 };
 use axum::{serve, Json};
+use axum_server::from_tcp_rustls;
+use chrono::Utc;
 use clap::{Args, ValueEnum};
+use http::HeaderMap;
+use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
 use strum::Display;
-use tokio::net::TcpListener;
-use tower::ServiceBuilder;
+use tokio::{
+    fs::{remove_file, symlink_metadata},
+    net::{TcpListener, UnixListener},
+    spawn,
+};
+use tower::{ServiceBuilder, ServiceExt as _};
 use tower_http::{
+    compression::CompressionLayer,
+    decompression::RequestDecompressionLayer,
     normalize_path::NormalizePathLayer,
     timeout::TimeoutLayer,
     trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
     LatencyUnit,
 };
-use tracing::{info, Level};
+use tracing::{info, warn, Instrument as _, Level};
 
 use crate::{
     app_error::AppError,
-    checkout::{CheckoutConfig, Libgit2Checkout},
+    build_info::BuildInfo,
+    checkout::CheckoutConfig,
     cli::{CommandResult, GlobalArgs, SUCCESS},
     events::CheckRequest,
-    github_client::OctorustClient,
     github_config::{GithubApiConfig, GithubAppConfig},
-    github_token::DefaultTokenFetcher,
-    runner::handler::{Config, Handler},
-    trace::init_fmt_with_pretty,
+    relay_signature::{RelaySignature, RelaySignatureConfig},
+    runner::{handler::Config, DefaultHandler, RunnerBuilder},
+    tls::TlsConfig,
+    trace::{init_fmt_with_pretty, TraceContext},
 };
 
 #[derive(Debug, Clone, Args)]
@@ -49,59 +70,141 @@ pub struct ServerArgs {
     /// The port to listen on.
     #[arg(long, default_value = "3001")]
     port: u16,
+    /// Path of the Unix domain socket to listen on instead of TCP `--address` and `--port`.
+    /// Existing socket at the path will be removed, but any other file at the path fails the startup.
+    #[arg(long, env, conflicts_with_all = ["address", "port", "tls_cert"])]
+    unix_socket: Option<PathBuf>,
+    #[command(flatten)]
+    tls_config: TlsConfig,
+    #[command(flatten)]
+    relay_signature_config: RelaySignatureConfig,
+    /// Reject signed requests whose timestamp differs from the current time more than this, to prevent replays. See
+    /// `--relay-secret`.
+    #[arg(long, env, default_value = "5m")]
+    relay_max_skew: humantime::Duration,
+    /// Cancel the in-progress job of the same repository and pull request when a newer event arrives, concluding its
+    /// check runs as cancelled, as its result is obsolete. Only jobs in this server process are cancelled.
+    #[arg(long, env, default_value = "false")]
+    cancel_in_progress: bool,
 }
 
 #[derive(Debug, Clone, ValueEnum, Display)]
 #[strum(serialize_all = "snake_case")]
 #[clap(rename_all = "snake_case")]
-enum Selection {
+pub enum Selection {
     PullRequest,
     CheckSuite,
+    MergeGroup,
 }
 
 impl Selection {
-    fn matches(&self, req: &CheckRequest) -> bool {
+    pub fn matches(&self, req: &CheckRequest) -> bool {
         match self {
             Self::PullRequest => {
-                req.event_name == "pull_request"
-                    || (req.event_name == "check_suite" && req.action == "rerequested")
+                matches!(req.event_name.as_str(), "pull_request" | "issue_comment")
+                    || (matches!(req.event_name.as_str(), "check_suite" | "check_run")
+                        && req.action == "rerequested")
             }
             Self::CheckSuite => req.event_name == "check_suite",
+            Self::MergeGroup => req.event_name == "merge_group",
         }
     }
 }
 
 struct AppState {
-    handler: Handler<OctorustClient, Libgit2Checkout, DefaultTokenFetcher>,
+    handler: DefaultHandler,
     selection: Selection,
+    signature: Option<RelaySignature>,
+    max_skew: Duration,
 }
 
 pub async fn server(global: GlobalArgs, args: ServerArgs) -> CommandResult {
-    init_fmt_with_pretty(&global.verbose);
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let tls = args.tls_config.load().await?;
+
+    let mut handler = RunnerBuilder::new(
+        args.github_app_config,
+        args.github_config,
+        args.checkout_config,
+        args.handler_config,
+    )
+    .build()?;
+    if args.cancel_in_progress {
+        handler = handler.with_cancel_in_progress();
+    }
+    handler.preflight().await?;
+    let state = AppState {
+        handler,
+        selection: args.select,
+        signature: args.relay_signature_config.build(),
+        max_skew: args.relay_max_skew.into(),
+    };
+    let app = build_app(state);
 
-    let client = OctorustClient::new(args.github_config.clone(), args.github_app_config.clone())?;
-    let checkout = Libgit2Checkout::new(args.checkout_config);
-    let fetcher =
-        DefaultTokenFetcher::new(args.github_config.clone(), args.github_app_config.clone())?;
-    let handler = Handler::new(args.handler_config, client, checkout, fetcher);
-    let app = build_app(handler, args.select);
+    if let Some(path) = args.unix_socket {
+        return serve_unix(&path, app).await;
+    }
 
     let listener = TcpListener::bind([args.address, args.port.to_string()].join(":")).await?;
     println!("listening on {}", listener.local_addr()?);
-    serve(listener, app).await?;
+    match tls {
+        Some(tls) => {
+            from_tcp_rustls(listener.into_std()?, tls)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        None => serve(listener, app).await?,
+    }
 
     SUCCESS
 }
 
-fn build_app(
-    handler: Handler<OctorustClient, Libgit2Checkout, DefaultTokenFetcher>,
-    selection: Selection,
-) -> Router {
-    let shared_state = Arc::new(AppState { handler, selection });
+// axum::serve only supports TcpListener, so serve each connection with hyper directly.
+async fn serve_unix(path: &Path, app: Router) -> CommandResult {
+    // Remove the socket left by a previous run, but never a regular file given by mistake.
+    match symlink_metadata(path).await {
+        Ok(m) if m.file_type().is_socket() => {
+            remove_file(path)
+                .await
+                .with_context(|| format!("failed to remove existing socket: {}", path.display()))?;
+        }
+        Ok(_) => bail!(
+            "unix socket path exists and is not a socket: {}",
+            path.display()
+        ),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("failed to inspect unix socket path: {}", path.display()))
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to bind unix socket: {}", path.display()))?;
+    println!("listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let app = app.clone();
+        spawn(async move {
+            let service = service_fn(move |req: Request<Incoming>| app.clone().oneshot(req));
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!(error = ?e, "failed to serve unix socket connection");
+            }
+        });
+    }
+}
+
+fn build_app(state: AppState) -> Router {
+    let shared_state = Arc::new(state);
 
     let router = Router::new()
         .route("/", get(|| async { "ok" }))
         .route("/run", post(handle))
+        .route("/version", get(|| async { Json(BuildInfo::get()) }))
         .with_state(shared_state);
 
     apply_middleware(router)
@@ -120,22 +223,47 @@ fn apply_middleware(router: Router) -> Router {
                 ),
         )
         .layer(NormalizePathLayer::trim_trailing_slash())
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new())
         .layer(TimeoutLayer::new(Duration::from_secs(60 * 15)));
     router.layer(middleware)
 }
 
 async fn handle(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<CheckRequest>,
-) -> Result<&'static str, AppError> {
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    // Verify the raw body before parsing it.
+    if let Some(signature) = &state.signature {
+        if let Err(e) = signature.verify(&headers, &body, Utc::now(), state.max_skew) {
+            warn!("relay request verification failed: {e}");
+            return Err(AppError::AuthorizationError);
+        }
+    }
+    let req = match Json::<CheckRequest>::from_bytes(&body) {
+        Ok(Json(req)) => req,
+        Err(rejection) => return Ok(rejection.into_response()),
+    };
+
     if !state.selection.matches(&req) {
         info!(
             "skipping event: selection={}, event={}, action={}",
             state.selection, req.event_name, req.action
         );
-        return Ok("skipped");
+        return Ok("skipped".into_response());
     }
 
-    state.handler.handle_event(req).await?;
-    Ok("ok")
+    // Continue the trace of the relay if given, see `TraceContext`.
+    match TraceContext::from_headers(&headers) {
+        Some(cx) => {
+            state
+                .handler
+                .handle_event(req)
+                .instrument(cx.span())
+                .await?;
+        }
+        None => state.handler.handle_event(req).await?,
+    }
+    Ok("ok".into_response())
 }
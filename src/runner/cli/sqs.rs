@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use aws_lambda_events::eventbridge::EventBridgeEvent;
+use aws_sdk_sqs::{types::Message, Client};
+use clap::Args;
+use serde::Deserialize;
+use tokio::{
+    spawn,
+    time::{interval_at, sleep, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+use super::server::Selection;
+use crate::{
+    checkout::CheckoutConfig,
+    cli::{CommandResult, GlobalArgs},
+    events::CheckRequest,
+    github_config::{GithubApiConfig, GithubAppConfig},
+    runner::{handler::Config, DefaultHandler, RunnerBuilder},
+    trace::init_fmt_with_pretty,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct SqsArgs {
+    #[command(flatten)]
+    github_app_config: GithubAppConfig,
+    #[command(flatten)]
+    github_config: GithubApiConfig,
+    #[command(flatten)]
+    checkout_config: CheckoutConfig,
+    #[command(flatten)]
+    handler_config: Config,
+    /// Filter events to process.
+    #[arg(short, long, default_value = "pull_request")]
+    select: Selection,
+    /// URL of the SQS queue to receive events.
+    #[arg(long, env)]
+    sqs_queue_url: String,
+    /// Visibility timeout of received messages. While handling a message, the timeout is extended every half of this
+    /// duration, so jobs longer than this are not redelivered.
+    #[arg(long, env, default_value = "60s", value_parser = parse_visibility_timeout)]
+    sqs_visibility_timeout: humantime::Duration,
+    /// How long to wait before receiving again after an SQS error.
+    #[arg(long, env, default_value = "5s")]
+    sqs_retry_interval: humantime::Duration,
+}
+
+// Maximum of long polling wait time.
+const WAIT_TIME_SECONDS: i32 = 20;
+// SQS accepts visibility timeout up to 12 hours.
+const MAX_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+
+fn parse_visibility_timeout(s: &str) -> Result<humantime::Duration> {
+    let d: humantime::Duration = s.parse()?;
+    if d.as_secs() < 2 || *d > MAX_VISIBILITY_TIMEOUT {
+        bail!("visibility timeout must be between 2s and 12h: {s}");
+    }
+    Ok(d)
+}
+
+/// Message body sent directly or via EventBridge rule target.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Body {
+    EventBridge(EventBridgeEvent<CheckRequest>),
+    Plain(CheckRequest),
+}
+
+impl From<Body> for CheckRequest {
+    fn from(v: Body) -> Self {
+        match v {
+            Body::EventBridge(e) => e.detail,
+            Body::Plain(r) => r,
+        }
+    }
+}
+
+/// Parse the message body sent directly or via EventBridge rule target. Also used by the lambda with SQS input and redrive.
+pub(super) fn parse_body(body: &str) -> Result<CheckRequest> {
+    serde_json::from_str::<Body>(body)
+        .map(CheckRequest::from)
+        .with_context(|| "deserializing message failed")
+}
+
+/// Long-poll SQS queue and handle each message. Messages are deleted only after handled successfully, failed
+/// messages become visible again after the visibility timeout and are redriven by the queue's redrive policy.
+pub async fn sqs(global: GlobalArgs, args: SqsArgs) -> CommandResult {
+    let _guard = init_fmt_with_pretty(&global)?;
+
+    let handler = RunnerBuilder::new(
+        args.github_app_config,
+        args.github_config,
+        args.checkout_config,
+        args.handler_config,
+    )
+    .build()?;
+    handler.preflight().await?;
+    let sdk_config = aws_config::load_from_env().await;
+    let consumer = Consumer {
+        client: Client::new(&sdk_config),
+        handler,
+        selection: args.select,
+        queue_url: args.sqs_queue_url,
+        visibility_timeout: *args.sqs_visibility_timeout,
+    };
+
+    info!(queue_url = consumer.queue_url, "receiving events from SQS");
+    loop {
+        match consumer.receive().await {
+            Ok(messages) => {
+                for message in messages {
+                    consumer.process(message).await;
+                }
+            }
+            Err(e) => {
+                warn!(error = ?e, "receiving messages from SQS failed, retrying");
+                sleep(*args.sqs_retry_interval).await;
+            }
+        }
+    }
+}
+
+struct Consumer {
+    client: Client,
+    handler: DefaultHandler,
+    selection: Selection,
+    queue_url: String,
+    visibility_timeout: Duration,
+}
+
+impl Consumer {
+    async fn receive(&self) -> Result<Vec<Message>> {
+        let out = self
+            .client
+            .receive_message()
+            .queue_url(&self.queue_url)
+            .max_number_of_messages(1)
+            .wait_time_seconds(WAIT_TIME_SECONDS)
+            .visibility_timeout(visibility_secs(self.visibility_timeout))
+            .send()
+            .await?;
+        Ok(out.messages.unwrap_or_default())
+    }
+
+    async fn process(&self, message: Message) {
+        let id = message.message_id.clone().unwrap_or_default();
+        let Some(receipt_handle) = message.receipt_handle else {
+            warn!(id, "message has no receipt handle, skipping");
+            return;
+        };
+        let req = message
+            .body
+            .with_context(|| "message has no body")
+            .and_then(|b| parse_body(&b));
+        match req {
+            // Leave malformed messages for the redrive policy, e.g. moving to dead-letter queue.
+            Err(e) => {
+                error!(error = ?e, id, "malformed message, leaving for redrive");
+                return;
+            }
+            Ok(req) if !self.selection.matches(&req) => info!(
+                "skipping event: selection={}, event={}, action={}",
+                self.selection, req.event_name, req.action
+            ),
+            Ok(req) => {
+                let heartbeat = spawn(heartbeat(
+                    self.client.clone(),
+                    self.queue_url.clone(),
+                    receipt_handle.clone(),
+                    self.visibility_timeout,
+                ));
+                let res = self.handler.handle_event(req).await;
+                heartbeat.abort();
+                if let Err(e) = res {
+                    error!(error = ?e, id, "handling event failed, leaving for redrive");
+                    return;
+                }
+            }
+        }
+
+        if let Err(e) = self
+            .client
+            .delete_message()
+            .queue_url(&self.queue_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await
+        {
+            // The message will be delivered again, handling should be idempotent by reusing check runs.
+            error!(error = ?e, id, "deleting message failed");
+        }
+    }
+}
+
+// Extend visibility timeout every half of it while the job is running.
+async fn heartbeat(client: Client, queue_url: String, receipt_handle: String, timeout: Duration) {
+    let every = timeout / 2;
+    let mut ticker = interval_at(Instant::now() + every, every);
+    loop {
+        ticker.tick().await;
+        match client
+            .change_message_visibility()
+            .queue_url(&queue_url)
+            .receipt_handle(&receipt_handle)
+            .visibility_timeout(visibility_secs(timeout))
+            .send()
+            .await
+        {
+            Ok(_) => debug!("extended message visibility timeout"),
+            Err(e) => warn!(error = ?e, "extending message visibility timeout failed"),
+        }
+    }
+}
+
+// Bounded by parse_visibility_timeout, saturated otherwise.
+pub(super) fn visibility_secs(d: Duration) -> i32 {
+    d.as_secs().try_into().unwrap_or(i32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_body() {
+        let plain = serde_json::to_string(&CheckRequest {
+            request_id: "plain".to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        let req: CheckRequest = serde_json::from_str::<Body>(&plain).unwrap().into();
+        assert_eq!(req.request_id, "plain");
+
+        let envelope = format!(
+            r#"{{"version":"0","id":"x","detail-type":"orgu.check_request","source":"orgu-front","account":"1","time":"2024-01-01T00:00:00Z","region":"us-east-1","resources":[],"detail":{}}}"#,
+            plain.replace("plain", "envelope")
+        );
+        let req: CheckRequest = serde_json::from_str::<Body>(&envelope).unwrap().into();
+        assert_eq!(req.request_id, "envelope");
+    }
+}
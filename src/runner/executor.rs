@@ -0,0 +1,49 @@
+use std::{io::ErrorKind, process::Output, time::Duration};
+
+use anyhow::{bail, Context as _, Result};
+use tokio::{process::Command, time::timeout};
+
+use crate::runner::hanlder_view::fmt_cmd;
+
+#[derive(Debug)]
+pub enum ExecResult {
+    /// The command exited, successfully or not.
+    Completed(Output),
+    /// The command didn't exit within the timeout and was killed.
+    TimedOut,
+}
+
+/// Executes the job command. Errors are returned only when the command couldn't be executed.
+// Futures are not required to be Send. See `future_not_send` in Cargo.toml.
+#[allow(async_fn_in_trait)]
+#[cfg_attr(test, mockall::automock)]
+pub trait CommandExecutor: Sync + Send {
+    async fn run(&self, cmd: &mut Command, timeout: Duration) -> Result<ExecResult>;
+}
+
+/// Runs the command as a child process on the same host.
+#[derive(Debug, Clone, Default)]
+pub struct LocalExecutor;
+
+impl CommandExecutor for LocalExecutor {
+    async fn run(&self, cmd: &mut Command, duration: Duration) -> Result<ExecResult> {
+        // Without strong guarantee of killing the child process.
+        // https://docs.rs/tokio/latest/tokio/process/struct.Command.html#method.kill_on_drop
+        cmd.kill_on_drop(true);
+
+        match timeout(duration, cmd.output()).await {
+            Ok(Err(e)) if e.kind() == ErrorKind::NotFound => {
+                bail!(
+                    "program `{}` not found on PATH; check your --command and the runner image",
+                    cmd.as_std().get_program().to_string_lossy()
+                );
+            }
+            Ok(res) => {
+                let out =
+                    res.with_context(|| format!("failed to run command: {}", fmt_cmd(cmd)))?;
+                Ok(ExecResult::Completed(out))
+            }
+            Err(_) => Ok(ExecResult::TimedOut),
+        }
+    }
+}
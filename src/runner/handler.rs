@@ -1,22 +1,55 @@
-use std::{collections::HashMap, env, future::Future, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    future::Future,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    process::Output,
+    slice,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{Context as _, Result};
-use clap::Args;
+use anyhow::{anyhow, bail, Context as _, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use futures::{future::join_all, FutureExt as _};
+use octorust::types::{
+    CheckRun, ChecksCreateRequest, ChecksCreateRequestConclusion, ChecksUpdateRequest,
+};
+use serde::{ser::SerializeStruct as _, Serialize, Serializer};
+use thiserror::Error;
 use tokio::{
     process::Command,
-    time::{timeout, Instant},
+    select,
+    time::{sleep, timeout, Instant},
 };
-use tracing::{error, info, info_span, instrument, Instrument};
+use tracing::{error, info, info_span, instrument, warn, Instrument};
 
 use crate::{
-    checkout::{Checkout, CheckoutError, CheckoutInput},
-    events::CheckRequest,
-    github_client::GithubClient,
+    checkout::{Checkout, CheckoutError, CheckoutInput, WorkDir},
+    config_serde::{display, display_opt, REDACTED},
+    event_queue_client::SCHEMA_VERSION,
+    events::{CheckRequest, CustomPropValue, GithubRepository},
+    github_client::{
+        into_update_request, name_with_environment, DeploymentInput, DeploymentState,
+        DeploymentStatusInput, GithubClient, ReactionContent,
+    },
     github_token::TokenFetcher,
-    runner::hanlder_view::{fmt_cmd, CreateInput, UpdateInputBase},
+    runner::{
+        active_jobs::ActiveJobs,
+        annotations::AnnotationConfig,
+        audit::{append, AuditRecord, JobOutcome},
+        executor::{CommandExecutor, ExecResult},
+        hanlder_view::{
+            fmt_cmd, with_annotations, with_outcomes, with_timing, CommandOutcome, CreateInput,
+            UpdateInputBase, ViewConfig,
+        },
+        redelivery::{DeliveryCounter, TRACKED_DELIVERIES},
+    },
 };
 
-#[derive(Debug, Clone, Args)]
+#[derive(Debug, Clone, Args, Serialize)]
 pub struct Config {
     /// Job name to be used in the check run and reviewdog annotation.
     #[clap(long, env)]
@@ -24,25 +57,329 @@ pub struct Config {
     /// Command to run. To be executed without any shell.
     #[clap(required = true, last = true, env, num_args = 1.., value_delimiter = ' ')]
     command: Vec<String>,
+    /// Commands to run instead of COMMAND for repositories matching the patterns, as a JSON object of repository full
+    /// name patterns to commands, e.g. `{"owner/infra-*":["make","lint-infra"]}`. `*` matches any characters. The
+    /// longest matching pattern wins. Validated at startup. Disabled if none.
+    #[clap(long, env, value_parser = parse_command_map)]
+    command_map: Option<CommandMap>,
+    /// Custom property of the repository to pick the command by its value from `--property-command-map`, e.g.
+    /// `language`. Falls back to COMMAND if the property is absent or unmapped. `--command-map` takes precedence.
+    /// Disabled if none.
+    #[clap(long, env, requires = "property_command_map")]
+    command_by_property: Option<String>,
+    /// Commands to run for the values of `--command-by-property`, as a JSON object of property values to commands,
+    /// e.g. `{"rust":["cargo","clippy"]}`. The first mapped value is used for multi-select properties. Validated at
+    /// startup.
+    #[clap(long, env, value_parser = parse_command_map, requires = "command_by_property")]
+    property_command_map: Option<CommandMap>,
     /// Wrap stdout and stderr with code block in the check run output.
     #[clap(long, env, default_value = "true")]
     wrap_stdout: bool,
     /// Timeout for the command execution.
     #[clap(long, env, default_value = "10m")]
+    #[serde(serialize_with = "display")]
     job_timeout: humantime::Duration,
+    /// Programs allowed to be executed as the command. Can be passed multiple times.
+    /// If empty, any program is allowed.
+    #[clap(long, env, value_delimiter = ',')]
+    allowed_programs: Vec<String>,
+    /// Reuse the existing check run for the same delivery instead of creating a new one on redelivery.
+    #[clap(long, env, default_value = "false")]
+    reuse_check_runs: bool,
+    #[command(flatten)]
+    view: ViewConfig,
+    #[command(flatten)]
+    annotations: AnnotationConfig,
+    /// Timeout for handling the whole event including token fetch, checkout, the command execution and reporting.
+    /// The check run is concluded as timed out when exceeded. Disabled if none.
+    #[clap(long, env)]
+    #[serde(serialize_with = "display_opt")]
+    request_timeout: Option<humantime::Duration>,
+    /// Skip events sent by the given GitHub logins, e.g. `dependabot[bot]`. The check run is concluded as neutral.
+    #[clap(long, env, value_delimiter = ',')]
+    skip_senders: Vec<String>,
+    /// Run only for pull requests and merge groups targeting base branches matching any of these patterns, e.g.
+    /// `main,release/*`. `*` matches any characters. Other events are concluded as neutral. Events without a base
+    /// branch, e.g. check_suite, always run. All branches run if empty.
+    #[clap(long = "base-ref-filter", env, value_delimiter = ',')]
+    base_ref_filters: Vec<String>,
+    /// Run the command for each value of the matrix, e.g. `OS=linux,macos`. Each value is reported as its own check
+    /// run named `run-<job_name>-<value>` and passed to the command as the env var.
+    #[clap(long, env, value_parser = parse_matrix)]
+    matrix: Option<Matrix>,
+    /// Keep the working directory instead of removing it after the command, to inspect it for debugging. The path is
+    /// logged. Kept directories don't contain the token, but may contain artifacts written by the command.
+    #[clap(long, env, value_enum, default_value = "never")]
+    keep_checkout: KeepCheckout,
+    /// Also post the job result as a sticky comment on the pull request, updated on re-run. The check run is still
+    /// the source of truth, failing to post the comment is only logged.
+    #[clap(long, env, default_value = "false")]
+    pr_comment: bool,
+    /// React to the comment which triggered the job by `front server --comment-command`, with rocket on success and
+    /// thumbs down on failure, as GitHub has no cross mark reaction. Failing to react is only logged.
+    #[clap(long, env, default_value = "false")]
+    comment_reaction: bool,
+    /// Also report the job as a GitHub deployment of the commit, for jobs deploying it. The deployment goes pending,
+    /// in progress, then success or failure along with the job. The check run is still the source of truth, failing
+    /// to report the deployment is only logged. Left in progress if `--request-timeout` is exceeded.
+    #[clap(long, env, default_value = "false")]
+    deployment_mode: bool,
+    /// Environment of the deployment in `--deployment-mode`, e.g. `staging`.
+    #[clap(long, env, default_value = "production")]
+    deployment_environment: String,
+    #[command(flatten)]
+    #[serde(flatten)]
+    job_env: JobEnvConfig,
+    /// Timeout for reporting timeouts and failures to the check run, not to hang again on a slow Checks API after
+    /// the job has already hung or failed.
+    #[clap(long, env, default_value = "30s")]
+    #[serde(serialize_with = "display")]
+    report_timeout: humantime::Duration,
+    /// Give up an event redelivered more than this many times to this runner process, concluding the check run as
+    /// failed without running the command. Stops a poison event from being retried forever. Disabled if none.
+    #[clap(long, env)]
+    max_redeliveries: Option<u32>,
+    /// Check the GitHub App installation grants `checks: write` at startup and fail fast if not, instead of failing
+    /// to create check runs for every event. Costs an extra installation token.
+    #[clap(long, env, default_value = "false")]
+    preflight_permissions: bool,
+    /// Number of retries of the whole job, from creating the check runs to running the command, on orgu or
+    /// infrastructure errors, e.g. a transient token fetch or checkout failure. Command failures and timeouts are
+    /// results of the job and never retried, nor are errors running the command. A new check run is created for
+    /// each attempt unless `--reuse-check-runs` is enabled.
+    #[clap(long, env, default_value = "0")]
+    job_retries: u32,
+    /// Interval between the retries of the whole job. See `--job-retries`.
+    #[clap(long, env, default_value = "5s")]
+    #[serde(serialize_with = "display")]
+    job_retry_interval: humantime::Duration,
+    /// Append a JSON line per handled event to this file as an audit log: who sent it, the repository, commit,
+    /// command, outcome and duration. Unlike logs, the schema is stable and records are written regardless of the log
+    /// level. Failing to write is logged as an error. Disabled if none.
+    #[clap(long, env)]
+    audit_log: Option<PathBuf>,
+    /// Fail events with a newer schema version than this runner understands instead of only warning, so they are
+    /// retried or redriven, e.g. to a dead-letter queue, until runners are updated. Newer fields are lost otherwise.
+    #[clap(long, env, default_value = "false")]
+    reject_unknown_schema_version: bool,
+}
+
+impl Config {
+    /// Validate the commands of `--command-map` and `--property-command-map` not to fail every event of the mapped
+    /// repositories.
+    pub fn validate(&self) -> Result<()> {
+        let maps = [
+            ("command map", &self.command_map),
+            ("property command map", &self.property_command_map),
+        ];
+        for (name, map) in maps {
+            for (key, command) in map.iter().flat_map(|m| &m.0) {
+                let Some(program) = command.first() else {
+                    bail!("invalid {name}: empty command for `{key}`");
+                };
+                if !self.allowed_programs.is_empty() && !self.allowed_programs.contains(program) {
+                    bail!(
+                        "invalid {name}: program is not allowed to run for `{key}`: {program}. See \
+                         --allowed-programs."
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Command for the repository: the one of the most specific pattern in the command map, then the one of the
+    // custom property value, then the default.
+    fn command_for(&self, repo: &GithubRepository) -> &[String] {
+        let by_name = self
+            .command_map
+            .iter()
+            .flat_map(|m| &m.0)
+            .filter(|(pattern, _)| glob_match(pattern, &repo.full_name))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, command)| command);
+        by_name
+            .or_else(|| self.command_by_property(repo))
+            .unwrap_or(&self.command)
+    }
+
+    fn command_by_property(&self, repo: &GithubRepository) -> Option<&Vec<String>> {
+        let key = self.command_by_property.as_ref()?;
+        let map = self.property_command_map.as_ref()?;
+        let values = match repo.custom_properties.get(key)? {
+            CustomPropValue::Single(v) => slice::from_ref(v),
+            CustomPropValue::Multiple(vs) => vs.as_slice(),
+        };
+        values.iter().find_map(|v| map.0.get(v))
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct JobEnvConfig {
+    /// Env var set for every job as `KEY=value`, e.g. `RUST_BACKTRACE=1`. Values are literal. Env vars set by orgu,
+    /// e.g. `CI_*` and `GITHUB_TOKEN`, take precedence. Can be passed multiple times.
+    #[clap(long = "static-env", env, value_parser = parse_static_env)]
+    static_envs: Vec<StaticEnv>,
+    /// Keys of the static env vars holding secrets, e.g. `INTERNAL_API_KEY`. Their values are redacted in the
+    /// effective configuration and masked in the command output reported to the check run.
+    #[clap(long, env, value_delimiter = ',')]
+    secret_env_keys: Vec<String>,
+}
+
+impl JobEnvConfig {
+    fn is_secret(&self, key: &str) -> bool {
+        self.secret_env_keys.iter().any(|k| k == key)
+    }
+
+    // Replace secret values in the command output not to expose them in the check run. Invalid UTF-8 sequences are
+    // replaced as well, which is done when rendering the output anyway.
+    fn mask(&self, mut out: Output) -> Output {
+        let secrets = self
+            .static_envs
+            .iter()
+            .filter(|e| !e.value.is_empty() && self.is_secret(&e.key))
+            .map(|e| e.value.as_str())
+            .collect::<Vec<_>>();
+        if secrets.is_empty() {
+            return out;
+        }
+        let mask = |v: &[u8]| {
+            secrets
+                .iter()
+                .fold(String::from_utf8_lossy(v).into_owned(), |s, secret| {
+                    s.replace(secret, REDACTED)
+                })
+                .into_bytes()
+        };
+        out.stdout = mask(&out.stdout);
+        out.stderr = mask(&out.stderr);
+        out
+    }
+}
+
+impl Serialize for JobEnvConfig {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let static_envs = self
+            .static_envs
+            .iter()
+            .map(|e| StaticEnv {
+                key: e.key.clone(),
+                value: if self.is_secret(&e.key) {
+                    REDACTED.to_owned()
+                } else {
+                    e.value.clone()
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut st = s.serialize_struct("JobEnvConfig", 2)?;
+        st.serialize_field("static_envs", &static_envs)?;
+        st.serialize_field("secret_env_keys", &self.secret_env_keys)?;
+        st.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeepCheckout {
+    /// Always remove the working directory.
+    #[default]
+    Never,
+    /// Keep the working directory if the command failed, timed out or orgu failed to run it.
+    OnFailure,
+    /// Always keep the working directory.
+    Always,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Matrix {
+    key: String,
+    values: Vec<String>,
+}
+
+fn parse_matrix(s: &str) -> Result<Matrix> {
+    let (key, values) = s
+        .split_once('=')
+        .with_context(|| format!("invalid matrix: no `=` found in `{s}`"))?;
+    let values = values
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    if key.is_empty() || values.is_empty() {
+        bail!("invalid matrix: key and values are required: `{s}`");
+    }
+    Ok(Matrix {
+        key: key.to_owned(),
+        values,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandMap(BTreeMap<String, Vec<String>>);
+
+fn parse_command_map(s: &str) -> Result<CommandMap> {
+    let map = serde_json::from_str::<BTreeMap<String, Vec<String>>>(s).with_context(|| {
+        format!("invalid command map: expected a JSON object of commands: `{s}`")
+    })?;
+    if map.keys().any(String::is_empty) {
+        bail!("invalid command map: empty key: `{s}`");
+    }
+    Ok(CommandMap(map))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StaticEnv {
+    key: String,
+    value: String,
+}
+
+fn parse_static_env(s: &str) -> Result<StaticEnv> {
+    let (key, value) = s
+        .split_once('=')
+        .with_context(|| format!("invalid static env: no `=` found in `{s}`"))?;
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        bail!("invalid static env: invalid key: `{s}`");
+    }
+    Ok(StaticEnv {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+// Errors after the check runs are ready to run the command, e.g. disallowed or missing programs. Not retried as they
+// are deterministic or the command may have already run.
+#[derive(Debug, Error)]
+#[error(transparent)]
+struct CommandError(anyhow::Error);
+
+// Deployment being reported in `--deployment-mode`. Keeps the token not to fetch one for every status.
+#[derive(Debug)]
+struct Deployment {
+    id: i64,
+    token: String,
+}
+
+#[derive(Debug, Clone)]
+struct Variant {
+    key: String,
+    value: String,
 }
 
 #[derive(Debug)]
-pub struct Handler<CL: GithubClient, CH: Checkout, F: TokenFetcher> {
+pub struct Handler<CL: GithubClient, CH: Checkout, F: TokenFetcher, E: CommandExecutor> {
     config: Config,
     runner_job_name: String,
     client: CL,
     checkout: CH,
     token_fetcher: F,
+    executor: E,
+    deliveries: DeliveryCounter,
+    // Some if cancelling in-progress jobs is enabled.
+    active_jobs: Option<ActiveJobs>,
 }
 
-impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
-    pub fn new(config: Config, client: CL, checkout: CH, fetcher: F) -> Self {
+impl<CL: GithubClient, CH: Checkout, F: TokenFetcher, E: CommandExecutor> Handler<CL, CH, F, E> {
+    pub fn new(config: Config, client: CL, checkout: CH, fetcher: F, executor: E) -> Self {
         let runner_job_name = format!("run-{}", config.job_name);
         Self {
             config,
@@ -50,6 +387,39 @@ impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
             client,
             checkout,
             token_fetcher: fetcher,
+            executor,
+            deliveries: DeliveryCounter::new(TRACKED_DELIVERIES),
+            active_jobs: None,
+        }
+    }
+
+    /// Cancel the in-progress job of the same pull request when a newer event arrives, concluding its check runs as
+    /// cancelled. Only meaningful for long-running processes handling events concurrently, e.g. the runner server.
+    pub fn with_cancel_in_progress(mut self) -> Self {
+        self.active_jobs = Some(ActiveJobs::default());
+        self
+    }
+
+    /// Verify the permissions required to report check runs if `--preflight-permissions` is enabled.
+    pub async fn preflight(&self) -> Result<()> {
+        if !self.config.preflight_permissions {
+            return Ok(());
+        }
+        let permissions = self
+            .token_fetcher
+            .fetch_permissions()
+            .await
+            .with_context(|| "preflight: fetching installation token permissions failed")?;
+        match permissions.get("checks").map(String::as_str) {
+            Some("write") => {
+                info!("preflight: checks write permission is granted");
+                Ok(())
+            }
+            granted => bail!(
+                "preflight: GitHub App installation lacks `checks: write` permission (granted: {}), update the \
+                 app permissions and accept them in the installation",
+                granted.unwrap_or("none")
+            ),
         }
     }
 
@@ -63,134 +433,665 @@ impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
         ),
     )]
     pub async fn handle_event(&self, req: CheckRequest) -> Result<()> {
-        with_event_logging(req.clone(), async move { self.do_handle_event(req).await }).await
+        with_event_logging(req.clone(), async move {
+            self.check_schema_version(&req)?;
+            let started_at = Utc::now();
+            let res = self.handle_with_retries(&req).await;
+            self.write_audit_log(&req, started_at, &res).await;
+            self.react_to_comment(&req, &res).await;
+            res.map(|_| ())
+        })
+        .await
+    }
+
+    async fn handle_with_retries(&self, req: &CheckRequest) -> Result<JobOutcome> {
+        // Record the delivery once, retries of the job are not redeliveries.
+        let exceeded = self.exceeded_redeliveries(req);
+        let mut attempt = 0;
+        loop {
+            let e = match self.do_handle_event(req.clone(), exceeded).await {
+                Err(e)
+                    if attempt < self.config.job_retries
+                        && e.downcast_ref::<CommandError>().is_none() =>
+                {
+                    e
+                }
+                res => return res,
+            };
+            attempt += 1;
+            let interval = self.config.job_retry_interval;
+            warn!(attempt, error = ?e, "job failed with orgu error, retrying in {interval}");
+            sleep(interval.into()).await;
+        }
     }
 
-    async fn do_handle_event(&self, req: CheckRequest) -> Result<()> {
-        let create_input = CreateInput {
-            req: req.clone(),
-            name: self.runner_job_name.clone(),
-            command: self.config.command.clone(),
+    // Failing to write the audit log doesn't fail the event, not to run the job again.
+    async fn write_audit_log(
+        &self,
+        req: &CheckRequest,
+        started_at: DateTime<Utc>,
+        res: &Result<JobOutcome>,
+    ) {
+        let Some(path) = &self.config.audit_log else {
+            return;
         };
-        let check_run = self
-            .client
-            .create_check_run(
-                &req.repository.owner.login,
-                &req.repository.name,
-                &create_input.clone().into(),
-            )
+        let outcome = res.as_ref().map_or(JobOutcome::Error, |o| *o);
+        let command = self.config.command_for(&req.repository);
+        let record = AuditRecord::new(
+            req,
+            &self.config.job_name,
+            command,
+            outcome,
+            started_at,
+            Utc::now(),
+        );
+        append(path, &record)
+            .await
+            .unwrap_or_else(|e| error!(error = ?e, "failed to write audit log"));
+    }
+
+    // React to the triggering comment if `--comment-reaction` is enabled. Skipped jobs and jobs cancelled by a newer
+    // event are not reacted to.
+    async fn react_to_comment(&self, req: &CheckRequest, res: &Result<JobOutcome>) {
+        let Some(comment_id) = req.comment_id.filter(|_| self.config.comment_reaction) else {
+            return;
+        };
+        let content = match res {
+            Ok(JobOutcome::Skipped | JobOutcome::Cancelled) => return,
+            Ok(JobOutcome::Succeeded) => ReactionContent::Rocket,
+            Ok(JobOutcome::Failed | JobOutcome::TimedOut | JobOutcome::Error) | Err(_) => {
+                ReactionContent::ThumbsDown
+            }
+        };
+        let (owner, repo) = (&req.repository.owner.login, &req.repository.name);
+        let res = async {
+            let token = self.token_fetcher.fetch_token().await?;
+            self.client
+                .create_reaction(&token, owner, repo, comment_id, content)
+                .await
+        }
+        .await;
+        res.unwrap_or_else(|e| warn!(error = ?e, ?content, "failed to react to comment"));
+    }
+
+    fn check_schema_version(&self, req: &CheckRequest) -> Result<()> {
+        let version = req.schema_version;
+        if version <= SCHEMA_VERSION {
+            return Ok(());
+        }
+        if self.config.reject_unknown_schema_version {
+            bail!(
+                "unknown schema version of the request: version={version}, supported={SCHEMA_VERSION}, update \
+                 the runner"
+            );
+        }
+        warn!(
+            version,
+            supported = SCHEMA_VERSION,
+            "unknown schema version of the request, handling anyway, update the runner"
+        );
+        Ok(())
+    }
+
+    async fn do_handle_event(
+        &self,
+        req: CheckRequest,
+        exceeded_redeliveries: Option<(u32, u32)>,
+    ) -> Result<JobOutcome> {
+        let owner = &req.repository.owner.login;
+        let repo = &req.repository.name;
+
+        let mut variants = self.variants();
+        // Re-run only the re-requested check run. Other runners and matrix variants skip the event.
+        if let Some(rerequested) = &req.check_run {
+            variants.retain(|v| self.check_run_name(v.as_ref()) == rerequested.name);
+            if variants.is_empty() {
+                info!(
+                    check_run = rerequested.name,
+                    "skipping event for other check run"
+                );
+                return Ok(JobOutcome::Skipped);
+            }
+        }
+        let mut update_inputs = Vec::with_capacity(variants.len());
+        for variant in &variants {
+            let create_input = CreateInput {
+                req: req.clone(),
+                name: self.check_run_name(variant.as_ref()),
+                command: self.config.command_for(&req.repository).to_vec(),
+                view: self.config.view.clone(),
+            };
+            // Conclude the check runs of the preceding variants if failed, not to leave them queued.
+            let check_run = self
+                .ensure_updating_check_runs(
+                    &update_inputs,
+                    self.create_or_reuse_check_run(owner, repo, create_input.clone().into()),
+                )
+                .await?;
+            update_inputs
+                .push(create_input.into_update_input(check_run.id, self.config.wrap_stdout));
+        }
+
+        if let Some((redeliveries, max)) = exceeded_redeliveries {
+            warn!(redeliveries, max, "too many redeliveries, giving up");
+            self.conclude_all(&update_inputs, |i| {
+                i.into_redeliveries_exceeded(redeliveries, max)
+            })
             .await?;
-        let update_input = create_input.into_update_input(check_run.id, self.config.wrap_stdout);
+            // Return Ok not to be retried again.
+            return Ok(JobOutcome::Failed);
+        }
+
+        if req.repository.is_inactive() {
+            info!("skipping event for archived or disabled repository");
+            self.conclude_all(&update_inputs, UpdateInputBase::into_repository_inactive)
+                .await?;
+            return Ok(JobOutcome::Skipped);
+        }
 
-        self.ensure_updating_check_run(update_input.clone(), async move {
-            let owner = &req.repository.owner.login;
-            let repo = &req.repository.name;
+        if self.config.skip_senders.contains(&req.sender.login) {
+            info!(sender = req.sender.login, "skipping event from sender");
+            self.conclude_all(&update_inputs, UpdateInputBase::into_sender_skipped)
+                .await?;
+            return Ok(JobOutcome::Skipped);
+        }
 
-            let token = self.token_fetcher.fetch_token().await?;
-            let checkout_input = CheckoutInput {
-                owner: owner.clone(),
-                repo: repo.clone(),
-                sha: req.head_sha.to_owned(),
-                token: token.to_owned(),
+        if let Some(base_ref) = self.skipped_base_ref(&req) {
+            info!(base_ref, "skipping event for base branch");
+            self.conclude_all(&update_inputs, |i| i.into_base_ref_skipped(base_ref))
+                .await?;
+            return Ok(JobOutcome::Skipped);
+        }
+
+        let Some(d) = self.config.request_timeout else {
+            return self
+                .process_event_cancellable(&req, &variants, &update_inputs)
+                .await;
+        };
+        match timeout(
+            d.into(),
+            self.process_event_cancellable(&req, &variants, &update_inputs),
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(_) => {
+                info!(timeout_config = %d, "request timed out");
+                self.conclude_all(&update_inputs, |i| i.into_request_timed_out(d))
+                    .await?;
+                // Same as command timeout, this is not orgu failure.
+                Ok(JobOutcome::TimedOut)
+            }
+        }
+    }
+
+    // Run the job until completed, or cancelled by a newer event of the same pull request if enabled. Same as request
+    // timeouts, a deployment of the cancelled job is left in progress.
+    async fn process_event_cancellable(
+        &self,
+        req: &CheckRequest,
+        variants: &[Option<Variant>],
+        update_inputs: &[UpdateInputBase],
+    ) -> Result<JobOutcome> {
+        let active = self.active_jobs.as_ref();
+        let Some(mut active) = active.and_then(|jobs| jobs.register(req, &self.config.job_name))
+        else {
+            return self.process_event(req, variants, update_inputs).await;
+        };
+        select! {
+            res = self.process_event(req, variants, update_inputs) => res,
+            () = active.cancelled() => {
+                info!("job cancelled by a newer event");
+                self.conclude_all(update_inputs, UpdateInputBase::into_superseded).await?;
+                Ok(JobOutcome::Cancelled)
+            }
+        }
+    }
+
+    // Run the job, reporting it as a deployment as well if enabled.
+    async fn process_event(
+        &self,
+        req: &CheckRequest,
+        variants: &[Option<Variant>],
+        update_inputs: &[UpdateInputBase],
+    ) -> Result<JobOutcome> {
+        let deployment = self.start_deployment(req).await;
+        let res = self
+            .run_job(req, variants, update_inputs, deployment.as_ref())
+            .await;
+        if let Some(d) = &deployment {
+            let state = match &res {
+                Ok(true) => DeploymentState::Success,
+                Ok(false) => DeploymentState::Failure,
+                Err(_) => DeploymentState::Error,
             };
-            let cloned = match self.checkout.create_dir_and_checkout(&checkout_input).await {
-                Ok(v) => v,
-                Err(e) => {
-                    match e.downcast_ref::<CheckoutError>() {
+            self.update_deployment(req, d, state).await;
+        }
+        res.map(|succeeded| {
+            if succeeded {
+                JobOutcome::Succeeded
+            } else {
+                JobOutcome::Failed
+            }
+        })
+    }
+
+    // Fetch token, checkout and run the command for each variant after the check runs are created.
+    // Returns whether all the commands succeeded.
+    async fn run_job(
+        &self,
+        req: &CheckRequest,
+        variants: &[Option<Variant>],
+        update_inputs: &[UpdateInputBase],
+        deployment: Option<&Deployment>,
+    ) -> Result<bool> {
+        let owner = &req.repository.owner.login;
+        let repo = &req.repository.name;
+
+        let prepared = self
+            .ensure_updating_check_runs(update_inputs, async {
+                let token = self.token_fetcher.fetch_token().await?;
+                let checkout_input = CheckoutInput {
+                    owner: owner.clone(),
+                    repo: repo.clone(),
+                    sha: req.head_sha.to_owned(),
+                    head_ref: req.pull_request_head_ref.clone(),
+                    token: Some(token.to_owned()),
+                };
+                match self.checkout.create_dir_and_checkout(&checkout_input).await {
+                    Ok(v) => Ok(Some((v, token))),
+                    Err(e) => match e.downcast_ref::<CheckoutError>() {
                         Some(CheckoutError::Timeout(d)) => {
                             info!(duration = %d, "checkout timed out");
-                            self.client
-                                .update_check_run(
-                                    owner,
-                                    repo,
-                                    check_run.id,
-                                    &update_input.into_checkout_timed_out(*d),
-                                )
+                            self.conclude_all(update_inputs, |i| i.into_checkout_timed_out(*d))
                                 .await?;
                             // Checkout timeout is not orgu failure, so early return Ok.
-                            return Ok(());
+                            Ok(None)
+                        }
+                        Some(CheckoutError::Authentication(source)) => {
+                            info!(error = %source, "checkout failed due to authentication");
+                            self.conclude_all(
+                                update_inputs,
+                                UpdateInputBase::into_checkout_unauthorized,
+                            )
+                            .await?;
+                            // Lacking permissions is to be fixed by the repository owner, not orgu failure.
+                            Ok(None)
                         }
-                        _ => return Err(e),
-                    }
+                        Some(CheckoutError::TooLarge(max)) => {
+                            info!(
+                                max_fetch_bytes = max,
+                                "checkout cancelled due to size limit"
+                            );
+                            self.conclude_all(update_inputs, |i| i.into_checkout_too_large(*max))
+                                .await?;
+                            // The fetch settings are to be adjusted by the repository owner, not orgu failure.
+                            Ok(None)
+                        }
+                        // Handler always checks out with a token.
+                        Some(CheckoutError::Anonymous(_)) | None => Err(e),
+                    },
                 }
+            })
+            .await?;
+        let Some((cloned, token)) = prepared else {
+            return Ok(false);
+        };
+        if let Some(d) = deployment {
+            self.update_deployment(req, d, DeploymentState::InProgress)
+                .await;
+        }
+
+        // Variants share the checkout read-only and run concurrently. Each variant is concluded on its own check run,
+        // reported after all variants completed to summarize their outcomes.
+        // On early return or panic, the temporary directory is still removed when the last reference is dropped.
+        let cloned = Arc::new(cloned);
+        let runs = variants.iter().zip(update_inputs).map(|(variant, input)| {
+            let (cloned, token) = (Arc::clone(&cloned), &token);
+            async move {
+                let run = async {
+                    let cmd = self.build_command(&cloned, req, token, variant.as_ref())?;
+                    let span = info_span!("run command", command = fmt_cmd(&cmd), path = %cloned.path.display());
+                    self.run_command(cmd, input).instrument(span).await
+                };
+                // A panic in one variant must not abort the others, report it as a failure of the variant instead.
+                let run = AssertUnwindSafe(run).catch_unwind().map(|r| {
+                    r.unwrap_or_else(|_| Err(anyhow!("command run panicked")))
+                });
+                self.ensure_updating_check_runs(slice::from_ref(input), run)
+                    .await
+                    .map_err(|e| anyhow::Error::from(CommandError(e)))
+            }
+        });
+        let results = join_all(runs).await;
+        // Errored variants are already concluded as failure.
+        let outcomes = results
+            .iter()
+            .map(|r| {
+                r.as_ref().map_or(
+                    CommandOutcome {
+                        succeeded: false,
+                        elapsed: Duration::ZERO,
+                    },
+                    |(outcome, _)| *outcome,
+                )
+            })
+            .collect::<Vec<_>>();
+        let reports = results.into_iter().zip(update_inputs).map(|(r, input)| {
+            let outcomes = &outcomes;
+            async move {
+                let (_, update) = r?;
+                let update = if outcomes.len() > 1 {
+                    with_outcomes(update, outcomes)
+                } else {
+                    update
+                };
+                // Failure of given command is not orgu failure, so just report the failure.
+                self.ensure_updating_check_runs(
+                    slice::from_ref(input),
+                    self.report_command_result(input, &update),
+                )
+                .await
+                .map_err(|e| anyhow::Error::from(CommandError(e)))
+            }
+        });
+        let results = join_all(reports).await;
+        let failed = outcomes.iter().any(|o| !o.succeeded) || results.iter().any(Result::is_err);
+
+        if let Some(cloned) = Arc::into_inner(cloned) {
+            let keep = match self.config.keep_checkout {
+                KeepCheckout::Never => false,
+                KeepCheckout::OnFailure => failed,
+                KeepCheckout::Always => true,
             };
+            if keep {
+                let path = cloned.into_keep();
+                warn!(path = %path.display(), failed, "keeping working directory, remove it manually");
+            } else {
+                cloned
+                    .close()
+                    .await
+                    .unwrap_or_else(|e| warn!(error = ?e, "failed to clean up working directory"));
+            }
+        }
+        results.into_iter().try_for_each(|r| r)?;
+        Ok(!failed)
+    }
 
-            let cmd = self.build_command(&cloned.path, &req, &token)?;
-            let span =
-                info_span!("run command", command = fmt_cmd(&cmd), path = %cloned.path.display());
-            self.run_command(cmd, update_input).instrument(span).await
-        })
-        .await
+    // Create a deployment of the commit as pending if `--deployment-mode` is enabled. None if disabled or failed.
+    async fn start_deployment(&self, req: &CheckRequest) -> Option<Deployment> {
+        if !self.config.deployment_mode {
+            return None;
+        }
+        let (owner, repo) = (&req.repository.owner.login, &req.repository.name);
+        let input = DeploymentInput {
+            git_ref: req.head_sha.clone(),
+            environment: self.config.deployment_environment.clone(),
+            description: format!("Deployed by orgu job {}", self.config.job_name),
+            auto_merge: false,
+            required_contexts: Vec::new(),
+        };
+        let res = async {
+            let token = self.token_fetcher.fetch_token().await?;
+            let id = self
+                .client
+                .create_deployment(&token, owner, repo, &input)
+                .await?;
+            anyhow::Ok(Deployment { id, token })
+        }
+        .await;
+        match res {
+            Ok(d) => {
+                info!(deployment_id = d.id, "created deployment");
+                self.update_deployment(req, &d, DeploymentState::Pending)
+                    .await;
+                Some(d)
+            }
+            Err(e) => {
+                warn!(error = ?e, "failed to create deployment");
+                None
+            }
+        }
+    }
+
+    async fn update_deployment(&self, req: &CheckRequest, d: &Deployment, state: DeploymentState) {
+        let input = DeploymentStatusInput {
+            state,
+            description: format!("orgu job {}", self.config.job_name),
+        };
+        self.client
+            .create_deployment_status(
+                &d.token,
+                &req.repository.owner.login,
+                &req.repository.name,
+                d.id,
+                &input,
+            )
+            .await
+            .unwrap_or_else(|e| warn!(error = ?e, ?state, "failed to update deployment status"));
+    }
+
+    // Record the delivery and return the number of redeliveries and the limit if it exceeds the limit.
+    fn exceeded_redeliveries(&self, req: &CheckRequest) -> Option<(u32, u32)> {
+        let max = self.config.max_redeliveries?;
+        if req.delivery_id.is_empty() {
+            return None;
+        }
+        let redeliveries = self.deliveries.record(&req.delivery_id).saturating_sub(1);
+        (redeliveries > max).then_some((redeliveries, max))
+    }
+
+    // Base branch of the event if it doesn't match any of the filters.
+    fn skipped_base_ref<'req>(&self, req: &'req CheckRequest) -> Option<&'req str> {
+        if self.config.base_ref_filters.is_empty() {
+            return None;
+        }
+        // merge_group events give the full ref.
+        let base_ref = req.base_ref.as_deref()?;
+        let branch = base_ref.strip_prefix("refs/heads/").unwrap_or(base_ref);
+        let matched = self
+            .config
+            .base_ref_filters
+            .iter()
+            .any(|p| glob_match(p, branch));
+        (!matched).then_some(branch)
+    }
+
+    fn variants(&self) -> Vec<Option<Variant>> {
+        self.config.matrix.as_ref().map_or_else(
+            || vec![None],
+            |m| {
+                m.values
+                    .iter()
+                    .map(|v| {
+                        Some(Variant {
+                            key: m.key.clone(),
+                            value: v.clone(),
+                        })
+                    })
+                    .collect()
+            },
+        )
+    }
+
+    fn check_run_name(&self, variant: Option<&Variant>) -> String {
+        let name = variant.map_or_else(
+            || self.runner_job_name.clone(),
+            |v| format!("{}-{}", self.runner_job_name, v.value),
+        );
+        name_with_environment(&name, &self.config.view.environment)
+    }
+
+    // On redelivery, mark the existing check run as in progress again instead of creating a duplicate.
+    async fn create_or_reuse_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        input: ChecksCreateRequest,
+    ) -> Result<CheckRun> {
+        if self.config.reuse_check_runs {
+            let existing = self
+                .client
+                .find_check_run(
+                    owner,
+                    repo,
+                    &input.head_sha,
+                    &input.name,
+                    &input.external_id,
+                )
+                .await?;
+            if let Some(r) = existing {
+                info!(check_run_id = r.id, "reusing existing check run");
+                return self
+                    .client
+                    .update_check_run(owner, repo, r.id, &into_update_request(input))
+                    .await;
+            }
+        }
+        self.client.create_check_run(owner, repo, &input).await
     }
 
-    // Execute the command and update the check-run status.
+    // Execute the command and build the check-run conclusion, to be reported by the caller.
     // If the command fails to execute, it's likely due to a misconfiguration, and thus, an error is returned.
     // If the command executes but fails with an exit status, it's considered a domain failure, and thus, it's handled
     // as a normal outcome.
-    async fn run_command(&self, mut cmd: Command, update_input: UpdateInputBase) -> Result<()> {
+    async fn run_command(
+        &self,
+        mut cmd: Command,
+        update_input: &UpdateInputBase,
+    ) -> Result<(CommandOutcome, ChecksUpdateRequest)> {
         info!("running command with timeout: {}", self.config.job_timeout);
+        let started_at = Utc::now();
         let start = Instant::now();
-        // Without strong guarantee of killing the child process.
-        // https://docs.rs/tokio/latest/tokio/process/struct.Command.html#method.kill_on_drop
-        cmd.kill_on_drop(true);
-
-        let out = match timeout(self.config.job_timeout.into(), cmd.output()).await {
-            Ok(res) => res.with_context(|| format!("failed to run command: {}", fmt_cmd(&cmd)))?,
-            Err(_) => {
-                info!(elapsed = ?start.elapsed(), timeout_config = %self.config.job_timeout, "command timed out");
-                self.client
-                    .update_check_run(
-                        update_input.owner(),
-                        update_input.repo(),
-                        update_input.check_run_id,
-                        &update_input
-                            .clone()
-                            .into_command_timed_out(self.config.job_timeout, cmd),
-                    )
-                    .await?;
+        let res = self
+            .executor
+            .run(&mut cmd, self.config.job_timeout.into())
+            .await?;
+        let out = match res {
+            ExecResult::Completed(out) => self.config.job_env.mask(out),
+            ExecResult::TimedOut => {
+                let elapsed = start.elapsed();
+                info!(?elapsed, timeout_config = %self.config.job_timeout, "command timed out");
+                let input = with_timing(
+                    update_input
+                        .clone()
+                        .into_command_timed_out(self.config.job_timeout, cmd),
+                    started_at,
+                    elapsed,
+                );
+                let outcome = CommandOutcome {
+                    succeeded: false,
+                    elapsed,
+                };
                 // Timeout of command execution is not orgu failure, so early return an Ok.
-                return Ok(());
+                return Ok((outcome, input));
             }
         };
 
-        if out.status.success() {
-            info!(elapsed = ?start.elapsed(), "command succeeded");
-        } else {
+        let annotations = self.config.annotations.parse(&out.stdout);
+        let failing_level = self.config.annotations.failing_level(&annotations);
+        if !out.status.success() {
             info!(status = out.status.to_string(), elapsed = ?start.elapsed(), "command failed");
+        } else if let Some(level) = failing_level {
+            info!(%level, elapsed = ?start.elapsed(), "command succeeded but reported failing annotations");
+        } else {
+            info!(elapsed = ?start.elapsed(), "command succeeded");
+        };
+
+        let elapsed = start.elapsed();
+        let succeeded = out.status.success() && failing_level.is_none();
+        let input = match failing_level {
+            _ if !out.status.success() => {
+                update_input.clone().into_command_failed(cmd, &out, elapsed)
+            }
+            Some(level) => update_input
+                .clone()
+                .into_annotations_failed(cmd, &out, elapsed, level),
+            None => update_input
+                .clone()
+                .into_command_succeeded(cmd, &out, elapsed),
         };
+        let input = with_annotations(with_timing(input, started_at, elapsed), annotations.items);
+        Ok((CommandOutcome { succeeded, elapsed }, input))
+    }
 
-        let input = if out.status.success() {
-            update_input.clone().into_command_succeeded(cmd, &out)
+    // Not to hang again on a slow Checks API, reporting a timed out command is bounded by `--report-timeout`.
+    async fn report_command_result(
+        &self,
+        update_input: &UpdateInputBase,
+        input: &ChecksUpdateRequest,
+    ) -> Result<()> {
+        if input.conclusion == Some(ChecksCreateRequestConclusion::TimedOut) {
+            self.with_report_timeout(self.report_result(update_input, input))
+                .await
         } else {
-            update_input.clone().into_command_failed(cmd, &out)
+            self.report_result(update_input, input).await
+        }
+    }
+
+    // Conclude the check run, then mirror the result to the pull request comment if enabled.
+    async fn report_result(
+        &self,
+        update_input: &UpdateInputBase,
+        input: &ChecksUpdateRequest,
+    ) -> Result<()> {
+        let (owner, repo) = (update_input.owner(), update_input.repo());
+        let check_run = self
+            .client
+            .update_check_run(owner, repo, update_input.check_run_id, input)
+            .await?;
+        let Some(number) = update_input.req.pull_request_number else {
+            return Ok(());
         };
-        // Failure of given command is not orgu failure, so just report the failure and return Ok.
+        if !self.config.pr_comment {
+            return Ok(());
+        }
+        let body = update_input.to_pr_comment(input, &check_run);
         self.client
-            .update_check_run(
-                update_input.owner(),
-                update_input.repo(),
-                update_input.check_run_id,
-                &input,
-            )
-            .await?;
+            .upsert_issue_comment(owner, repo, number, &update_input.comment_marker(), &body)
+            .await
+            .unwrap_or_else(|e| warn!(error = ?e, "failed to post pull request comment"));
         Ok(())
     }
 
-    fn build_command(&self, work_dir: &Path, req: &CheckRequest, token: &str) -> Result<Command> {
+    fn build_command(
+        &self,
+        work_dir: &WorkDir,
+        req: &CheckRequest,
+        token: &str,
+        variant: Option<&Variant>,
+    ) -> Result<Command> {
         let (program, args) = self
             .config
-            .command
+            .command_for(&req.repository)
             .split_first()
             .with_context(|| "empty COMMAND arg given. See --help.")?;
+        if !self.config.allowed_programs.is_empty()
+            && !self.config.allowed_programs.contains(program)
+        {
+            bail!("program is not allowed to run: {program}. See --allowed-programs.");
+        }
         let mut c = Command::new(program);
         // Default to pipe stdin etc. Not to be piped, use `wait_with_output` instead of `output`.
         // https://docs.rs/tokio/latest/tokio/process/struct.Command.html#method.output
         //
         // Add reviewdog env vars: https://github.com/reviewdog/reviewdog?tab=readme-ov-file#jenkins-with-github-pull-request-builder-plugin
-        c.args(args)
-            .current_dir(work_dir)
-            .env_clear()
-            .env("GITHUB_TOKEN", token)
+        c.args(args).current_dir(&work_dir.path).env_clear();
+        if let Ok(v) = env::var("PATH") {
+            c.env("PATH", v);
+        }
+        // Set first so that env vars set by orgu below take precedence.
+        c.envs(
+            self.config
+                .job_env
+                .static_envs
+                .iter()
+                .map(|e| (&e.key, &e.value)),
+        );
+        if let Some(v) = variant {
+            c.env(&v.key, &v.value);
+        }
+        c.env("GITHUB_TOKEN", token)
             // Reviewdog env vars.
             .env("REVIEWDOG_GITHUB_API_TOKEN", token)
             .env("REVIEWDOG_SKIP_DOGHOUSE", "true")
@@ -206,6 +1107,7 @@ impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
             )
             // Other useful env vars.
             .env("CI_DELIVERY_ID", req.delivery_id.clone())
+            .env("CI_SENDER", req.sender.login.clone())
             .env("CI_REQUEST_ID", req.request_id.clone())
             .env("CI_EVENT_NAME", req.event_name.clone())
             .env("CI_EVENT_ACTION", req.action.clone())
@@ -214,8 +1116,18 @@ impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
             .env("CI_BASE_REF", req.base_ref.clone().unwrap_or_default())
             .env("CI_BEFORE", req.before.clone().unwrap_or_default())
             .env("CI_AFTER", req.after.clone().unwrap_or_default());
-        if let Ok(v) = env::var("PATH") {
-            c.env("PATH", v);
+        // The checkout is a detached HEAD at the commit with `origin` remote without credentials, so jobs can run git
+        // in it. Stop git from finding repositories above the checkout and from prompting for credentials.
+        c.env("CI_CHECKOUT_DIR", &work_dir.path)
+            .env("GIT_TERMINAL_PROMPT", "0");
+        if let Some(parent) = work_dir.path.parent() {
+            c.env("GIT_CEILING_DIRECTORIES", parent);
+        }
+        // Multi-line messages are passed as is, jobs should quote the env var.
+        if let Some(commit) = &work_dir.commit {
+            c.env("CI_COMMIT_MESSAGE", &commit.message)
+                .env("CI_COMMIT_AUTHOR", &commit.author)
+                .env("CI_COMMIT_TIMESTAMP", &commit.timestamp);
         }
         add_custom_props(&mut c, &req.repository.custom_properties);
 
@@ -223,28 +1135,48 @@ impl<CL: GithubClient, CH: Checkout, F: TokenFetcher> Handler<CL, CH, F> {
     }
 
     // We already created GitHub check_run, so in case of error, we should mark the check_run as completed with failure.
-    async fn ensure_updating_check_run(
+    async fn ensure_updating_check_runs<T>(
         &self,
-        input: UpdateInputBase,
-        f: impl Future<Output = Result<()>>,
-    ) -> Result<()> {
+        inputs: &[UpdateInputBase],
+        f: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
         match f.await {
-            Ok(_) => Ok(()),
+            Ok(v) => Ok(v),
             Err(e) => {
                 info!(original = ?e, "updating check run as failure due to error");
-                self.client
-                    .update_check_run(
-                        input.owner(),
-                        input.repo(),
-                        input.check_run_id,
-                        &input.clone().into_event_handle_failed(&e),
-                    )
+                self.conclude_all(inputs, |i| i.into_event_handle_failed(&e))
                     .await?;
                 // After successfully updating the check run, return the original error.
                 Err(e)
             }
         }
     }
+
+    // Conclude all the check runs of the job with the same conclusion, e.g. on skips and failures before running.
+    async fn conclude_all(
+        &self,
+        inputs: &[UpdateInputBase],
+        into: impl Fn(UpdateInputBase) -> ChecksUpdateRequest,
+    ) -> Result<()> {
+        for input in inputs {
+            self.with_report_timeout(self.client.update_check_run(
+                input.owner(),
+                input.repo(),
+                input.check_run_id,
+                &into(input.clone()),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn with_report_timeout<T>(&self, f: impl Future<Output = Result<T>>) -> Result<T> {
+        let d = self.config.report_timeout;
+        timeout(d.into(), f).await.unwrap_or_else(|_| {
+            error!(timeout_config = %d, "reporting to check run timed out, the check run may be left in progress");
+            Err(anyhow!("reporting to check run timed out ({d})"))
+        })
+    }
 }
 
 // Log errors in the Handler layer to make easier to develop error reporting in local environment.
@@ -264,11 +1196,62 @@ async fn with_event_logging(req: CheckRequest, f: impl Future<Output = Result<()
 
 // Job can refer custom properties as env vars with `CUSTOM_PROP_` prefix with upcased key.
 // e.g. `CUSTOM_PROP_TEAM=t-ferris`.
-fn add_custom_props(c: &mut Command, custom_props: &HashMap<String, String>) {
-    for (k, v) in custom_props {
-        let upcased = k.to_uppercase();
-        c.env(format!("CUSTOM_PROP_{upcased}"), v);
+// Match with `*` wildcards only, which is enough for branch names.
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return s.is_empty();
+    };
+    let Some(mut rest) = s.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    // No wildcard in the pattern.
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        let Some(i) = rest.find(part) else {
+            return false;
+        };
+        rest = rest.get(i.saturating_add(part.len())..).unwrap_or_default();
+    }
+    rest.ends_with(last)
+}
+
+fn add_custom_props(c: &mut Command, custom_props: &HashMap<String, CustomPropValue>) {
+    c.envs(custom_prop_envs(custom_props));
+}
+
+// Non-alphanumeric chars in keys are replaced with underscores to be valid env var names, e.g. `team-name` becomes
+// `CUSTOM_PROP_TEAM_NAME`. On collision, the first key in sorted order wins. List values are joined with comma.
+fn custom_prop_envs(custom_props: &HashMap<String, CustomPropValue>) -> BTreeMap<String, String> {
+    let mut sorted = custom_props.iter().collect::<Vec<_>>();
+    sorted.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    let mut envs = BTreeMap::new();
+    for (k, v) in sorted {
+        let normalized = k
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let name = format!("CUSTOM_PROP_{normalized}");
+        if envs.contains_key(&name) {
+            warn!(
+                key = k,
+                env = name,
+                "custom property key collides after normalization, ignored"
+            );
+            continue;
+        }
+        envs.insert(name, v.to_string());
     }
+    envs
 }
 
 #[cfg(test)]
@@ -282,8 +1265,32 @@ mod test {
             Self {
                 job_name: Default::default(),
                 command: Default::default(),
+                command_map: Default::default(),
+                command_by_property: Default::default(),
+                property_command_map: Default::default(),
                 wrap_stdout: Default::default(),
                 job_timeout: Duration::from_secs(10 * 60).into(),
+                allowed_programs: Default::default(),
+                reuse_check_runs: Default::default(),
+                view: Default::default(),
+                annotations: Default::default(),
+                request_timeout: Default::default(),
+                skip_senders: Default::default(),
+                base_ref_filters: Default::default(),
+                matrix: Default::default(),
+                keep_checkout: Default::default(),
+                pr_comment: Default::default(),
+                comment_reaction: Default::default(),
+                deployment_mode: Default::default(),
+                deployment_environment: Default::default(),
+                job_env: Default::default(),
+                report_timeout: Duration::from_secs(30).into(),
+                max_redeliveries: Default::default(),
+                preflight_permissions: Default::default(),
+                job_retries: Default::default(),
+                job_retry_interval: Duration::ZERO.into(),
+                reject_unknown_schema_version: Default::default(),
+                audit_log: Default::default(),
             }
         }
     }
@@ -291,17 +1298,25 @@ mod test {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::create_dir_all, time::Duration};
+    use std::{
+        fs::{create_dir_all, read_to_string, remove_dir_all},
+        future::pending,
+        time::Duration,
+    };
 
     use mockall::Sequence;
-    use octorust::types::{ChecksCreateRequestConclusion, ChecksUpdateRequest};
+    use octorust::types::{ChecksCreateRequestConclusion, ChecksUpdateRequest, JobStatus};
     use pretty_assertions::assert_eq;
 
     use crate::{
-        checkout::{MockCheckout, WorkDir},
-        events::{GithubRepository, User},
+        checkout::{CommitInfo, MockCheckout},
+        events::{RerequestedCheckRun, User},
         github_client::{empty_checkrun, MockGithubClient},
         github_token::MockTokenFetcher,
+        runner::{
+            annotations::{AnnotationFormat, Level},
+            executor::{LocalExecutor, MockCommandExecutor},
+        },
     };
 
     use super::*;
@@ -336,7 +1351,15 @@ mod tests {
         let path = dir.path().join("test_repo");
         // Blocking create_dir_all is ok in test.
         create_dir_all(&path).unwrap();
-        WorkDir { path, _parent: dir }
+        WorkDir {
+            path,
+            commit: Some(CommitInfo {
+                message: "fix: typo\n\nlonger body".to_owned(),
+                author: "ferris <ferris@example.com>".to_owned(),
+                timestamp: "2024-01-01T09:00:00+09:00".to_owned(),
+            }),
+            _parent: dir,
+        }
     }
 
     #[tokio::test]
@@ -379,11 +1402,21 @@ mod tests {
             assert!(text.contains("CI_REPO_OWNER=owner"));
             assert!(text.contains("CI_REPO_NAME=repo"));
             assert!(text.contains("CI_PULL_REQUEST=55"));
+            assert!(text.contains("CI_SENDER=octocat"));
+            assert!(text.contains("CI_CHECKOUT_DIR=/"));
+            assert!(text.contains("GIT_TERMINAL_PROMPT=0"));
+            assert!(text.contains("CI_COMMIT_MESSAGE=fix: typo\n\nlonger body"));
+            assert!(text.contains("CI_COMMIT_AUTHOR=ferris <ferris@example.com>"));
+            assert!(text.contains("CI_COMMIT_TIMESTAMP=2024-01-01T09:00:00+09:00"));
 
             assert!(text.contains("PATH="));
 
             assert!(text.contains("CUSTOM_PROP_TEAM=t-platform"));
             assert!(text.contains("CUSTOM_PROP_DOMAIN=d-platform"));
+            assert!(text.contains("CUSTOM_PROP_TEAMS=a,b"));
+
+            assert!(text.contains("REGISTRY_MIRROR=https://mirror.example.com"));
+            assert!(!text.contains("CI_COMMIT=overridden"));
         }
 
         client
@@ -393,20 +1426,34 @@ mod tests {
             .withf(|_, _, _, input| {
                 check_env(input);
                 input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+                    && input.started_at.is_some()
+                    && input.completed_at >= input.started_at
             })
             .returning(|_, _, _, _| Ok(empty_checkrun()));
 
         let config = Config {
             job_name: "test_job".to_owned(),
             command: vec!["env".to_owned()],
+            job_env: JobEnvConfig {
+                static_envs: vec![
+                    parse_static_env("REGISTRY_MIRROR=https://mirror.example.com").unwrap(),
+                    parse_static_env("CI_COMMIT=overridden").unwrap(),
+                ],
+                ..Default::default()
+            },
             ..Default::default()
         };
-        let handler = Handler::new(config, client, checkout, fetcher);
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
 
         let mut req = build_checkrequest();
+        req.sender.login = "octocat".to_owned();
         let props = &mut req.repository.custom_properties;
-        props.insert("team".to_owned(), "t-platform".to_owned());
-        props.insert("domain".to_owned(), "d-platform".to_owned());
+        props.insert("team".to_owned(), "t-platform".to_owned().into());
+        props.insert("domain".to_owned(), "d-platform".to_owned().into());
+        props.insert(
+            "teams".to_owned(),
+            CustomPropValue::Multiple(vec!["a".to_owned(), "b".to_owned()]),
+        );
         let res = handler.handle_event(req).await;
         res.unwrap();
     }
@@ -443,14 +1490,14 @@ mod tests {
             command: vec!["false".to_owned()],
             ..Default::default()
         };
-        let handler = Handler::new(config, client, checkout, fetcher);
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
 
         let res = handler.handle_event(Default::default()).await;
         res.unwrap();
     }
 
     #[tokio::test]
-    async fn empty_command() {
+    async fn program_not_found() {
         let mut fetcher = MockTokenFetcher::new();
         fetcher
             .expect_fetch_token()
@@ -461,6 +1508,13 @@ mod tests {
             .returning(|_, _, _| Ok(empty_checkrun()));
         client
             .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+                    && input.output.as_ref().unwrap().text.contains(
+                        "program `orgu-nonexistent-program` not found on PATH; check your --command and the runner image",
+                    )
+            })
             .returning(|_, _, _, _| Ok(empty_checkrun()));
         let mut checkout = MockCheckout::new();
         checkout
@@ -468,19 +1522,695 @@ mod tests {
             .returning(|_| Ok(work_dir()));
 
         let config = Config {
-            command: Vec::new(),
+            command: vec!["orgu-nonexistent-program".to_owned()],
             ..Default::default()
         };
-        let handler = Handler::new(config, client, checkout, fetcher);
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
 
         let res = handler.handle_event(Default::default()).await;
-        assert!(res.is_err());
         assert_eq!(
             res.unwrap_err().to_string(),
-            "empty COMMAND arg given. See --help."
+            "program `orgu-nonexistent-program` not found on PATH; check your --command and the runner image"
         );
     }
 
+    #[tokio::test]
+    async fn skip_sender() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher.expect_fetch_token().never();
+        let mut checkout = MockCheckout::new();
+        checkout.expect_create_dir_and_checkout().never();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Neutral)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            skip_senders: vec!["dependabot[bot]".to_owned()],
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let mut req = build_checkrequest();
+        req.sender.login = "dependabot[bot]".to_owned();
+        let res = handler.handle_event(req).await;
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn skip_archived_repository() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher.expect_fetch_token().never();
+        let mut checkout = MockCheckout::new();
+        checkout.expect_create_dir_and_checkout().never();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Neutral)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let handler = Handler::new(config(), client, checkout, fetcher, LocalExecutor);
+
+        let mut req = build_checkrequest();
+        req.repository.archived = true;
+        let res = handler.handle_event(req).await;
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_schema_version() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher.expect_fetch_token().never();
+        let mut checkout = MockCheckout::new();
+        checkout.expect_create_dir_and_checkout().never();
+        let mut client = MockGithubClient::new();
+        client.expect_create_check_run().never();
+
+        let config = Config {
+            reject_unknown_schema_version: true,
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let mut req = build_checkrequest();
+        req.schema_version = SCHEMA_VERSION + 1;
+        let res = handler.handle_event(req).await;
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .starts_with("unknown schema version of the request"));
+    }
+
+    #[tokio::test]
+    async fn audit_log() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .once()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .once()
+            .returning(|_| Ok(work_dir()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = Config {
+            audit_log: Some(path.clone()),
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+        handler.handle_event(build_checkrequest()).await.unwrap();
+
+        let log = read_to_string(&path).unwrap();
+        let records = log
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 1);
+        let record = records.first().unwrap();
+        let field = |k: &str| record.get(k).cloned().unwrap_or_default();
+        assert_eq!(field("outcome"), "succeeded");
+        assert_eq!(field("repository"), "owner/repo");
+        assert_eq!(field("head_sha"), "testsha");
+        assert_eq!(field("command"), serde_json::json!(["echo", "hello"]));
+    }
+
+    #[tokio::test]
+    async fn preflight() {
+        for (checks, ok) in [(Some("write"), true), (Some("read"), false), (None, false)] {
+            let mut fetcher = MockTokenFetcher::new();
+            fetcher
+                .expect_fetch_permissions()
+                .once()
+                .returning(move || {
+                    Ok(checks
+                        .map(|v| HashMap::from([("checks".to_owned(), v.to_owned())]))
+                        .unwrap_or_default())
+                });
+            let config = Config {
+                preflight_permissions: true,
+                ..config()
+            };
+            let handler = Handler::new(
+                config,
+                MockGithubClient::new(),
+                MockCheckout::new(),
+                fetcher,
+                LocalExecutor,
+            );
+            assert_eq!(handler.preflight().await.is_ok(), ok, "checks: {checks:?}");
+        }
+
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher.expect_fetch_permissions().never();
+        let handler = Handler::new(
+            config(),
+            MockGithubClient::new(),
+            MockCheckout::new(),
+            fetcher,
+            LocalExecutor,
+        );
+        handler.preflight().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_redeliveries() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .times(2)
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .times(2)
+            .returning(|_| Ok(work_dir()));
+        let mut seq = Sequence::new();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .times(3)
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .times(2)
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+                    && input
+                        .output
+                        .as_ref()
+                        .unwrap()
+                        .summary
+                        .starts_with("Event was redelivered 2 times")
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            max_redeliveries: Some(1),
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let mut req = build_checkrequest();
+        req.delivery_id = "delivery".to_owned();
+        for _ in 0..3 {
+            handler.handle_event(req.clone()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn job_retries() {
+        let mut seq = Sequence::new();
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| Err(anyhow!("connection reset")));
+        fetcher
+            .expect_fetch_token()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .once()
+            .returning(|_| Ok(work_dir()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .times(2)
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        let mut seq = Sequence::new();
+        client
+            .expect_update_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            job_retries: 2,
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        handler.handle_event(build_checkrequest()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn job_retries_not_on_command_error() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .once()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .once()
+            .returning(|_| Ok(work_dir()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            command: vec!["orgu-nonexistent-program".to_owned()],
+            job_retries: 2,
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        handler
+            .handle_event(build_checkrequest())
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn deployment_mode() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .times(2)
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .once()
+            .returning(|_| Ok(work_dir()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_create_deployment()
+            .once()
+            .withf(|token, owner, repo, input| {
+                token == "test_token"
+                    && owner == "owner"
+                    && repo == "repo"
+                    && input.git_ref == "testsha"
+                    && input.environment == "staging"
+                    && input.required_contexts.is_empty()
+            })
+            .returning(|_, _, _, _| Ok(42));
+        let mut seq = Sequence::new();
+        for state in [
+            DeploymentState::Pending,
+            DeploymentState::InProgress,
+            DeploymentState::Success,
+        ] {
+            client
+                .expect_create_deployment_status()
+                .once()
+                .in_sequence(&mut seq)
+                .withf(move |_, _, _, id, input| *id == 42 && input.state == state)
+                .returning(|_, _, _, _, _| Ok(()));
+        }
+
+        let config = Config {
+            deployment_mode: true,
+            deployment_environment: "staging".to_owned(),
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        handler.handle_event(build_checkrequest()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn request_timedout() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::TimedOut)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        let config = Config {
+            command: vec!["sleep".to_owned(), "10".to_owned()],
+            request_timeout: Some(Duration::from_millis(100).into()),
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        // Request timeout is considered as success with reporting via Checks API.
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancel_in_progress() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .times(2)
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Cancelled)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        // Only the older commit hangs.
+        let config = Config {
+            command: vec![
+                "sh".to_owned(),
+                "-c".to_owned(),
+                r#"test "$CI_COMMIT" = newer || sleep 10"#.to_owned(),
+            ],
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor)
+            .with_cancel_in_progress();
+        let older = CheckRequest {
+            head_sha: "older".to_owned(),
+            ..build_checkrequest()
+        };
+        let newer = CheckRequest {
+            head_sha: "newer".to_owned(),
+            ..build_checkrequest()
+        };
+
+        let (older, newer) = tokio::join!(handler.handle_event(older), async {
+            sleep(Duration::from_millis(100)).await;
+            handler.handle_event(newer).await
+        });
+        older.unwrap();
+        newer.unwrap();
+    }
+
+    #[tokio::test]
+    async fn command_timedout() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::TimedOut)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+        let mut executor = MockCommandExecutor::new();
+        executor
+            .expect_run()
+            .once()
+            .withf(|cmd, timeout| {
+                cmd.as_std().get_program() == "echo" && *timeout == Duration::from_secs(30)
+            })
+            .returning(|_, _| Ok(ExecResult::TimedOut));
+
+        let config = Config {
+            job_timeout: Duration::from_secs(30).into(),
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, executor);
+
+        let res = handler.handle_event(Default::default()).await;
+        // Command timeout is considered as success with reporting failure via Checks API.
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkout_unauthorized() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::ActionRequired)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout.expect_create_dir_and_checkout().returning(|_| {
+            Err(CheckoutError::Authentication(git2::Error::from_str("auth")).into())
+        });
+
+        let handler = Handler::new(config(), client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkout_too_large() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Cancelled)
+                    && input
+                        .output
+                        .as_ref()
+                        .unwrap()
+                        .summary
+                        .contains("1024 bytes")
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Err(CheckoutError::TooLarge(1024).into()));
+
+        let handler = Handler::new(config(), client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn empty_command() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        let config = Config {
+            command: Vec::new(),
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        assert!(res.is_err());
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "empty COMMAND arg given. See --help."
+        );
+    }
+
+    #[tokio::test]
+    async fn program_not_allowed() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        let config = Config {
+            command: vec!["false".to_owned()],
+            allowed_programs: vec!["echo".to_owned()],
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "program is not allowed to run: false. See --allowed-programs."
+        );
+    }
+
+    #[tokio::test]
+    async fn reuse_check_run() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        let mut seq = Sequence::new();
+        let mut client = MockGithubClient::new();
+        client
+            .expect_find_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, name, external_id| name == "run-" && external_id == "delivery")
+            .returning(|_, _, _, _, _| {
+                let mut r = empty_checkrun();
+                r.id = 42;
+                Ok(Some(r))
+            });
+        client.expect_create_check_run().never();
+        client
+            .expect_update_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, id, input| *id == 42 && input.status == Some(JobStatus::InProgress))
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, input| {
+                input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            reuse_check_runs: true,
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let req = CheckRequest {
+            delivery_id: "delivery".to_owned(),
+            ..build_checkrequest()
+        };
+        handler.handle_event(req).await.unwrap();
+    }
+
     #[tokio::test]
     async fn checkout_timedout() {
         let mut fetcher = MockTokenFetcher::new();
@@ -510,10 +2240,530 @@ mod tests {
             })
             .returning(|_, _, _, _| Ok(empty_checkrun()));
 
-        let handler = Handler::new(config(), client, checkout, fetcher);
+        let handler = Handler::new(config(), client, checkout, fetcher, LocalExecutor);
 
         let res = handler.handle_event(Default::default()).await;
         // Checkout timeout is considered as success with reporting failure via Checks API.
         res.unwrap();
     }
+
+    #[tokio::test]
+    async fn matrix() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .once()
+            .returning(|| Ok("test_token".to_owned()));
+        let dir = work_dir();
+        let parent = dir._parent.path().to_path_buf();
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .once()
+            .return_once(|_| Ok(dir));
+
+        let mut client = MockGithubClient::new();
+        for (id, name) in [(1, "run-test_job-a"), (2, "run-test_job-b")] {
+            client
+                .expect_create_check_run()
+                .once()
+                .withf(move |_, _, input| input.name == name)
+                .returning(move |_, _, _| {
+                    let mut r = empty_checkrun();
+                    r.id = id;
+                    Ok(r)
+                });
+        }
+        for (id, env) in [(1, "V=a"), (2, "V=b")] {
+            client
+                .expect_update_check_run()
+                .once()
+                .withf(move |_, _, check_run_id, input| {
+                    *check_run_id == id
+                        && input.conclusion == Some(ChecksCreateRequestConclusion::Success)
+                        && input.output.as_ref().unwrap().text.contains(env)
+                        && input
+                            .output
+                            .as_ref()
+                            .unwrap()
+                            .summary
+                            .starts_with("Matrix succeeded: 2 passed, 0 failed")
+                })
+                .returning(|_, _, _, _| Ok(empty_checkrun()));
+        }
+
+        let config = Config {
+            job_name: "test_job".to_owned(),
+            command: vec!["env".to_owned()],
+            matrix: Some(parse_matrix("V=a,b").unwrap()),
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(build_checkrequest()).await;
+        res.unwrap();
+        // The shared working directory is removed after all variants completed.
+        assert!(!parent.exists());
+    }
+
+    #[tokio::test]
+    async fn matrix_partially_created() {
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .once()
+            .withf(|_, _, input| input.name == "run-test_job-a")
+            .returning(|_, _, _| {
+                let mut r = empty_checkrun();
+                r.id = 1;
+                Ok(r)
+            });
+        client
+            .expect_create_check_run()
+            .once()
+            .withf(|_, _, input| input.name == "run-test_job-b")
+            .returning(|_, _, _| Err(anyhow!("rate limited")));
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, check_run_id, input| {
+                *check_run_id == 1
+                    && input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            job_name: "test_job".to_owned(),
+            matrix: Some(parse_matrix("V=a,b").unwrap()),
+            ..config()
+        };
+        let handler = Handler::new(
+            config,
+            client,
+            MockCheckout::new(),
+            MockTokenFetcher::new(),
+            LocalExecutor,
+        );
+
+        let res = handler.handle_event(build_checkrequest()).await;
+        res.unwrap_err();
+    }
+
+    #[test]
+    fn parse_matrix_invalid() {
+        parse_matrix("V").unwrap_err();
+        parse_matrix("=a,b").unwrap_err();
+        parse_matrix("V=").unwrap_err();
+        let m = parse_matrix("V=a,,b").unwrap();
+        assert_eq!(m.key, "V");
+        assert_eq!(m.values, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn command_map() {
+        let config = Config {
+            command_map: Some(
+                parse_command_map(r#"{"owner/*":["make","lint"],"owner/infra-*":["tflint"]}"#)
+                    .unwrap(),
+            ),
+            allowed_programs: vec!["echo".to_owned(), "make".to_owned()],
+            ..config()
+        };
+        let repo = |full_name: &str| GithubRepository {
+            full_name: full_name.to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(config.command_for(&repo("owner/infra-aws")), ["tflint"]);
+        assert_eq!(config.command_for(&repo("owner/app")), ["make", "lint"]);
+        assert_eq!(config.command_for(&repo("other/app")), ["echo", "hello"]);
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid command map: program is not allowed to run for `owner/infra-*`: tflint. See --allowed-programs."
+        );
+
+        let config = Config {
+            command_map: Some(parse_command_map(r#"{"owner/*":[]}"#).unwrap()),
+            ..config()
+        };
+        config.validate().unwrap_err();
+        parse_command_map(r#"{"":["make"]}"#).unwrap_err();
+        parse_command_map(r#"["make"]"#).unwrap_err();
+    }
+
+    #[test]
+    fn command_by_property() {
+        let config = Config {
+            command_map: Some(parse_command_map(r#"{"owner/legacy":["make"]}"#).unwrap()),
+            command_by_property: Some("language".to_owned()),
+            property_command_map: Some(
+                parse_command_map(r#"{"rust":["cargo","clippy"],"go":["go","vet"]}"#).unwrap(),
+            ),
+            ..config()
+        };
+        let repo = |full_name: &str, language: Option<CustomPropValue>| {
+            let mut repo = GithubRepository {
+                full_name: full_name.to_owned(),
+                ..Default::default()
+            };
+            if let Some(v) = language {
+                repo.custom_properties.insert("language".to_owned(), v);
+            }
+            repo
+        };
+        let single = |v: &str| Some(CustomPropValue::Single(v.to_owned()));
+        assert_eq!(
+            config.command_for(&repo("owner/app", single("rust"))),
+            ["cargo", "clippy"]
+        );
+        let multiple = CustomPropValue::Multiple(vec!["python".to_owned(), "go".to_owned()]);
+        assert_eq!(
+            config.command_for(&repo("owner/app", Some(multiple))),
+            ["go", "vet"]
+        );
+        assert_eq!(
+            config.command_for(&repo("owner/legacy", single("rust"))),
+            ["make"]
+        );
+        assert_eq!(
+            config.command_for(&repo("owner/app", single("java"))),
+            ["echo", "hello"]
+        );
+        assert_eq!(
+            config.command_for(&repo("owner/app", None)),
+            ["echo", "hello"]
+        );
+        config.validate().unwrap();
+
+        let config = Config {
+            allowed_programs: vec!["echo".to_owned(), "make".to_owned(), "cargo".to_owned()],
+            ..config
+        };
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid property command map: program is not allowed to run for `go`: go. See --allowed-programs."
+        );
+    }
+
+    #[test]
+    fn parse_static_env_invalid() {
+        parse_static_env("KEY").unwrap_err();
+        parse_static_env("=v").unwrap_err();
+        parse_static_env("A KEY=v").unwrap_err();
+        let e = parse_static_env("KEY=a=b").unwrap();
+        assert_eq!((e.key.as_str(), e.value.as_str()), ("KEY", "a=b"));
+    }
+
+    #[test]
+    fn secret_env_keys() {
+        let job_env = JobEnvConfig {
+            static_envs: vec![
+                parse_static_env("API_KEY=s3cr3t").unwrap(),
+                parse_static_env("MIRROR=https://mirror.example.com").unwrap(),
+            ],
+            secret_env_keys: vec!["API_KEY".to_owned()],
+        };
+        assert_eq!(
+            serde_json::to_value(&job_env).unwrap(),
+            serde_json::json!({
+                "static_envs": [
+                    {"key": "API_KEY", "value": "(redacted)"},
+                    {"key": "MIRROR", "value": "https://mirror.example.com"},
+                ],
+                "secret_env_keys": ["API_KEY"],
+            })
+        );
+
+        let out = job_env.mask(Output {
+            status: Default::default(),
+            stdout: b"API_KEY=s3cr3t MIRROR=https://mirror.example.com".to_vec(),
+            stderr: b"auth failed: s3cr3t".to_vec(),
+        });
+        assert_eq!(
+            String::from_utf8(out.stdout).unwrap(),
+            "API_KEY=(redacted) MIRROR=https://mirror.example.com"
+        );
+        assert_eq!(
+            String::from_utf8(out.stderr).unwrap(),
+            "auth failed: (redacted)"
+        );
+    }
+
+    #[tokio::test]
+    async fn report_timeout() {
+        let config = Config {
+            report_timeout: Duration::from_millis(10).into(),
+            ..config()
+        };
+        let handler = Handler::new(
+            config,
+            MockGithubClient::new(),
+            MockCheckout::new(),
+            MockTokenFetcher::new(),
+            LocalExecutor,
+        );
+        handler
+            .with_report_timeout(pending::<Result<()>>())
+            .await
+            .unwrap_err();
+    }
+
+    #[test]
+    fn glob_match_branches() {
+        assert!(glob_match("main", "main"));
+        assert!(!glob_match("main", "main2"));
+        assert!(glob_match("release/*", "release/1.0"));
+        assert!(!glob_match("release/*", "releases/1.0"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("feat/*/fix-*", "feat/a/fix-b"));
+        assert!(!glob_match("a*a", "a"));
+    }
+
+    #[tokio::test]
+    async fn skip_base_ref() {
+        let config = Config {
+            base_ref_filters: vec!["main".to_owned(), "release/*".to_owned()],
+            ..config()
+        };
+        let handler = Handler::new(
+            config,
+            MockGithubClient::new(),
+            MockCheckout::new(),
+            MockTokenFetcher::new(),
+            LocalExecutor,
+        );
+        let req = |base_ref: Option<&str>| CheckRequest {
+            base_ref: base_ref.map(ToOwned::to_owned),
+            ..build_checkrequest()
+        };
+        assert_eq!(handler.skipped_base_ref(&req(Some("main"))), None);
+        assert_eq!(
+            handler.skipped_base_ref(&req(Some("refs/heads/release/1"))),
+            None
+        );
+        assert_eq!(
+            handler.skipped_base_ref(&req(Some("develop"))),
+            Some("develop")
+        );
+        // Events without a base branch, e.g. check_suite, always run.
+        assert_eq!(handler.skipped_base_ref(&req(None)), None);
+    }
+
+    #[test]
+    fn custom_prop_envs_normalized() {
+        let props = HashMap::from([
+            ("team-name".to_owned(), "t-ferris".to_owned().into()),
+            ("service.tier".to_owned(), "1".to_owned().into()),
+            ("domain".to_owned(), "d-platform".to_owned().into()),
+        ]);
+        let actual = custom_prop_envs(&props);
+        assert_eq!(
+            actual,
+            BTreeMap::from([
+                ("CUSTOM_PROP_DOMAIN".to_owned(), "d-platform".to_owned()),
+                ("CUSTOM_PROP_SERVICE_TIER".to_owned(), "1".to_owned()),
+                ("CUSTOM_PROP_TEAM_NAME".to_owned(), "t-ferris".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn custom_prop_envs_collision() {
+        let props = HashMap::from([
+            ("team.name".to_owned(), "dot".to_owned().into()),
+            ("team-name".to_owned(), "hyphen".to_owned().into()),
+        ]);
+        let actual = custom_prop_envs(&props);
+        // `team-name` comes first in sorted order.
+        assert_eq!(
+            actual,
+            BTreeMap::from([("CUSTOM_PROP_TEAM_NAME".to_owned(), "hyphen".to_owned())])
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_checkout_on_failure() {
+        for (command, kept) in [("true", false), ("false", true)] {
+            let mut fetcher = MockTokenFetcher::new();
+            fetcher
+                .expect_fetch_token()
+                .returning(|| Ok("test_token".to_owned()));
+            let mut client = MockGithubClient::new();
+            client
+                .expect_create_check_run()
+                .returning(|_, _, _| Ok(empty_checkrun()));
+            client
+                .expect_update_check_run()
+                .returning(|_, _, _, _| Ok(empty_checkrun()));
+            let dir = work_dir();
+            let path = dir.path.clone();
+            let mut checkout = MockCheckout::new();
+            checkout
+                .expect_create_dir_and_checkout()
+                .return_once(move |_| Ok(dir));
+            let config = Config {
+                command: vec![command.to_owned()],
+                keep_checkout: KeepCheckout::OnFailure,
+                ..Default::default()
+            };
+            let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+            handler.handle_event(build_checkrequest()).await.unwrap();
+            assert_eq!(path.exists(), kept, "command={command}");
+            if kept {
+                remove_dir_all(path.parent().unwrap()).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn skip_other_rerequested_check_run() {
+        let mut client = MockGithubClient::new();
+        client.expect_create_check_run().never();
+        let handler = Handler::new(
+            config(),
+            client,
+            MockCheckout::new(),
+            MockTokenFetcher::new(),
+            LocalExecutor,
+        );
+        let req = CheckRequest {
+            check_run: Some(RerequestedCheckRun {
+                id: 1,
+                name: "run-other".to_owned(),
+            }),
+            ..build_checkrequest()
+        };
+        handler.handle_event(req).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn annotations_fail_on_level() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+
+        client
+            .expect_update_check_run()
+            .once()
+            .withf(|_, _, _, input| {
+                let output = input.output.as_ref().unwrap();
+                input.conclusion == Some(ChecksCreateRequestConclusion::Failure)
+                    && output
+                        .summary
+                        .starts_with("Command reported annotations at or above warning")
+                    && output.annotations.len() == 1
+            })
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+
+        let config = Config {
+            command: vec![
+                "echo".to_owned(),
+                r#"{"message":"unused","location":{"path":"a.go"},"severity":"WARNING"}"#
+                    .to_owned(),
+            ],
+            annotations: AnnotationConfig {
+                annotation_format: AnnotationFormat::Rdjsonl,
+                annotation_level_map: None,
+                annotations_fail_on_level: Some(Level::Warning),
+            },
+            ..Default::default()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+
+        let res = handler.handle_event(Default::default()).await;
+        res.unwrap();
+    }
+
+    #[tokio::test]
+    async fn comment_reaction() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        let mut seq = Sequence::new();
+        client
+            .expect_create_reaction()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, id, content| *id == 7 && *content == ReactionContent::Rocket)
+            .returning(|_, _, _, _, _| Ok(()));
+        // Failing to react doesn't fail the event.
+        client
+            .expect_create_reaction()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|_, _, _, id, content| *id == 7 && *content == ReactionContent::ThumbsDown)
+            .returning(|_, _, _, _, _| bail!("fail"));
+
+        let config = Config {
+            comment_reaction: true,
+            ..config()
+        };
+        let mut handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+        let req = CheckRequest {
+            comment_id: Some(7),
+            ..build_checkrequest()
+        };
+        handler.handle_event(req.clone()).await.unwrap();
+
+        handler.config.command = vec!["false".to_owned()];
+        handler.handle_event(req).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pr_comment() {
+        let mut fetcher = MockTokenFetcher::new();
+        fetcher
+            .expect_fetch_token()
+            .returning(|| Ok("test_token".to_owned()));
+        let mut client = MockGithubClient::new();
+        client
+            .expect_create_check_run()
+            .returning(|_, _, _| Ok(empty_checkrun()));
+        client
+            .expect_update_check_run()
+            .returning(|_, _, _, _| Ok(empty_checkrun()));
+        client
+            .expect_upsert_issue_comment()
+            .once()
+            .withf(|owner, repo, number, marker, body| {
+                owner == "owner"
+                    && repo == "repo"
+                    && *number == 55
+                    && marker == "<!-- orgu: run-test -->"
+                    && body.starts_with("### run-test: Runner executed job successfully")
+            })
+            // Comment failure must not fail the job.
+            .returning(|_, _, _, _, _| Err(anyhow!("forbidden")));
+        let mut checkout = MockCheckout::new();
+        checkout
+            .expect_create_dir_and_checkout()
+            .returning(|_| Ok(work_dir()));
+        let config = Config {
+            job_name: "test".to_owned(),
+            pr_comment: true,
+            ..config()
+        };
+        let handler = Handler::new(config, client, checkout, fetcher, LocalExecutor);
+        handler.handle_event(build_checkrequest()).await.unwrap();
+    }
 }
@@ -1,34 +1,80 @@
-use std::process::Output;
+use std::{process::Output, time::Duration as StdDuration};
 
+use chrono::{DateTime, Utc};
+use clap::{ArgAction, Args};
 use humantime::Duration;
 use octorust::types::{
-    ChecksCreateRequest, ChecksCreateRequestConclusion, ChecksCreateRequestOutput,
-    ChecksUpdateRequest, ChecksUpdateRequestOutput, JobStatus,
+    CheckRun, ChecksCreateRequest, ChecksCreateRequestConclusion, ChecksCreateRequestOutput,
+    ChecksCreateRequestOutputAnnotations, ChecksUpdateRequest, ChecksUpdateRequestOutput,
+    JobStatus,
 };
+use serde::Serialize;
 use tokio::process::Command;
 
-use crate::events::CheckRequest;
+use crate::{events::CheckRequest, runner::annotations::Level};
+
+/// Configuration of how the check run is rendered.
+#[derive(Debug, Clone, Default, Args, Serialize)]
+pub struct ViewConfig {
+    /// Don't show the command in the check run summary while running.
+    #[clap(long, env, default_value = "false")]
+    pub hide_command: bool,
+    /// Title of the check run while running.
+    #[clap(long, env)]
+    pub create_title: Option<String>,
+    /// Summary of the check run while running. Placeholders: `{command}`, `{name}`, `{owner}`, `{repo}`, `{head_sha}`.
+    #[clap(long, env)]
+    pub create_summary_template: Option<String>,
+    /// Don't append delivery ID and request ID to the check run summary. These IDs are still logged.
+    #[clap(long, env, default_value = "false")]
+    pub hide_debug_info: bool,
+    /// Identifier of this runner instance appended to the debug info, e.g. `--runner-id "$HOSTNAME"` or the ECS task
+    /// ID, to find the logs of the runner which handled the job in a fleet. Hidden with `--hide-debug-info`. Not
+    /// shown if none.
+    #[clap(long, env)]
+    pub runner_id: Option<String>,
+    /// Label of this deployment, e.g. `staging`. Suffixed to the check run name and shown in the summary to avoid
+    /// conflicts with other deployments watching the same repository. Not suffixed if empty.
+    #[clap(long, env, default_value = "")]
+    pub environment: String,
+    /// Strip ANSI escape sequences such as colors and control characters from the command output, which GitHub shows
+    /// as garbage.
+    #[clap(long, env, default_value = "true", action = ArgAction::Set)]
+    pub strip_ansi: bool,
+    /// Language hint of the code block wrapping stdout for syntax highlighting, e.g. `console`. Only used with
+    /// `--wrap-stdout`.
+    #[clap(long, env)]
+    pub stdout_code_lang: Option<String>,
+    /// Wrap stdout and stderr sections with collapsible `<details>` blocks.
+    #[clap(long, env, default_value = "false")]
+    pub collapse_output: bool,
+}
 
 #[derive(Debug, Clone)]
 pub struct CreateInput {
     pub req: CheckRequest,
     pub name: String,
     pub command: Vec<String>,
+    pub view: ViewConfig,
 }
 
+const DEFAULT_CREATE_TITLE: &str = "Runner is running job";
+const HIDDEN_COMMAND: &str = "(hidden)";
+
 impl From<CreateInput> for ChecksCreateRequest {
     fn from(v: CreateInput) -> Self {
+        let summary = with_debug_info(v.summary(), &v.req, &v.view);
         Self {
             name: v.name,
             head_sha: v.req.head_sha.clone(),
             status: Some(JobStatus::InProgress),
             conclusion: None,
             output: Some(ChecksCreateRequestOutput {
-                title: "Runner is running job".to_owned(),
-                summary: with_debug_info(
-                    format!("Running command:\n```\n{}\n```", v.command.join(" ")),
-                    &v.req,
-                ),
+                title: v
+                    .view
+                    .create_title
+                    .unwrap_or_else(|| DEFAULT_CREATE_TITLE.to_owned()),
+                summary,
                 text: "".to_owned(),
                 annotations: Vec::new(),
                 images: Vec::new(),
@@ -37,18 +83,39 @@ impl From<CreateInput> for ChecksCreateRequest {
             started_at: None,
             completed_at: None,
             details_url: String::new(),
-            external_id: String::new(),
+            // Delivery ID is the same for re-delivery, so use it to find the existing check run.
+            external_id: v.req.delivery_id,
         }
     }
 }
 
 impl CreateInput {
+    #[allow(clippy::literal_string_with_formatting_args)] // Placeholders of user given template.
+    fn summary(&self) -> String {
+        let command = if self.view.hide_command {
+            HIDDEN_COMMAND.to_owned()
+        } else {
+            self.command.join(" ")
+        };
+        match &self.view.create_summary_template {
+            Some(t) => t
+                .replace("{command}", &command)
+                .replace("{name}", &self.name)
+                .replace("{owner}", &self.req.repository.owner.login)
+                .replace("{repo}", &self.req.repository.name)
+                .replace("{head_sha}", &self.req.head_sha),
+            None if self.view.hide_command => "Running command.".to_owned(),
+            None => format!("Running command:\n```\n{command}\n```"),
+        }
+    }
+
     pub fn into_update_input(self, check_run_id: i64, wrap_stdout: bool) -> UpdateInputBase {
         UpdateInputBase {
             req: self.req,
             name: self.name,
             check_run_id,
             wrap_stdout,
+            view: self.view,
         }
     }
 }
@@ -59,6 +126,7 @@ pub struct UpdateInputBase {
     pub req: CheckRequest,
     pub name: String,
     pub wrap_stdout: bool,
+    pub view: ViewConfig,
 }
 
 impl UpdateInputBase {
@@ -81,7 +149,127 @@ impl UpdateInputBase {
               self.req.repository.name,
               self.req.head_sha,
             );
-            o.summary = with_debug_info(summary, &self.req);
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_checkout_too_large(self, max_fetch_bytes: u64) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Cancelled);
+        input.output = input.output.map(|mut o| {
+            "Checkout repository cancelled due to size".clone_into(&mut o.title);
+            let summary = format!(
+                "Runner cancelled fetching the repository as it received more than {max_fetch_bytes} bytes. Reduce \
+                 the fetch depth or ask the runner owner to raise the limit: owner={}, repo={}, sha={}",
+                self.req.repository.owner.login, self.req.repository.name, self.req.head_sha,
+            );
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_sender_skipped(self) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Neutral);
+        input.output = input.output.map(|mut o| {
+            "Runner skipped job".clone_into(&mut o.title);
+            let summary = format!(
+                "Job is skipped for events sent by `{}`.",
+                self.req.sender.login
+            );
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_repository_inactive(self) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Neutral);
+        input.output = input.output.map(|mut o| {
+            "Runner skipped job".clone_into(&mut o.title);
+            let summary = "Job is skipped for archived or disabled repositories.".to_owned();
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_base_ref_skipped(self, base_ref: &str) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Neutral);
+        input.output = input.output.map(|mut o| {
+            "Runner skipped job".clone_into(&mut o.title);
+            let summary = format!(
+                "Job is skipped for the base branch `{base_ref}`, which doesn't match the configured filters."
+            );
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_redeliveries_exceeded(self, redeliveries: u32, max: u32) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Failure);
+        input.output = input.output.map(|mut o| {
+            "Too many redeliveries, giving up".clone_into(&mut o.title);
+            let summary = format!(
+                "Event was redelivered {redeliveries} times, more than the limit ({max}). Runner gave up handling it \
+                 as it likely fails every time. Re-run the check run to try again."
+            );
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_request_timed_out(self, duration: Duration) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::TimedOut);
+        input.output = input.output.map(|mut o| {
+            "Handling request timed out".clone_into(&mut o.title);
+            let summary = format!(
+                "Runner could not complete handling the request in time ({duration}). Checkout or the job may be slow."
+            );
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_superseded(self) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Cancelled);
+        input.output = input.output.map(|mut o| {
+            "Job cancelled by a newer event".clone_into(&mut o.title);
+            let summary =
+                "Runner cancelled the job as a newer event of the same pull request arrived."
+                    .to_owned();
+            o.summary = with_debug_info(summary, &self.req, &self.view);
+            o
+        });
+        input
+    }
+
+    pub fn into_checkout_unauthorized(self) -> ChecksUpdateRequest {
+        let summary = format!(
+            "Runner could not access the repository, check installation permissions of the GitHub App: owner={}, repo={}",
+            self.req.repository.owner.login, self.req.repository.name,
+        );
+        self.into_action_required("Could not access repository", summary)
+    }
+
+    /// For failures to be fixed by the user, e.g. missing permissions, rather than failures of the job itself.
+    pub fn into_action_required(self, title: &str, summary: String) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::ActionRequired);
+        input.output = input.output.map(|mut o| {
+            title.clone_into(&mut o.title);
+            o.summary = with_debug_info(summary, &self.req, &self.view);
             o
         });
         input
@@ -96,33 +284,82 @@ impl UpdateInputBase {
                 "Job execution has timed out on the runner ({duration}): `{}`",
                 fmt_cmd(&cmd)
             );
-            o.summary = with_debug_info(summary, &self.req);
+            o.summary = with_debug_info(summary, &self.req, &self.view);
             o
         });
         input
     }
 
-    pub fn into_command_succeeded(self, cmd: Command, out: &Output) -> ChecksUpdateRequest {
+    pub fn into_command_succeeded(
+        self,
+        cmd: Command,
+        out: &Output,
+        elapsed: StdDuration,
+    ) -> ChecksUpdateRequest {
         let mut input = default_checks_update_request(&self);
         input.conclusion = Some(ChecksCreateRequestConclusion::Success);
         input.output = input.output.map(|mut o| {
             "Runner executed job successfully".clone_into(&mut o.title);
-            o.summary =
-                with_debug_info(format!("Command succeeded: `{}`", fmt_cmd(&cmd)), &self.req);
+            o.summary = with_debug_info(
+                format!(
+                    "Command succeeded: `{}`\n\n{}",
+                    fmt_cmd(&cmd),
+                    elapsed_line(elapsed)
+                ),
+                &self.req,
+                &self.view,
+            );
             o.text = self.to_text(out);
             o
         });
         input
     }
 
-    pub fn into_command_failed(self, cmd: Command, out: &Output) -> ChecksUpdateRequest {
+    pub fn into_command_failed(
+        self,
+        cmd: Command,
+        out: &Output,
+        elapsed: StdDuration,
+    ) -> ChecksUpdateRequest {
         let mut input = default_checks_update_request(&self);
         input.conclusion = Some(ChecksCreateRequestConclusion::Failure);
         input.output = input.output.map(|mut o| {
             "Runner ran job but it failed".clone_into(&mut o.title);
             o.summary = with_debug_info(
-                format!("Command failed with {}: `{}`", out.status, fmt_cmd(&cmd)),
+                format!(
+                    "Command failed with {}: `{}`\n\n{}",
+                    out.status,
+                    fmt_cmd(&cmd),
+                    elapsed_line(elapsed)
+                ),
+                &self.req,
+                &self.view,
+            );
+            o.text = self.to_text(out);
+            o
+        });
+        input
+    }
+
+    pub fn into_annotations_failed(
+        self,
+        cmd: Command,
+        out: &Output,
+        elapsed: StdDuration,
+        level: Level,
+    ) -> ChecksUpdateRequest {
+        let mut input = default_checks_update_request(&self);
+        input.conclusion = Some(ChecksCreateRequestConclusion::Failure);
+        input.output = input.output.map(|mut o| {
+            "Runner ran job but it reported annotations".clone_into(&mut o.title);
+            o.summary = with_debug_info(
+                format!(
+                    "Command reported annotations at or above {level}: `{}`\n\n{}",
+                    fmt_cmd(&cmd),
+                    elapsed_line(elapsed)
+                ),
                 &self.req,
+                &self.view,
             );
             o.text = self.to_text(out);
             o
@@ -138,6 +375,7 @@ impl UpdateInputBase {
             o.summary = with_debug_info(
                 "Event handling failed, contact operation team.".to_owned(),
                 &self.req,
+                &self.view,
             );
             // Use Debug trait here to include ancestor errors.
             o.text = format!("Error:\n\n```\n{:?}\n```", error);
@@ -146,20 +384,87 @@ impl UpdateInputBase {
         input
     }
 
-    fn to_text(&self, out: &Output) -> String {
-        let stdout = cut_text_length(&out.stdout);
-        let stderr = cut_text_length(&out.stderr);
-        if self.wrap_stdout {
+    /// Hidden marker to find the sticky PR comment of this check run on re-run.
+    pub fn comment_marker(&self) -> String {
+        format!("<!-- orgu: {} -->", self.name)
+    }
+
+    /// Summary of the check run result for the sticky PR comment. Output is left in the check run.
+    pub fn to_pr_comment(&self, input: &ChecksUpdateRequest, check_run: &CheckRun) -> String {
+        let (title, summary) = input
+            .output
+            .as_ref()
+            .map(|o| (o.title.as_str(), o.summary.as_str()))
+            .unwrap_or_default();
+        let link = if check_run.html_url.is_empty() {
+            String::new()
+        } else {
             format!(
-                "## stdout\n```\n{}\n```\n## stderr\n```\n{}\n```",
-                stdout, stderr
+                "\n\nSee [the check run]({}) for details.",
+                check_run.html_url
             )
+        };
+        format!("### {}: {title}\n\n{summary}{link}", self.name)
+    }
+
+    fn to_text(&self, out: &Output) -> String {
+        let stdout_lang = self.view.stdout_code_lang.as_deref().unwrap_or_default();
+        [
+            self.render_section("stdout", stdout_lang, &self.render_output(&out.stdout)),
+            self.render_section("stderr", "", &self.render_output(&out.stderr)),
+        ]
+        .join("\n")
+    }
+
+    fn render_section(&self, name: &str, lang: &str, body: &str) -> String {
+        let body = if self.wrap_stdout {
+            format!("```{lang}\n{body}\n```")
         } else {
-            format!("## stdout\n{}\n## stderr\n{}", stdout, stderr)
+            body.to_owned()
+        };
+        if self.view.collapse_output {
+            // Blank lines are required to render markdown inside HTML blocks.
+            format!("<details><summary>{name}</summary>\n\n{body}\n\n</details>")
+        } else {
+            format!("## {name}\n{body}")
+        }
+    }
+
+    fn render_output(&self, v: &[u8]) -> String {
+        let s = String::from_utf8_lossy(v);
+        if self.view.strip_ansi {
+            cut_text_length(&strip_ansi(&s))
+        } else {
+            cut_text_length(&s)
         }
     }
 }
 
+/// Result of a command run, aggregated into the check run summary.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandOutcome {
+    pub succeeded: bool,
+    pub elapsed: StdDuration,
+}
+
+// At-a-glance result of the commands, e.g. `1 passed, 0 failed in 1s 200ms`. Details are in the text section.
+fn outcome_line(outcomes: &[CommandOutcome]) -> String {
+    let passed = outcomes.iter().filter(|o| o.succeeded).count();
+    let failed = outcomes.len().saturating_sub(passed);
+    let elapsed = outcomes.iter().map(|o| o.elapsed).sum::<StdDuration>();
+    format!("{passed} passed, {failed} failed in {}", round_ms(elapsed))
+}
+
+// A single command has nothing to count, so only its duration is shown.
+fn elapsed_line(elapsed: StdDuration) -> String {
+    format!("Finished in {}", round_ms(elapsed))
+}
+
+// Sub-millisecond precision is noise.
+fn round_ms(elapsed: StdDuration) -> Duration {
+    StdDuration::from_millis(elapsed.as_millis().try_into().unwrap_or(u64::MAX)).into()
+}
+
 pub fn fmt_cmd(cmd: &Command) -> String {
     let c = cmd.as_std();
     let mut s = vec![c.get_program()];
@@ -170,6 +475,50 @@ pub fn fmt_cmd(cmd: &Command) -> String {
         .join(" ")
 }
 
+/// Set when the command started and completed, so that GitHub shows the duration of the command rather than the
+/// time between the API calls. `completed_at` is derived from the measured elapsed time to show it as is.
+pub fn with_timing(
+    mut input: ChecksUpdateRequest,
+    started_at: DateTime<Utc>,
+    elapsed: StdDuration,
+) -> ChecksUpdateRequest {
+    let completed_at = chrono::Duration::from_std(elapsed)
+        .ok()
+        .and_then(|d| started_at.checked_add_signed(d))
+        .unwrap_or_else(Utc::now);
+    input.started_at = Some(started_at);
+    input.completed_at = Some(completed_at);
+    input
+}
+
+/// Summarize the outcomes of all the matrix variants in the check run of each variant. The conclusion of the
+/// variant is kept, the summary tells whether any variant failed.
+pub fn with_outcomes(
+    mut input: ChecksUpdateRequest,
+    outcomes: &[CommandOutcome],
+) -> ChecksUpdateRequest {
+    let result = if outcomes.iter().all(|o| o.succeeded) {
+        "Matrix succeeded"
+    } else {
+        "Matrix failed"
+    };
+    if let Some(o) = &mut input.output {
+        o.summary = format!("{result}: {}\n\n{}", outcome_line(outcomes), o.summary);
+    }
+    input
+}
+
+/// Attach the annotations parsed from the command output.
+pub fn with_annotations(
+    mut input: ChecksUpdateRequest,
+    annotations: Vec<ChecksCreateRequestOutputAnnotations>,
+) -> ChecksUpdateRequest {
+    if let Some(o) = &mut input.output {
+        o.annotations = annotations;
+    }
+    input
+}
+
 fn default_checks_update_request(base: &UpdateInputBase) -> ChecksUpdateRequest {
     ChecksUpdateRequest {
         name: base.name.clone(),
@@ -190,21 +539,265 @@ fn default_checks_update_request(base: &UpdateInputBase) -> ChecksUpdateRequest
     }
 }
 
-fn with_debug_info(original: String, req: &CheckRequest) -> String {
-    format!(
+fn with_debug_info(original: String, req: &CheckRequest, view: &ViewConfig) -> String {
+    let original = if view.environment.is_empty() {
+        original
+    } else {
+        format!("{original}\n\nEnvironment: `{}`", view.environment)
+    };
+    if view.hide_debug_info {
+        return original;
+    }
+    let debug_info = format!(
       "{original}\n\nDelivery ID (not unique for re-delivery): `{}`\nRequest ID (unique for re-delivery): `{}`",
       req.delivery_id, req.request_id,
-  )
+    );
+    match &view.runner_id {
+        Some(id) => format!("{debug_info}\nRunner ID: `{id}`"),
+        None => debug_info,
+    }
 }
 
 // GitHub API has a limit of 65535 characters for text fields. So cut the text if it's too long.
 // https://docs.github.com/en/rest/checks/runs?apiVersion=2022-11-28#create-a-check-run
 const MAX_TEXT_LENGTH: usize = 30_000;
-fn cut_text_length(v: &[u8]) -> String {
-    let s = String::from_utf8_lossy(v);
+fn cut_text_length(s: &str) -> String {
     if s.chars().count() > MAX_TEXT_LENGTH {
         format!("{}...", s.chars().take(MAX_TEXT_LENGTH).collect::<String>())
     } else {
-        s.to_string()
+        s.to_owned()
+    }
+}
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+// Keep newlines and tabs, drop other control characters including carriage returns of progress bars.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => match chars.next() {
+                // CSI sequence ends with a byte in `@`..=`~`, e.g. `ESC[1;31m`.
+                Some('[') => {
+                    chars.by_ref().find(|c| ('@'..='~').contains(c));
+                }
+                // OSC sequence ends with BEL or `ESC\`, e.g. hyperlinks.
+                Some(']') => {
+                    let end = chars.by_ref().find(|c| *c == BEL || *c == ESC);
+                    if end == Some(ESC) {
+                        chars.next();
+                    }
+                }
+                // Other sequences are two characters, e.g. `ESC(B`'s first part.
+                _ => {}
+            },
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt as _, process::ExitStatus};
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn create_input(view: ViewConfig) -> CreateInput {
+        CreateInput {
+            req: CheckRequest {
+                head_sha: "testsha".to_owned(),
+                ..Default::default()
+            },
+            name: "run-test".to_owned(),
+            command: vec!["echo".to_owned(), "hello".to_owned()],
+            view,
+        }
+    }
+
+    #[test]
+    fn create_summary_default() {
+        let input = create_input(Default::default());
+        assert_eq!(input.summary(), "Running command:\n```\necho hello\n```");
+    }
+
+    #[test]
+    fn create_summary_hide_command() {
+        let input = create_input(ViewConfig {
+            hide_command: true,
+            ..Default::default()
+        });
+        assert_eq!(input.summary(), "Running command.");
+    }
+
+    #[test]
+    fn create_summary_template() {
+        let input = create_input(ViewConfig {
+            hide_command: true,
+            create_summary_template: Some("{name} at {head_sha}: {command}".to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(input.summary(), "run-test at testsha: (hidden)");
+    }
+
+    #[test]
+    fn output_text() {
+        let out = Output {
+            status: ExitStatus::from_raw(0),
+            stdout: b"out".to_vec(),
+            stderr: b"err".to_vec(),
+        };
+        let text = |wrap_stdout: bool, view: ViewConfig| {
+            create_input(view)
+                .into_update_input(1, wrap_stdout)
+                .to_text(&out)
+        };
+        assert_eq!(
+            text(true, Default::default()),
+            "## stdout\n```\nout\n```\n## stderr\n```\nerr\n```"
+        );
+        assert_eq!(
+            text(false, Default::default()),
+            "## stdout\nout\n## stderr\nerr"
+        );
+        assert_eq!(
+            text(
+                true,
+                ViewConfig {
+                    stdout_code_lang: Some("console".to_owned()),
+                    collapse_output: true,
+                    ..Default::default()
+                }
+            ),
+            "<details><summary>stdout</summary>\n\n```console\nout\n```\n\n</details>\n<details><summary>stderr</summary>\n\n```\nerr\n```\n\n</details>"
+        );
+    }
+
+    #[test]
+    fn outcome_summary() {
+        let outcomes = [
+            CommandOutcome {
+                succeeded: true,
+                elapsed: StdDuration::from_micros(1_200_500),
+            },
+            CommandOutcome {
+                succeeded: false,
+                elapsed: StdDuration::from_secs(2),
+            },
+        ];
+        assert_eq!(outcome_line(&outcomes), "1 passed, 1 failed in 3s 200ms");
+        assert_eq!(
+            elapsed_line(StdDuration::from_micros(1_200_500)),
+            "Finished in 1s 200ms"
+        );
+
+        let input = create_input(Default::default()).into_update_input(1, true);
+        let input = with_outcomes(default_checks_update_request(&input), &outcomes);
+        assert!(input
+            .output
+            .unwrap()
+            .summary
+            .starts_with("Matrix failed: 1 passed, 1 failed in 3s 200ms\n\n"));
+    }
+
+    #[test]
+    fn hide_debug_info() {
+        let shown: ChecksCreateRequest = create_input(Default::default()).into();
+        assert!(shown.output.unwrap().summary.contains("Delivery ID"));
+
+        let hidden: ChecksCreateRequest = create_input(ViewConfig {
+            hide_debug_info: true,
+            ..Default::default()
+        })
+        .into();
+        assert!(!hidden.output.unwrap().summary.contains("Delivery ID"));
+    }
+
+    #[test]
+    fn action_required() {
+        let input = create_input(ViewConfig {
+            hide_debug_info: true,
+            ..Default::default()
+        })
+        .into_update_input(1, true)
+        .into_action_required("Missing config", "Add `.orgu.yml`.".to_owned());
+        assert_eq!(
+            input.conclusion,
+            Some(ChecksCreateRequestConclusion::ActionRequired)
+        );
+        let output = input.output.unwrap();
+        assert_eq!(output.title, "Missing config");
+        assert_eq!(output.summary, "Add `.orgu.yml`.");
+    }
+
+    #[test]
+    fn strip_ansi_output() {
+        assert_eq!(
+            strip_ansi("\u{1b}[1;31merror\u{1b}[0m: failed\r\n\twarn\u{0}"),
+            "error: failed\n\twarn"
+        );
+        assert_eq!(
+            strip_ansi("\u{1b}]8;;https://example.com\u{7}link\u{1b}]8;;\u{1b}\\ done"),
+            "link done"
+        );
+        assert_eq!(strip_ansi("50%\r100%\n"), "50%100%\n");
+        assert_eq!(strip_ansi("日本語 ✓"), "日本語 ✓");
+    }
+
+    #[test]
+    fn runner_id() {
+        let view = |hide_debug_info| ViewConfig {
+            runner_id: Some("ip-10-0-0-1".to_owned()),
+            hide_debug_info,
+            ..Default::default()
+        };
+        let shown: ChecksCreateRequest = create_input(view(false)).into();
+        assert!(shown
+            .output
+            .unwrap()
+            .summary
+            .ends_with("\nRunner ID: `ip-10-0-0-1`"));
+        let hidden: ChecksCreateRequest = create_input(view(true)).into();
+        assert!(!hidden.output.unwrap().summary.contains("Runner ID"));
+    }
+
+    #[test]
+    fn timing() {
+        let started_at = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let input = with_timing(
+            create_input(Default::default())
+                .into_update_input(1, true)
+                .into_sender_skipped(),
+            started_at,
+            StdDuration::from_millis(83_250),
+        );
+        assert_eq!(input.started_at, Some(started_at));
+        assert_eq!(
+            input.completed_at.unwrap() - started_at,
+            chrono::Duration::milliseconds(83_250)
+        );
+    }
+
+    #[test]
+    fn environment_in_summary() {
+        let input: ChecksCreateRequest = create_input(ViewConfig {
+            environment: "staging".to_owned(),
+            hide_debug_info: true,
+            ..Default::default()
+        })
+        .into();
+        assert!(input
+            .output
+            .unwrap()
+            .summary
+            .ends_with("\n\nEnvironment: `staging`"));
     }
 }
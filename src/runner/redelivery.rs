@@ -0,0 +1,69 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+};
+
+/// Number of the most recently seen deliveries to track. Older ones are forgotten and counted from zero again.
+pub const TRACKED_DELIVERIES: usize = 1024;
+
+/// Counts how many times each delivery has been seen in this process, keeping only the most recently seen
+/// deliveries up to `capacity`. Not shared between processes, so redeliveries to other runners are not counted.
+#[derive(Debug)]
+pub struct DeliveryCounter {
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    counts: HashMap<String, u32>,
+    // Least recently seen first.
+    order: VecDeque<String>,
+}
+
+impl DeliveryCounter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Records the delivery and returns how many times it has been seen including this time.
+    pub fn record(&self, delivery_id: &str) -> u32 {
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        let Seen { counts, order } = &mut *seen;
+        if let Some(count) = counts.get_mut(delivery_id) {
+            *count = count.saturating_add(1);
+            order.retain(|d| d != delivery_id);
+            order.push_back(delivery_id.to_owned());
+            return *count;
+        }
+        if order.len() >= self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                counts.remove(&oldest);
+            }
+        }
+        counts.insert(delivery_id.to_owned(), 1);
+        order.push_back(delivery_id.to_owned());
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let counter = DeliveryCounter::new(2);
+        assert_eq!(counter.record("a"), 1);
+        assert_eq!(counter.record("a"), 2);
+        assert_eq!(counter.record("b"), 1);
+        // `a` is seen more recently than `b`, so `b` is evicted.
+        assert_eq!(counter.record("a"), 3);
+        assert_eq!(counter.record("c"), 1);
+        assert_eq!(counter.record("b"), 1);
+        assert_eq!(counter.record("c"), 2);
+    }
+}
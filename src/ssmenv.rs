@@ -1,22 +1,30 @@
 use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context as _, Result};
-use aws_sdk_ssm::client::Client;
-use tracing::{debug, trace};
+use arc_swap::ArcSwap;
+use tokio::{
+    spawn,
+    time::{interval_at, Instant},
+};
+use tracing::{debug, info, trace, warn};
 
 type EnvKey = String;
 type ParameterName = String;
 type FullParameterName = String;
 type ParameterValue = String;
 
+const SSM_PREFIX: &str = "ssm://";
+
 // Should be called in main thread exclusively, because it reads/writes environment variables.
 pub async fn with_replaced_env<T, F>(f: F) -> Result<T>
 where
     F: FnOnce() -> T,
 {
     let original: HashMap<EnvKey, FullParameterName> = env::vars()
-        .filter(|(_, v)| v.starts_with("ssm://"))
+        .filter(|(_, v)| v.starts_with(SSM_PREFIX))
         .collect();
     trace!("original env vars: {:?}", original);
     if original.is_empty() {
@@ -25,12 +33,12 @@ where
 
     let names: Vec<ParameterName> = original
         .values()
-        .map(|v| v.trim_start_matches("ssm://").to_owned())
+        .map(|v| v.trim_start_matches(SSM_PREFIX).to_owned())
         .collect();
     let fetched_values = fetch(names).await?;
 
     for (k, v) in original.iter() {
-        let trimmed = v.trim_start_matches("ssm://");
+        let trimmed = v.trim_start_matches(SSM_PREFIX);
         let value: ParameterValue = fetched_values
             .get(trimmed)
             .map(ToOwned::to_owned)
@@ -47,7 +55,10 @@ where
     Ok(res)
 }
 
+#[cfg(feature = "aws")]
 async fn fetch(names: Vec<ParameterName>) -> Result<HashMap<ParameterName, ParameterValue>> {
+    use aws_sdk_ssm::client::Client;
+
     debug!("fetching SSM values for names: {}", names.join(", "));
 
     let config = aws_config::load_from_env().await;
@@ -62,3 +73,70 @@ async fn fetch(names: Vec<ParameterName>) -> Result<HashMap<ParameterName, Param
         .with_context(|| "no parameter fetched")?;
     Ok(res.into_iter().flat_map(|p| p.name.zip(p.value)).collect())
 }
+
+// Fail instead of passing `ssm://` values through as-is.
+#[cfg(not(feature = "aws"))]
+async fn fetch(names: Vec<ParameterName>) -> Result<HashMap<ParameterName, ParameterValue>> {
+    anyhow::bail!(
+        "orgu is built without the `aws` feature, cannot fetch SSM values for names: {}",
+        names.join(", ")
+    )
+}
+
+/// Secret value which can be swapped atomically while the process is running.
+/// Use `spawn_refresh` to keep the value in sync with AWS SSM parameter.
+#[derive(Debug, Clone)]
+pub struct RefreshableSecret {
+    inner: Arc<ArcSwap<String>>,
+}
+
+impl RefreshableSecret {
+    pub fn new(value: String) -> Self {
+        Self {
+            inner: Arc::new(ArcSwap::from_pointee(value)),
+        }
+    }
+
+    #[allow(clippy::rc_buffer)] // ArcSwap requires sized type.
+    pub fn load(&self) -> Arc<String> {
+        self.inner.load_full()
+    }
+
+    pub fn store(&self, value: String) {
+        self.inner.store(Arc::new(value));
+    }
+
+    /// Periodically re-fetch the value from AWS SSM if the original value of env var `key` is `ssm://` value.
+    /// Do nothing for other values. Because `with_replaced_env` restores original env vars, the parameter name can
+    /// be read from the env var after parsing CLI args.
+    pub fn spawn_refresh(&self, key: &str, every: Duration) {
+        let Some(name) = env::var(key)
+            .ok()
+            .and_then(|v| v.strip_prefix(SSM_PREFIX).map(ToOwned::to_owned))
+        else {
+            debug!("not a SSM value, skipping refresh: key={key}");
+            return;
+        };
+        info!("refreshing SSM value in background: key={key}, interval={every:?}");
+
+        let secret = self.clone();
+        spawn(async move {
+            let mut ticker = interval_at(Instant::now() + every, every);
+            loop {
+                ticker.tick().await;
+                match fetch(vec![name.clone()]).await {
+                    Ok(mut values) => match values.remove(&name) {
+                        Some(v) => {
+                            secret.store(v);
+                            debug!("refreshed SSM value: name={name}");
+                        }
+                        None => warn!("no value fetched for {name}, keeping current value"),
+                    },
+                    Err(e) => {
+                        warn!(error = ?e, "failed to refresh SSM value, keeping current value")
+                    }
+                }
+            }
+        });
+    }
+}
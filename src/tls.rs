@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Args;
+use rustls::crypto::ring::default_provider;
+use tracing::debug;
+
+#[derive(Debug, Clone, Args)]
+pub struct TlsConfig {
+    /// Path to PEM encoded TLS certificate chain. If given with `--tls-key`, the server serves over HTTPS.
+    #[arg(long, env, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+    /// Path to PEM encoded TLS private key.
+    #[arg(long, env, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Load certificate and private key. Returns None if TLS is not configured.
+    /// Call this at startup to fail fast on invalid files.
+    pub async fn load(&self) -> Result<Option<RustlsConfig>> {
+        let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+            return Ok(None);
+        };
+        // Other crates may have installed a provider already, it's ok to use it.
+        if default_provider().install_default().is_err() {
+            debug!("rustls crypto provider already installed");
+        }
+        let config = RustlsConfig::from_pem_file(cert, key)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to load TLS certificate and key: cert={}, key={}",
+                    cert.display(),
+                    key.display()
+                )
+            })?;
+        Ok(Some(config))
+    }
+}
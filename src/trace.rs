@@ -1,58 +1,258 @@
 // To filter aws sdk logs, see: https://docs.aws.amazon.com/sdk-for-rust/latest/dg/logging.html#logging-filtering
 
-use clap_verbosity_flag::{LogLevel, Verbosity};
-use tracing::{level_filters::LevelFilter, Level};
+use std::{io::stderr, path::Path};
+
+use anyhow::{Context as _, Result};
+use clap::ValueEnum;
+use http::HeaderMap;
+use tracing::{info_span, level_filters::LevelFilter, Level, Span};
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
 use tracing_log::AsTrace as _;
 use tracing_subscriber::{
+    filter::Directive,
     fmt::{
+        self,
         format::{DefaultFields, Format, Full},
         time::ChronoLocal,
-        SubscriberBuilder,
     },
-    util::SubscriberInitExt,
-    EnvFilter,
+    layer::{Layered, SubscriberExt as _},
+    registry,
+    util::SubscriberInitExt as _,
+    EnvFilter, Layer, Registry,
 };
 
-pub fn init_fmt_with_json<L: LogLevel>(v: &Verbosity<L>) {
-    init_subscriber(v, |b| b.json());
+use crate::cli::GlobalArgs;
+
+/// Rotation of the log file.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogRotation {
+    Daily,
+    Hourly,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(v: LogRotation) -> Self {
+        match v {
+            LogRotation::Daily => Self::DAILY,
+            LogRotation::Hourly => Self::HOURLY,
+            LogRotation::Never => Self::NEVER,
+        }
+    }
+}
+
+/// Flushes logs buffered for the log file on drop. Keep it until the command returns.
+#[must_use]
+pub struct LogGuard(Option<WorkerGuard>);
+
+// Used by Lambda subcommands whose logs are collected by CloudWatch Logs.
+#[cfg(feature = "aws")]
+pub fn init_fmt_with_json(global: &GlobalArgs) -> Result<LogGuard> {
+    init_subscriber(global, |b| b.json())
 }
 
-pub fn init_fmt_with_pretty<L: LogLevel>(v: &Verbosity<L>) {
-    init_subscriber(v, |b| b.pretty());
+pub fn init_fmt_with_pretty(global: &GlobalArgs) -> Result<LogGuard> {
+    init_subscriber(global, |b| b.pretty())
 }
 
-pub fn init_fmt_with_full<L: LogLevel>(v: &Verbosity<L>) {
-    init_subscriber(v, |b| b.with_ansi(false));
+pub fn init_fmt_with_full(global: &GlobalArgs) -> Result<LogGuard> {
+    init_subscriber(global, |b| b.with_ansi(false))
 }
 
-type DefaultSubscriberBuilder =
-    SubscriberBuilder<DefaultFields, Format<Full, ChronoLocal>, EnvFilter>;
+// Used when stdout is reserved for machine readable output.
+pub fn init_fmt_with_full_to_stderr(global: &GlobalArgs) -> Result<LogGuard> {
+    init_subscriber(global, |b| b.with_ansi(false).with_writer(stderr))
+}
+
+type FilteredRegistry = Layered<EnvFilter, Registry>;
+type DefaultLayer = fmt::Layer<FilteredRegistry, DefaultFields, Format<Full, ChronoLocal>>;
 
-fn init_subscriber<L, F, B>(v: &Verbosity<L>, f: F)
+fn init_subscriber<F, L>(global: &GlobalArgs, f: F) -> Result<LogGuard>
 where
-    L: LogLevel,
-    F: FnOnce(DefaultSubscriberBuilder) -> B,
-    B: SubscriberInitExt,
+    F: FnOnce(DefaultLayer) -> L,
+    L: Layer<FilteredRegistry> + Send + Sync + 'static,
 {
     // Don't set subscriber if user wants to silence output.
-    match v.log_level_filter().as_trace() {
-        LevelFilter::OFF => (),
-        filter => {
-            let env_filter = into_env_filter(filter);
-            let builder = SubscriberBuilder::default()
+    let filter = match global.verbose.log_level_filter().as_trace() {
+        LevelFilter::OFF => return Ok(LogGuard(None)),
+        filter => filter,
+    };
+    let env_filter = into_env_filter(filter, &global.log_levels);
+    let stdout =
+        (!global.log_file_only).then(|| f(fmt::layer().with_timer(ChronoLocal::rfc_3339())));
+    let (file, guard) = match &global.log_file {
+        Some(path) => {
+            let (writer, guard) = file_writer(path, global.log_rotation)?;
+            // Plain format without colors regardless of the command.
+            let layer = fmt::layer()
                 .with_timer(ChronoLocal::rfc_3339())
-                .with_env_filter(env_filter);
-            f(builder).init();
+                .with_ansi(false)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
         }
-    }
+        None => (None, None),
+    };
+    registry().with(env_filter).with(stdout).with(file).init();
+    Ok(LogGuard(guard))
 }
 
-fn into_env_filter(filter: LevelFilter) -> EnvFilter {
+// Writes in a background thread not to block the runtime on file I/O.
+fn file_writer(path: &Path, rotation: LogRotation) -> Result<(NonBlocking, WorkerGuard)> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("log file must be a file path: {}", path.display()))?;
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(file_name.to_string_lossy())
+        .build(dir)
+        .with_context(|| format!("failed to open log file: {}", path.display()))?;
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+fn into_env_filter(filter: LevelFilter, log_levels: &[Directive]) -> EnvFilter {
     // If log level is lower than debug, only apply it to orgu targets.
     let default = if filter >= Level::DEBUG {
         format!("info,orgu={filter}")
     } else {
         filter.to_string()
     };
-    EnvFilter::try_from_default_env().unwrap_or_else(|_| default.into())
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| default.into());
+    // Directives for the same target are replaced, so explicit ones win.
+    log_levels
+        .iter()
+        .cloned()
+        .fold(env_filter, EnvFilter::add_directive)
+}
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// W3C Trace Context given by the caller in `traceparent` and `tracestate` headers.
+/// <https://www.w3.org/TR/trace-context/>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub sampled: bool,
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// None if `traceparent` is missing or invalid, which is ignored as the spec requires.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?;
+        let mut parts = traceparent.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let valid = |s: &str, len: usize| {
+            s.len() == len
+                && s.bytes()
+                    .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+                && s.bytes().any(|b| b != b'0')
+        };
+        // Version ff is invalid. Later versions may append fields.
+        if version.len() != 2
+            || version == "ff"
+            || (version == "00" && parts.next().is_some())
+            || !valid(trace_id, 32)
+            || !valid(parent_id, 16)
+            || flags.len() != 2
+        {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        let tracestate = headers
+            .get(TRACESTATE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToOwned::to_owned);
+        Some(Self {
+            trace_id: trace_id.to_owned(),
+            parent_id: parent_id.to_owned(),
+            sampled: flags & 1 == 1,
+            tracestate,
+        })
+    }
+
+    /// Span to correlate logs with the caller's trace.
+    pub fn span(&self) -> Span {
+        info_span!(
+            "trace context",
+            trace_id = self.trace_id.as_str(),
+            parent_id = self.parent_id.as_str(),
+            sampled = self.sampled,
+            tracestate = self.tracestate.as_deref(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn headers(traceparent: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, HeaderValue::from_str(traceparent).unwrap());
+        headers
+    }
+
+    #[test]
+    fn file_writer() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("orgu.log");
+        let (_writer, _guard) = super::file_writer(&path, LogRotation::Never).unwrap();
+        assert!(path.exists());
+        super::file_writer(Path::new("/"), LogRotation::Never).unwrap_err();
+    }
+
+    #[test]
+    fn log_levels() {
+        let directives = ["orgu=trace", "orgu::checkout=debug", "hyper=warn"]
+            .map(|d| d.parse::<Directive>().unwrap());
+        let filter = into_env_filter(LevelFilter::DEBUG, &directives).to_string();
+        for d in ["orgu=trace", "orgu::checkout=debug", "hyper=warn", "info"] {
+            assert!(filter.split(',').any(|f| f == d), "{d} in {filter}");
+        }
+        assert!(!filter.contains("orgu=debug"), "{filter}");
+    }
+
+    #[test]
+    fn trace_context() {
+        let mut h = headers("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        h.insert(TRACESTATE, HeaderValue::from_static("congo=t61rcWkgMzE"));
+        assert_eq!(
+            TraceContext::from_headers(&h),
+            Some(TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                parent_id: "00f067aa0ba902b7".to_owned(),
+                sampled: true,
+                tracestate: Some("congo=t61rcWkgMzE".to_owned()),
+            })
+        );
+
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(
+                TraceContext::from_headers(&headers(invalid)),
+                None,
+                "{invalid}"
+            );
+        }
+        assert_eq!(TraceContext::from_headers(&HeaderMap::new()), None);
+    }
 }